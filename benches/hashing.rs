@@ -0,0 +1,37 @@
+// Compares the hash tables with and without the `fxhash` feature, see the README:
+//     cargo bench --bench hashing
+//     cargo bench --bench hashing --features fxhash
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use regexp::Engine;
+use std::hint::black_box;
+
+// `w0000xyz|w0001xyz|...`, the automaton has a state per char of every branch.
+fn alternation(branches: usize) -> String {
+    (0..branches)
+        .map(|i| format!("w{:04}xyz", i))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn compile(c: &mut Criterion) {
+    let pattern = alternation(2000);
+    c.bench_function("compile 2000 branch alternation", |b| {
+        b.iter(|| Engine::new(black_box(&pattern)).unwrap())
+    });
+}
+
+// The deterministic form of `(a|b)*a(a|b){9}` has a state per set of the last 10 chars, each
+// looked up in the map of state sets.
+fn dfa_state_map(c: &mut Criterion) {
+    c.bench_function("compile_dfa 1024 states", |b| {
+        b.iter(|| {
+            let mut engine = Engine::new(black_box("(a|b)*a(a|b){9}")).unwrap();
+            assert!(engine.compile_dfa());
+            engine
+        })
+    });
+}
+
+criterion_group!(benches, compile, dfa_state_map);
+criterion_main!(benches);
@@ -0,0 +1,102 @@
+// `#[derive(FromCaptures)]` for `regexp::meta::FromCaptures`, eg:
+//
+//     #[derive(FromCaptures)]
+//     struct LogLine {
+//         ip: String,
+//         status: u16,
+//         agent: Option<String>,
+//     }
+//
+// Each field is parsed with `FromStr` from the group of the same name, `Option` fields are None
+// when the group took no part in the match.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, GenericArgument, PathArguments, Type};
+
+#[proc_macro_derive(FromCaptures)]
+pub fn derive_from_captures(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "FromCaptures needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "FromCaptures can only be derived for structs",
+            ))
+        }
+    };
+
+    let values = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().expect("Named field without a name");
+        // Raw identifiers name the group without the `r#`, eg: `r#type` for `(?P<type>...)`.
+        let group = ident.to_string().trim_start_matches("r#").to_string();
+        match option_inner(&field.ty) {
+            Some(inner) => quote! {
+                #ident: match caps.parse::<#inner>(#group) {
+                    ::std::result::Result::Ok(value) => ::std::option::Option::Some(value),
+                    ::std::result::Result::Err(::regexp::meta::CaptureParseError::Missing(_)) => {
+                        ::std::option::Option::None
+                    }
+                    ::std::result::Result::Err(err) => {
+                        return ::std::result::Result::Err(
+                            err.map_invalid(|err| ::std::format!("{}: {}", #group, err)),
+                        )
+                    }
+                }
+            },
+            None => {
+                let ty = &field.ty;
+                quote! {
+                    #ident: caps
+                        .parse::<#ty>(#group)
+                        .map_err(|err| err.map_invalid(|err| ::std::format!("{}: {}", #group, err)))?
+                }
+            }
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::regexp::meta::FromCaptures for #name #ty_generics #where_clause {
+            fn from_captures(
+                caps: &::regexp::meta::Captures,
+            ) -> ::std::result::Result<Self, ::regexp::meta::CaptureParseError<::std::string::String>> {
+                ::std::result::Result::Ok(#name { #(#values),* })
+            }
+        }
+    })
+}
+
+// The `T` of an `Option<T>` field.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let last = path.path.segments.last()?;
+    if last.ident != "Option" {
+        return None;
+    }
+    match &last.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,57 @@
+use crate::engine::*;
+use regex::Regex;
+use std::fmt::Write;
+
+// Runs both this engine and the regex crate on the input. Returns a report with the matcher trace
+// when they disagree.
+pub fn check(pattern: &str, s: &str) -> Option<String> {
+    let engine = Engine::new(pattern).expect("Pattern rejected by the engine");
+    let reference = Regex::new(pattern).expect("Pattern rejected by the regex crate");
+    let full_reference =
+        Regex::new(&format!("^(?:{})$", pattern)).expect("Pattern rejected by the regex crate");
+
+    let results = [
+        ("is_match", engine.is_match(s), reference.is_match(s)),
+        (
+            "is_full_match",
+            engine.is_full_match(s),
+            full_reference.is_match(s),
+        ),
+    ];
+
+    if results.iter().all(|(_, ours, theirs)| ours == theirs) {
+        return None;
+    }
+
+    let mut report = String::new();
+    for (name, ours, theirs) in results {
+        if ours != theirs {
+            writeln!(report, "{}: engine={} regex={}", name, ours, theirs).unwrap();
+        }
+    }
+
+    let (_, trace) = engine.trace(s);
+    writeln!(report, "trace (state, char index):").unwrap();
+    for (state, i) in trace {
+        writeln!(report, "\t{} {}", state, i).unwrap();
+    }
+
+    Some(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(None, check("a(b|cd)*e", "xabcdbe"));
+        assert_eq!(None, check("a[^bc]{2,3}", "ad"));
+        assert_eq!(None, check("", "abc"));
+
+        // The wildcard matches new lines here, unlike in the regex crate.
+        let report = check("a.", "a\n").unwrap();
+        assert!(report.starts_with("is_match: engine=true regex=false\n"));
+        assert!(report.contains("trace (state, char index):\n"));
+    }
+}
@@ -0,0 +1,318 @@
+// The common subset of the regex crate's `Regex` with the same method signatures, so a project
+// can try this engine by swapping `use regex::Regex;` for `use regexp::compat::Regex;`. Matches
+// are leftmost-first like in the regex crate, eg: `a|ab` finds "a" in "abc": `find`, `captures`
+// and `replace_all` all go by `Engine::find_iter` and fill in the groups of the branches each
+// match took.
+
+use crate::engine::*;
+use crate::types;
+use crate::types::{RegexError, Span};
+use std::borrow::Cow;
+use std::ops::{Index, Range};
+
+pub type Error = RegexError;
+
+#[derive(Debug)]
+pub struct Regex {
+    pattern: String,
+    engine: Engine,
+}
+
+impl Regex {
+    pub fn new(re: &str) -> Result<Regex, Error> {
+        Ok(Regex {
+            pattern: re.to_string(),
+            engine: Engine::new(re)?,
+        })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.engine.is_match(haystack)
+    }
+
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        let found = self.engine.find_iter(haystack).next()?;
+        Some(Match::new(haystack, found.span()))
+    }
+
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        let found = self.engine.find_iter(haystack).next()?;
+        Some(Captures(self.engine.captures_of(haystack, found)))
+    }
+
+    // Replaces every match, with a string expanding `$1` or `$name` (see `Captures::expand`) or a
+    // function of the captures.
+    pub fn replace_all<'h, R: Replacer>(&self, haystack: &'h str, mut rep: R) -> Cow<'h, str> {
+        let mut matches = self.engine.find_iter(haystack).peekable();
+        if matches.peek().is_none() {
+            return Cow::Borrowed(haystack);
+        }
+
+        let mut out = String::with_capacity(haystack.len());
+        let mut last_end = 0;
+        for found in matches {
+            out.push_str(&haystack[last_end..found.start]);
+            last_end = found.end;
+            let captures = Captures(self.engine.captures_of(haystack, found));
+            rep.replace_append(&captures, &mut out);
+        }
+        out.push_str(&haystack[last_end..]);
+        Cow::Owned(out)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl std::fmt::Display for Regex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+impl std::str::FromStr for Regex {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Regex, Error> {
+        Regex::new(s)
+    }
+}
+
+// A match with byte offsets into the haystack, like `regex::Match`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Match<'h> {
+    haystack: &'h str,
+    start: usize,
+    end: usize,
+}
+
+impl<'h> Match<'h> {
+    fn new(haystack: &'h str, (start, end): Span) -> Match<'h> {
+        Match {
+            haystack,
+            start,
+            end,
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.range()]
+    }
+}
+
+// The groups of a match, like `regex::Captures`, indexable by number or name.
+#[derive(Debug, Clone)]
+pub struct Captures<'h>(types::Captures<'h>);
+
+impl<'h> Captures<'h> {
+    pub fn get(&self, i: usize) -> Option<Match<'h>> {
+        Some(Match::new(self.0.haystack, self.0.span(i)?))
+    }
+
+    pub fn name(&self, name: &str) -> Option<Match<'h>> {
+        let found = self.0.name(name)?;
+        Some(Match::new(self.0.haystack, found.span()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // Always false, as the whole match is a group.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Appends the replacement with `$1`, `${1}`, `$name` or `${name}` filled in and `$$` for a `$`.
+    pub fn expand(&self, replacement: &str, dst: &mut String) {
+        self.0.expand(replacement, dst)
+    }
+}
+
+// Panics when the group took no part in the match, like the regex crate.
+impl Index<usize> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.0
+            .get(i)
+            .map(|found| found.slice)
+            .unwrap_or_else(|| panic!("no group at index '{}'", i))
+    }
+}
+
+impl<'n> Index<&'n str> for Captures<'_> {
+    type Output = str;
+
+    fn index(&self, name: &'n str) -> &str {
+        self.0
+            .name(name)
+            .map(|found| found.slice)
+            .unwrap_or_else(|| panic!("no group named '{}'", name))
+    }
+}
+
+// What a match is replaced with, see `Regex::replace_all`.
+pub trait Replacer {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String);
+}
+
+impl Replacer for &str {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst)
+    }
+}
+
+impl Replacer for String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst)
+    }
+}
+
+impl Replacer for &String {
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        caps.expand(self, dst)
+    }
+}
+
+impl<F, T> Replacer for F
+where
+    F: FnMut(&Captures<'_>) -> T,
+    T: AsRef<str>,
+{
+    fn replace_append(&mut self, caps: &Captures<'_>, dst: &mut String) {
+        dst.push_str(self(caps).as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compat::*;
+
+    #[test]
+    fn test_regex() {
+        let re = Regex::new("(?P<y>[0-9]{4})-([0-9]{2})").unwrap();
+        assert!(re.is_match("on 2024-05"));
+        assert!(!re.is_match("on 24-05"));
+        assert_eq!("(?P<y>[0-9]{4})-([0-9]{2})", re.as_str());
+        assert!("a(".parse::<Regex>().is_err());
+
+        let found = re.find("on 2024-05!").unwrap();
+        assert_eq!((3, 10), (found.start(), found.end()));
+        assert_eq!(3..10, found.range());
+        assert_eq!("2024-05", found.as_str());
+        assert_eq!(7, found.len());
+
+        let caps = re.captures("on 2024-05!").unwrap();
+        assert_eq!("2024", &caps["y"]);
+        assert_eq!("05", &caps[2]);
+        assert_eq!(Some("2024"), caps.get(1).map(|m| m.as_str()));
+        assert_eq!(Some(3), caps.name("y").map(|m| m.start()));
+        assert_eq!(3, caps.len());
+    }
+
+    // Leftmost-first like the regex crate, also where a branch is a prefix of a later one.
+    #[test]
+    fn test_leftmost_first() {
+        let re = Regex::new("a|ab").unwrap();
+        assert_eq!(Some("a"), re.find("ab").map(|m| m.as_str()));
+        assert_eq!("-b", re.replace_all("ab", "-"));
+
+        let re = Regex::new("(a|ab)(b*)").unwrap();
+        let caps = re.captures("abb").unwrap();
+        assert_eq!(("a", "bb"), (&caps[1], &caps[2]));
+    }
+
+    #[cfg(feature = "check")]
+    #[test]
+    fn test_regex_crate_results() {
+        let cases = [
+            ("a|ab", "ab"),
+            ("ab|a", "ab"),
+            ("(a|ab)(b*)", "xabb"),
+            ("(a|ab)(c|bcd)", "abcd"),
+            ("ab+?", "abbb"),
+        ];
+        for (pattern, haystack) in cases {
+            let ours = Regex::new(pattern).unwrap();
+            let theirs = regex::Regex::new(pattern).unwrap();
+            assert_eq!(
+                theirs.find(haystack).map(|m| m.range()),
+                ours.find(haystack).map(|m| m.range()),
+                "{}",
+                pattern
+            );
+
+            assert_eq!(
+                theirs.replace_all(haystack, "<$0>"),
+                ours.replace_all(haystack, "<$0>"),
+                "{}",
+                pattern
+            );
+
+            let (our_caps, their_caps) = (
+                ours.captures(haystack).unwrap(),
+                theirs.captures(haystack).unwrap(),
+            );
+            for group in 0..their_caps.len() {
+                assert_eq!(
+                    their_caps.get(group).map(|m| m.range()),
+                    our_caps.get(group).map(|m| m.range()),
+                    "{} group {}",
+                    pattern,
+                    group
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let re = Regex::new("(?P<y>[0-9]{4})-([0-9]{2})").unwrap();
+        assert_eq!(
+            "05/2024, 06/2025",
+            re.replace_all("2024-05, 2025-06", "$2/$y")
+        );
+        assert_eq!(
+            "[2024] [2025]",
+            re.replace_all("2024-05 2025-06", |caps: &Captures| format!(
+                "[{}]",
+                &caps[1]
+            ))
+        );
+        // Groups of the branches the leftmost-first match took.
+        assert_eq!(
+            "[]",
+            Regex::new("ab|(a)b").unwrap().replace_all("ab", "[$1]")
+        );
+        assert_eq!(
+            "[]",
+            Regex::new("c.|(.)c").unwrap().replace_all("cc", "[$1]")
+        );
+        assert!(matches!(
+            re.replace_all("no dates", String::from("x")),
+            Cow::Borrowed("no dates")
+        ));
+    }
+}
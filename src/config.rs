@@ -0,0 +1,132 @@
+// Default grep flags from the file in `$ITARATO_REGEX_CONFIG`, else from
+// `$XDG_CONFIG_HOME/itarato-regex/config.toml`, else from `~/.config/itarato-regex/config.toml`,
+// eg:
+//
+//     # Records are split at runs of `x` unless `--no-config` is given.
+//     multiline = false
+//     record_separator = "xx*"
+//
+// Only `key = value` lines of booleans and quoted strings are read, and at most one of the keys
+// choosing how matches are printed can be set. The keys are the fields, others are an error. Flags
+// on the command line override the file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub multiline: bool,
+    pub paragraph: bool,
+    pub only_matching: bool,
+    pub with_source: bool,
+    pub record_separator: Option<String>,
+}
+
+impl Config {
+    // The default config when there is no config file.
+    pub fn load() -> Result<Config, String> {
+        let path = match std::env::var("ITARATO_REGEX_CONFIG") {
+            Ok(path) => path,
+            Err(_) => match Config::default_path() {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text).map_err(|err| format!("{}: {}", path, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(format!("{}: {}", path, err)),
+        }
+    }
+
+    fn default_path() -> Option<String> {
+        let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(dir) => dir,
+            Err(_) => format!("{}/.config", std::env::var("HOME").ok()?),
+        };
+        Some(format!("{}/itarato-regex/config.toml", config_dir))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid_line = || format!("Invalid line {}: {}", line_idx + 1, line);
+            let (key, value) = line.split_once('=').ok_or_else(invalid_line)?;
+            let value = value.trim();
+            let as_bool = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(invalid_line()),
+            };
+
+            match key.trim() {
+                "multiline" => config.multiline = as_bool()?,
+                "paragraph" => config.paragraph = as_bool()?,
+                "only_matching" => config.only_matching = as_bool()?,
+                "with_source" => config.with_source = as_bool()?,
+                "record_separator" => {
+                    let pattern = value
+                        .strip_prefix('"')
+                        .and_then(|value| value.strip_suffix('"'))
+                        .ok_or_else(invalid_line)?;
+                    config.record_separator = Some(pattern.into());
+                }
+                _ => return Err(invalid_line()),
+            }
+        }
+
+        // Like the grep flags, the keys choosing how matches are printed exclude each other.
+        let modes = [
+            (config.multiline, "multiline"),
+            (config.paragraph, "paragraph"),
+            (config.only_matching, "only_matching"),
+            (config.with_source, "with_source"),
+            (config.record_separator.is_some(), "record_separator"),
+        ];
+        let mut set_modes = modes.iter().filter(|(is_set, _)| *is_set);
+        if let (Some((_, first)), Some((_, second))) = (set_modes.next(), set_modes.next()) {
+            return Err(format!("{} and {} can't both be set", first, second));
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(Config {
+                only_matching: false,
+                record_separator: Some("xx*".into()),
+                ..Config::default()
+            }),
+            Config::parse(
+                "# Defaults\n\
+                 multiline = false\n\
+                 \n\
+                 only_matching=false\n\
+                 record_separator = \"xx*\"\n"
+            )
+        );
+
+        assert_eq!(
+            Err("Invalid line 1: multiline = yes".into()),
+            Config::parse("multiline = yes")
+        );
+        assert_eq!(
+            Err("Invalid line 2: color = true".into()),
+            Config::parse("paragraph = true\ncolor = true")
+        );
+        assert_eq!(
+            Err("multiline and paragraph can't both be set".into()),
+            Config::parse("multiline = true\nparagraph = true")
+        );
+    }
+}
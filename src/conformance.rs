@@ -0,0 +1,65 @@
+// Data driven tests with the regression corpus, any engine backend can be validated with it.
+
+const CORPUS: &str = include_str!("../tests/corpus.txt");
+
+// One corpus case: pattern, input, is-full-match, is-match.
+pub type CorpusCase = (&'static str, &'static str, bool, bool);
+
+pub fn corpus() -> Vec<CorpusCase> {
+    CORPUS
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields.as_slice() {
+                [pattern, input, is_full_match, is_match] => (
+                    *pattern,
+                    *input,
+                    *is_full_match == "yes",
+                    *is_match == "yes",
+                ),
+                _ => panic!("Invalid corpus line: {:?}", line),
+            }
+        })
+        .collect()
+}
+
+// Runs the corpus against a backend, given as a function from pattern and input to the
+// (is-full-match, is-match) results. Returns the failing cases.
+pub fn run_corpus(backend: impl Fn(&str, &str) -> (bool, bool)) -> Vec<CorpusCase> {
+    corpus()
+        .into_iter()
+        .filter(|(pattern, input, is_full_match, is_match)| {
+            backend(pattern, input) != (*is_full_match, *is_match)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::conformance::*;
+    use crate::engine::*;
+
+    #[test]
+    fn test_engine() {
+        assert_eq!(
+            Vec::<CorpusCase>::new(),
+            run_corpus(|pattern, input| {
+                let engine = Engine::new(pattern).unwrap();
+                (engine.is_full_match(input), engine.is_match(input))
+            })
+        );
+    }
+
+    #[test]
+    fn test_dfa() {
+        assert_eq!(
+            Vec::<CorpusCase>::new(),
+            run_corpus(|pattern, input| {
+                let mut engine = Engine::new(pattern).unwrap();
+                assert!(engine.compile_dfa(), "No DFA for {:?}", pattern);
+                (engine.is_full_match(input), engine.is_match(input))
+            })
+        );
+    }
+}
@@ -0,0 +1,319 @@
+// Stable API of the deterministic form of the automata, eg: the columns of a table of moves.
+
+use crate::input::Input;
+pub use crate::types::CharClasses;
+use crate::types::{HashMap, State, Transition};
+
+// Most states `Engine::compile_dfa` builds before falling back to the automaton of the pattern.
+pub const MAX_STATES: usize = 10_000;
+
+// Most moves `DfaStorage::Auto` keeps in a dense table, eg: 256 KiB of moves on 64 bit targets.
+pub const MAX_DENSE_MOVES: usize = 32 * 1024;
+
+// State without any automaton state left, the search fails there.
+const DEAD: usize = 0;
+
+// How the moves of the states are stored: a dense table takes a lookup per char but a move per
+// class in every state, the sparse ranges only keep the moves that change between adjacent
+// chars and take a binary search per char, eg: for the many states of a big alternation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DfaStorage {
+    Dense,
+    Sparse,
+    // Dense up to `MAX_DENSE_MOVES` moves, sparse above.
+    #[default]
+    Auto,
+}
+
+#[derive(Debug)]
+enum Moves {
+    // Move of each state on each class, by `state * class count + class`.
+    Dense(Vec<usize>),
+    // First char of each range of chars with the same move, with the move, sorted by state then
+    // char. The ranges of a state are from `offsets[state]` to `offsets[state + 1]`.
+    Sparse {
+        offsets: Vec<usize>,
+        ranges: Vec<(char, usize)>,
+    },
+}
+
+// The automaton of a pattern made deterministic by subset construction: each state is the set of
+// automaton states active at a position, and each class of chars moves it to a single state, so
+// matching reads each char once, without a stack of alternatives.
+#[derive(Debug)]
+pub struct Dfa {
+    classes: CharClasses,
+    moves: Moves,
+    // Whether the set of the state has the finish state, before the end of the input.
+    is_accepting: Vec<bool>,
+    // Same, at the end of the input, where `$` holds.
+    is_accepting_at_end: Vec<bool>,
+    // State of each entry state + whether the empty input matches from it.
+    starts: Vec<(usize, bool)>,
+}
+
+impl Dfa {
+    // Builds the states reachable from the entry states, eg: the search and the start states of an
+    // engine. None when that takes more than `max_states`, eg: for `(a|b)*a(a|b){20}`, which needs
+    // a state per last 21 chars.
+    pub fn new(
+        transitions: &Transition,
+        entry_states: &[State],
+        finish_state: State,
+        max_states: usize,
+        storage: DfaStorage,
+    ) -> Option<Dfa> {
+        let classes = CharClasses::new(transitions);
+        let representatives = classes.representatives();
+
+        let mut dfa = Dfa {
+            classes,
+            moves: Moves::Dense(vec![]),
+            is_accepting: vec![],
+            is_accepting_at_end: vec![],
+            starts: vec![],
+        };
+        let mut ids: HashMap<Vec<State>, usize> = HashMap::default();
+        let mut sets: Vec<Vec<State>> = vec![];
+        dfa.add_state(&mut ids, &mut sets, vec![], transitions, finish_state);
+
+        for &entry in entry_states {
+            let start = closure(transitions, &[entry], true, false);
+            let accepts_empty =
+                closure(transitions, &[entry], true, true).binary_search(&finish_state);
+            let id = dfa.add_state(&mut ids, &mut sets, start, transitions, finish_state);
+            dfa.starts.push((id, accepts_empty.is_ok()));
+        }
+
+        // States get their moves in order, new ones are added to the end.
+        let mut moves = vec![];
+        let mut next = 0;
+        while next < sets.len() {
+            if sets.len() > max_states {
+                return None;
+            }
+
+            for &c in &representatives {
+                let stepped = sets[next]
+                    .iter()
+                    .flat_map(|&state| transitions.states_from(state, Some(&c), 0))
+                    .filter(|(_, i)| *i == 1)
+                    .map(|(state, _)| state)
+                    .collect::<Vec<_>>();
+                let set = closure(transitions, &stepped, false, false);
+                let id = dfa.add_state(&mut ids, &mut sets, set, transitions, finish_state);
+                moves.push(id);
+            }
+            next += 1;
+        }
+
+        let is_sparse = match storage {
+            DfaStorage::Dense => false,
+            DfaStorage::Sparse => true,
+            DfaStorage::Auto => moves.len() > MAX_DENSE_MOVES,
+        };
+        dfa.moves = match is_sparse {
+            true => dfa.sparse_moves(&moves),
+            false => Moves::Dense(moves),
+        };
+        Some(dfa)
+    }
+
+    // The ranges of chars of the dense moves, merging adjacent ones with the same move.
+    fn sparse_moves(&self, dense: &[usize]) -> Moves {
+        let class_count = self.classes.count();
+        let mut offsets = vec![0];
+        let mut ranges: Vec<(char, usize)> = vec![];
+
+        for state_moves in dense.chunks(class_count) {
+            let state_start = ranges.len();
+            for &(start, class) in self.classes.ranges() {
+                let to = state_moves[class];
+                if ranges.len() == state_start || ranges[ranges.len() - 1].1 != to {
+                    ranges.push((start, to));
+                }
+            }
+            offsets.push(ranges.len());
+        }
+
+        Moves::Sparse { offsets, ranges }
+    }
+
+    // How the moves are stored, `Dense` or `Sparse`.
+    pub fn storage(&self) -> DfaStorage {
+        match self.moves {
+            Moves::Dense(_) => DfaStorage::Dense,
+            Moves::Sparse { .. } => DfaStorage::Sparse,
+        }
+    }
+
+    // Number of stored moves, a move per class of each state when dense.
+    pub fn move_count(&self) -> usize {
+        match &self.moves {
+            Moves::Dense(moves) => moves.len(),
+            Moves::Sparse { ranges, .. } => ranges.len(),
+        }
+    }
+
+    fn next_state(&self, state: usize, c: char) -> usize {
+        match &self.moves {
+            Moves::Dense(moves) => moves[state * self.classes.count() + self.classes.class_of(c)],
+            Moves::Sparse { offsets, ranges } => {
+                let state_ranges = &ranges[offsets[state]..offsets[state + 1]];
+                let i = state_ranges.partition_point(|(start, _)| *start <= c);
+                state_ranges[i - 1].1
+            }
+        }
+    }
+
+    // The state of the set, added when new.
+    fn add_state(
+        &mut self,
+        ids: &mut HashMap<Vec<State>, usize>,
+        sets: &mut Vec<Vec<State>>,
+        set: Vec<State>,
+        transitions: &Transition,
+        finish_state: State,
+    ) -> usize {
+        if let Some(id) = ids.get(&set) {
+            return *id;
+        }
+
+        let at_end = closure(transitions, &set, false, true);
+        self.is_accepting
+            .push(set.binary_search(&finish_state).is_ok());
+        self.is_accepting_at_end
+            .push(at_end.binary_search(&finish_state).is_ok());
+        ids.insert(set.clone(), sets.len());
+        sets.push(set);
+        sets.len() - 1
+    }
+
+    // Number of states, including the one without automaton states.
+    pub fn state_count(&self) -> usize {
+        self.is_accepting.len()
+    }
+
+    // Whether the input matches from the entry state of the given index, up to the end of the
+    // input with `to_end`.
+    pub fn run(&self, entry: usize, input: impl Input, to_end: bool) -> bool {
+        let (mut state, accepts_empty) = self.starts[entry];
+        let mut is_empty = true;
+
+        for c in input.chars() {
+            if !to_end && self.is_accepting[state] {
+                return true;
+            }
+
+            state = self.next_state(state, c);
+            if state == DEAD {
+                return false;
+            }
+            is_empty = false;
+        }
+
+        match is_empty {
+            true => accepts_empty,
+            false => self.is_accepting_at_end[state],
+        }
+    }
+}
+
+// States reachable through epsilon moves and the anchors holding at the position, sorted.
+fn closure(transitions: &Transition, states: &[State], is_start: bool, is_end: bool) -> Vec<State> {
+    let mut out = states
+        .iter()
+        .flat_map(|&state| transitions.epsilon_closure_at(state, is_start, is_end))
+        .collect::<Vec<_>>();
+    out.sort();
+    out.dedup();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dfa::*;
+    use crate::types::START_STATE;
+    use crate::Parser;
+
+    fn dfa_of(raw: &str, max_states: usize) -> Option<Dfa> {
+        dfa_with(raw, max_states, DfaStorage::Auto)
+    }
+
+    fn dfa_with(raw: &str, max_states: usize, storage: DfaStorage) -> Option<Dfa> {
+        let pattern = Parser::parse(raw).unwrap();
+        let (transitions, finish_state) = pattern.to_transition(START_STATE, START_STATE + 1);
+        Dfa::new(
+            &transitions,
+            &[START_STATE],
+            finish_state,
+            max_states,
+            storage,
+        )
+    }
+
+    #[test]
+    fn test_run() {
+        let dfa = dfa_of("a(b|c)*d", MAX_STATES).unwrap();
+        assert!(dfa.run(0, "abcbd", true));
+        assert!(dfa.run(0, "ad", true));
+        assert!(!dfa.run(0, "abc", true));
+        assert!(!dfa.run(0, "xad", true));
+        // Accepts before the end of the input without `to_end`.
+        assert!(dfa.run(0, "adx", false));
+        assert!(!dfa.run(0, "adx", true));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let dfa = dfa_of("^a*$", MAX_STATES).unwrap();
+        assert!(dfa.run(0, "", true));
+        assert!(dfa.run(0, "aaa", false));
+        assert!(!dfa.run(0, "aab", false));
+
+        let dfa = dfa_of("a|$", MAX_STATES).unwrap();
+        assert!(dfa.run(0, "", true));
+        assert!(!dfa.run(0, "b", false));
+    }
+
+    #[test]
+    fn test_max_states() {
+        // A state per last 4 chars, plus the start and the dead states.
+        let dfa = dfa_of("(a|b)*a(a|b)(a|b)(a|b)", 100).unwrap();
+        assert_eq!(18, dfa.state_count());
+        assert!(dfa_of("(a|b)*a(a|b)(a|b)(a|b)", 10).is_none());
+    }
+
+    #[test]
+    fn test_storage() {
+        for storage in [DfaStorage::Dense, DfaStorage::Sparse] {
+            let dfa = dfa_with("a[b-y]*z|$", MAX_STATES, storage).unwrap();
+            assert_eq!(storage, dfa.storage());
+            assert!(dfa.run(0, "abcyz", true));
+            assert!(dfa.run(0, "az", true));
+            assert!(dfa.run(0, "", true));
+            assert!(!dfa.run(0, "aaz", true));
+            assert!(!dfa.run(0, "a\u{10FFFF}", false));
+        }
+
+        // 5 states and 4 classes: a, b-y, z and the rest. The sparse ranges merge the moves to the
+        // dead state, eg: a single range in the dead and the accepting states.
+        let dense = dfa_with("a[b-y]*z", MAX_STATES, DfaStorage::Dense).unwrap();
+        let sparse = dfa_with("a[b-y]*z", MAX_STATES, DfaStorage::Sparse).unwrap();
+        assert_eq!(5 * 4, dense.move_count());
+        assert_eq!(1 + 3 + 4 + 4 + 1, sparse.move_count());
+
+        // A string of 200 different chars: 202 states, 201 classes.
+        let raw = (0..200)
+            .map(|i| char::from_u32(0x100 + i).unwrap())
+            .collect::<String>();
+        let dfa = dfa_of(&raw, MAX_STATES).unwrap();
+        assert_eq!(DfaStorage::Sparse, dfa.storage());
+        assert!(dfa.run(0, &raw, true));
+        assert_eq!(
+            DfaStorage::Dense,
+            dfa_of(&raw[..100], MAX_STATES).unwrap().storage()
+        );
+    }
+}
@@ -1,221 +1,4700 @@
+use crate::dfa::{Dfa, DfaStorage, MAX_STATES};
+use crate::input::*;
+use crate::optimizer::*;
 use crate::parser::*;
+use crate::pike::Program;
+use crate::scratch::*;
 use crate::types::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::BufRead;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+//                 From   To     Label   Color
+type DotEdge = (State, State, String, &'static str);
+
+// Bytes searched between two checks of the deadline of `Engine::find_with_deadline`.
+const DEADLINE_CHECK_LEN: usize = 16 * 1024;
+
+pub struct EngineBuilder<'a> {
+    pattern: &'a str,
+    budget: CompileBudget,
+    pool_scratch: bool,
+    max_haystack_len: Option<usize>,
+    graphemes: bool,
+    reject_nullable: bool,
+    safe_mode: bool,
+    prune: bool,
+    merge: bool,
+    captures: bool,
+    max_capture_groups: Option<usize>,
+    auto_optimize: bool,
+    dfa_storage: DfaStorage,
+}
+
+impl<'a> EngineBuilder<'a> {
+    pub fn new(pattern: &'a str) -> EngineBuilder<'a> {
+        EngineBuilder {
+            pattern,
+            budget: CompileBudget::default(),
+            pool_scratch: true,
+            max_haystack_len: None,
+            graphemes: false,
+            reject_nullable: false,
+            safe_mode: false,
+            prune: true,
+            merge: true,
+            captures: true,
+            max_capture_groups: None,
+            auto_optimize: false,
+            dfa_storage: DfaStorage::Auto,
+        }
+    }
+
+    // Bounds the compilation work, eg: for untrusted patterns with huge repetitions.
+    pub fn max_compile_steps(mut self, max_steps: usize) -> EngineBuilder<'a> {
+        self.budget.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn max_compile_time(mut self, max_duration: Duration) -> EngineBuilder<'a> {
+        self.budget.max_duration = Some(max_duration);
+        self
+    }
+
+    // Matcher buffers are kept per thread and reused by the next match by default. Turning it off
+    // allocates them on each match instead, eg: for low memory environments.
+    pub fn scratch_pooling(mut self, pool_scratch: bool) -> EngineBuilder<'a> {
+        self.pool_scratch = pool_scratch;
+        self
+    }
+
+    // Matches only look at the first `max_len` bytes of the strings (cut back to a char boundary),
+    // eg: to enforce input budgets of a service. See `Engine::is_match_bounded`. Applies to all
+    // the methods taking a string, not to streams and `Input`s, which have no length upfront.
+    pub fn max_haystack_len(mut self, max_len: usize) -> EngineBuilder<'a> {
+        self.max_haystack_len = Some(max_len);
+        self
+    }
+
+    // `.` matches an extended grapheme cluster instead of a char, eg: "e\u{301}" (e + combining
+    // accent) is one `.`, and matches start at cluster boundaries, for `is_match` and `find` alike.
+    // The scanners fed by chunks read by char, as a cluster may go on in the next chunk:
+    // `stream_matcher`, `end_scanner`, `replace_stream` and `find_stream`. So does
+    // `is_match_fuzzy`.
+    #[cfg(feature = "grapheme")]
+    pub fn grapheme_mode(mut self, graphemes: bool) -> EngineBuilder<'a> {
+        self.graphemes = graphemes;
+        self
+    }
+
+    // Strict mode for find-all uses: fails the build when the pattern matches the empty string,
+    // eg: `a*`, which would match at every position.
+    pub fn reject_nullable(mut self, reject_nullable: bool) -> EngineBuilder<'a> {
+        self.reject_nullable = reject_nullable;
+        self
+    }
+
+    // For untrusted haystacks: `is_match` and `is_full_match` simulate the automaton on the set of
+    // active states, which reads each char once, so matching takes at most (chars + 1) * states
+    // steps whatever the pattern, eg: `(a|a)*b` on "aaa...a". Grapheme mode needs lookahead over
+    // the haystack, so it can't be combined with it.
+    pub fn safe_mode(mut self) -> EngineBuilder<'a> {
+        self.safe_mode = true;
+        self
+    }
+
+    // Unreachable and dead end states are dropped by default. Keeping them shows the automaton as
+    // compiled, eg: for debugging the compiler with `to_dot`.
+    pub fn prune_states(mut self, prune: bool) -> EngineBuilder<'a> {
+        self.prune = prune;
+        self
+    }
+
+    // States with the same moves are merged by default, see `Transition::merge_equivalent`.
+    pub fn merge_states(mut self, merge: bool) -> EngineBuilder<'a> {
+        self.merge = merge;
+        self
+    }
+
+    // Turning it off compiles the groups as non-capturing, eg: `(ab)+` as `(?:ab)+`, which gives a
+    // smaller automaton when only the whole match is needed. `Engine::captures` then only has
+    // group 0 and `Engine::capture_group_tree` is empty.
+    pub fn captures(mut self, captures: bool) -> EngineBuilder<'a> {
+        self.captures = captures;
+        self
+    }
+
+    // Fails the build when the pattern has more capturing groups, as each one adds two slots to
+    // every thread of `Engine::captures`. Not checked when captures are turned off.
+    pub fn max_capture_groups(mut self, max_groups: usize) -> EngineBuilder<'a> {
+        self.max_capture_groups = Some(max_groups);
+        self
+    }
+
+    // Rewrites the pattern for faster searches where only the spans of the matches change, not
+    // which strings a search matches: drops the `.*` the pattern starts or ends with, see
+    // `PatternSection::strip_redundant_wildcards`. `Parser::lint` warns about them instead.
+    pub fn auto_optimize(mut self, auto_optimize: bool) -> EngineBuilder<'a> {
+        self.auto_optimize = auto_optimize;
+        self
+    }
+
+    // How `Engine::compile_dfa` stores the moves, picked by the size of the table by default, see
+    // `DfaStorage`.
+    pub fn dfa_storage(mut self, storage: DfaStorage) -> EngineBuilder<'a> {
+        self.dfa_storage = storage;
+        self
+    }
+
+    pub fn build(self) -> Result<Engine, RegexError> {
+        self.build_with(&mut CharClassInterner::default())
+    }
+
+    fn build_with(self, interner: &mut CharClassInterner) -> Result<Engine, RegexError> {
+        let mut pattern = Parser::parse(self.pattern)?;
+        if self.auto_optimize {
+            pattern = pattern.strip_redundant_wildcards();
+        }
+        if !self.captures {
+            pattern.strip_captures();
+        } else if let Some(max_groups) = self.max_capture_groups {
+            if let Some(group) = pattern.capture_groups().get(max_groups) {
+                return Err(RegexError::new(
+                    ErrorCode::TooManyCaptureGroups,
+                    Some(group.span()),
+                    format!("Pattern has more than {} capture groups", max_groups),
+                )
+                .with_suggestion(Some(
+                    "use `(?:` for the groups that don't need to capture".into(),
+                )));
+            }
+        }
+
+        let mut engine = Engine::compile(pattern, &self.budget, interner, self.prune, self.merge)?;
+        if self.captures {
+            engine.groups = Parser::group_tree(self.pattern).into();
+        }
+        engine.pool_scratch = self.pool_scratch;
+        engine.max_haystack_len = self.max_haystack_len;
+        engine.graphemes = self.graphemes;
+        engine.safe_mode = self.safe_mode;
+        engine.dfa_storage = self.dfa_storage;
+
+        if self.safe_mode && self.graphemes {
+            return Err(RegexError::new(
+                ErrorCode::UnsupportedInSafeMode,
+                None,
+                "Grapheme mode is not supported in safe mode".into(),
+            ));
+        }
+        if self.reject_nullable && engine.is_nullable() {
+            return Err(RegexError::new(
+                ErrorCode::NullablePattern,
+                None,
+                ErrorCode::NullablePattern.message().into(),
+            )
+            .with_suggestion(Some(
+                "make sure at least one part is required, eg: `a+` instead of `a*`".into(),
+            )));
+        }
+        Ok(engine)
+    }
+}
 
 #[derive(Debug)]
 pub struct Engine {
-    transitions: Transition,
+    // None when the engine is built from a transition table.
+    // Shared with the anchored views of the engine.
+    pattern: Option<Arc<PatternSection>>,
+    groups: Arc<[CaptureGroup]>,
+    // Name of each capture group by index, see `PatternSection::capture_names`.
+    group_names: Arc<[Option<String>]>,
+    transitions: Arc<Transition>,
+    start_state: State,
     finish_state: State,
+    // Entry state of the unanchored search: loops on any char, then jumps to the start state.
+    search_state: State,
+    // Whether matches have to start where the search starts, see `anchored_view`.
+    anchored: bool,
+    // Key of the reusable matcher buffers.
+    id: usize,
+    pool_scratch: bool,
+    max_haystack_len: Option<usize>,
+    // Whether `.` matches a grapheme cluster.
+    graphemes: bool,
+    // Whether matching uses the set simulation, see `EngineBuilder::safe_mode`.
+    safe_mode: bool,
+    // Deterministic form of the automaton from the search and the start states, see `compile_dfa`.
+    dfa: Option<Arc<Dfa>>,
+    // Prioritized form of the pattern for the leftmost-first ends of `find_iter`, None when the
+    // engine is built from a transition table.
+    program: Option<Arc<Program>>,
+    dfa_storage: DfaStorage,
+    stats: EngineStats,
 }
 
-impl Engine {
-    pub fn new(pattern: &str) -> Engine {
-        let pattern = Parser::parse(pattern);
-        let (transitions, finish_state) = pattern.to_transition(0, 1);
-        Engine {
-            transitions,
-            finish_state,
+impl Engine {
+    // Compiles the pattern with the default settings, see `EngineBuilder` for the others.
+    pub fn new(pattern: &str) -> Result<Engine, RegexError> {
+        EngineBuilder::new(pattern).build()
+    }
+
+    // Compiles a pattern built without the parser, eg: by a code generator. Fails like `new` when
+    // the automaton gets too large.
+    pub fn from_ast(pattern: PatternSection) -> Result<Engine, RegexError> {
+        Engine::compile(
+            pattern,
+            &CompileBudget::default(),
+            &mut CharClassInterner::default(),
+            true,
+            true,
+        )
+    }
+
+    // Compiles the patterns with the default settings, sharing one copy of the negated classes the
+    // patterns have in common, eg: for thousands of rules loaded at startup. The error is of the
+    // first invalid pattern, with its index.
+    pub fn compile_many(patterns: &[&str]) -> Result<Vec<Engine>, (usize, RegexError)> {
+        let mut interner = CharClassInterner::default();
+        patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| {
+                EngineBuilder::new(pattern)
+                    .build_with(&mut interner)
+                    .map_err(|err| (i, err))
+            })
+            .collect()
+    }
+
+    fn compile(
+        pattern: PatternSection,
+        budget: &CompileBudget,
+        interner: &mut CharClassInterner,
+        prune: bool,
+        merge: bool,
+    ) -> Result<Engine, RegexError> {
+        let mut stats = EngineStats {
+            ast_nodes: pattern.node_count(),
+            ..EngineStats::default()
+        };
+        let group_names = pattern.capture_names().into();
+        let program = Program::new(&pattern, budget)?;
+        let pattern = Optimizer::factor_prefixes(pattern);
+        stats.factored_ast_nodes = pattern.node_count();
+        let (mut transitions, finish_state) =
+            pattern.to_transition_with_budget(START_STATE, START_STATE + 1, budget, interner)?;
+
+        // The finish state is not the last one when the pattern ends in a loop, eg: `a*`.
+        let last_state = transitions
+            .max_state()
+            .unwrap_or(START_STATE)
+            .max(finish_state);
+        let search_state = next_state(last_state)?;
+        transitions.insert_wildcard(search_state, search_state);
+        transitions.insert_base((search_state, None), START_STATE);
+        stats.compiled = transitions.size();
+        if prune {
+            transitions.prune(&[START_STATE, search_state], finish_state);
+        }
+        stats.pruned = transitions.size();
+        if merge {
+            transitions.merge_equivalent(&[START_STATE, finish_state, search_state]);
+        }
+        stats.merged = transitions.size();
+        stats.char_classes = CharClasses::new(&transitions).count();
+
+        Ok(Engine {
+            pattern: Some(Arc::new(pattern)),
+            groups: Arc::new([]),
+            group_names,
+            transitions: Arc::new(transitions),
+            start_state: START_STATE,
+            finish_state,
+            search_state,
+            anchored: false,
+            id: next_engine_id(),
+            pool_scratch: true,
+            max_haystack_len: None,
+            graphemes: false,
+            safe_mode: false,
+            dfa: None,
+            program: Some(Arc::new(program)),
+            dfa_storage: DfaStorage::Auto,
+            stats,
+        })
+    }
+
+    // Builds the engine from a hand written automaton, eg:
+    //
+    //     start q0
+    //     accept q1 q2
+    //     q0 a q1
+    //     q1 . q1
+    //     q0 eps q2
+    //
+    // States are any names, `.` reads any char, `\.` a dot, `[a-z]` a char of the group and `[^ab]`
+    // any char but the listed ones, see `ClassRange::to_pattern`, `eps` is an epsilon move and `^`
+    // and `$` are anchors, `\^` and `\$` read these chars. Lines starting with `#` are comments.
+    // Moves of states not on a path from the start to an accepting state are dropped.
+    pub fn from_table(table: &str) -> Result<Engine, String> {
+        let mut transitions = Transition::new();
+        let mut start = None;
+        let mut accepts = vec![];
+        let mut edges = vec![];
+        let mut class_edges = vec![];
+        let mut wildcard_edges = vec![];
+        let mut anchor_edges = vec![];
+
+        for (line_idx, line) in table.lines().enumerate() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["start", state] => start = Some(*state),
+                ["accept", states @ ..] => accepts.extend_from_slice(states),
+                [from, "eps", to] => edges.push((*from, None, *to)),
+                [from, ".", to] => wildcard_edges.push((*from, *to)),
+                [from, "^", to] => anchor_edges.push((*from, Anchor::Start, *to)),
+                [from, "$", to] => anchor_edges.push((*from, Anchor::End, *to)),
+                [from, escaped @ ("\\." | "\\^" | "\\$"), to] => {
+                    edges.push((*from, escaped.chars().nth(1), *to))
+                }
+                [from, group, to] if group.len() > 2 && group.starts_with('[') => {
+                    let class = ClassRange::from_pattern(group)
+                        .ok_or_else(|| format!("Invalid line {}: {}", line_idx + 1, line))?;
+                    class_edges.push((*from, class, *to))
+                }
+                [from, c, to] if c.chars().count() == 1 => {
+                    edges.push((*from, c.chars().next(), *to))
+                }
+                _ => return Err(format!("Invalid line {}: {}", line_idx + 1, line)),
+            }
+        }
+
+        let start = start.ok_or("Missing start state")?;
+
+        // States are numbered in order of appearance.
+        let mut ids = HashMap::default();
+        let mut id_of = |name| {
+            let next_id = ids.len();
+            *ids.entry(name).or_insert(next_id)
+        };
+
+        for (from, c, to) in edges {
+            transitions.insert_base((id_of(from), c), id_of(to));
+        }
+        for (from, class, to) in class_edges {
+            transitions.insert_class(id_of(from), class.into(), id_of(to));
+        }
+        for (from, to) in wildcard_edges {
+            transitions.insert_wildcard(id_of(from), id_of(to));
+        }
+        for (from, anchor, to) in anchor_edges {
+            transitions.insert_anchor(id_of(from), anchor, id_of(to));
+        }
+
+        let start_state = id_of(start);
+        let accepts = accepts.into_iter().map(&mut id_of).collect::<Vec<_>>();
+        let finish_state = ids.len();
+        for accept in accepts {
+            transitions.insert_base((accept, None), finish_state);
+        }
+
+        let search_state = finish_state + 1;
+        transitions.insert_wildcard(search_state, search_state);
+        transitions.insert_base((search_state, None), start_state);
+        let compiled = transitions.size();
+        transitions.prune(&[start_state, search_state], finish_state);
+        let stats = EngineStats {
+            compiled,
+            pruned: transitions.size(),
+            merged: transitions.size(),
+            char_classes: CharClasses::new(&transitions).count(),
+            ..EngineStats::default()
+        };
+
+        Ok(Engine {
+            pattern: None,
+            groups: Arc::new([]),
+            group_names: vec![None].into(),
+            transitions: Arc::new(transitions),
+            start_state,
+            finish_state,
+            search_state,
+            anchored: false,
+            id: next_engine_id(),
+            pool_scratch: true,
+            max_haystack_len: None,
+            graphemes: false,
+            safe_mode: false,
+            dfa: None,
+            program: None,
+            dfa_storage: DfaStorage::Auto,
+            stats,
+        })
+    }
+
+    pub fn stats(&self) -> EngineStats {
+        self.stats
+    }
+
+    // The same engine, but matches have to start where the search starts, eg: `ab` matches "abx"
+    // but not "xab". The automaton is shared, not copied.
+    pub fn anchored_view(&self) -> Engine {
+        Engine {
+            pattern: self.pattern.clone(),
+            groups: Arc::clone(&self.groups),
+            group_names: Arc::clone(&self.group_names),
+            transitions: Arc::clone(&self.transitions),
+            start_state: self.start_state,
+            finish_state: self.finish_state,
+            search_state: self.search_state,
+            anchored: true,
+            id: next_engine_id(),
+            pool_scratch: self.pool_scratch,
+            max_haystack_len: self.max_haystack_len,
+            graphemes: self.graphemes,
+            safe_mode: self.safe_mode,
+            dfa: self.dfa.clone(),
+            program: self.program.clone(),
+            dfa_storage: self.dfa_storage,
+            stats: self.stats,
+        }
+    }
+
+    // Builds the deterministic form of the automaton, so `is_match` and `is_full_match` read each
+    // char once, see `Dfa`. Returns false and keeps matching on the automaton when that would take
+    // more than `dfa::MAX_STATES` states, or in grapheme mode, where `.` reads a whole cluster.
+    pub fn compile_dfa(&mut self) -> bool {
+        if self.graphemes {
+            return false;
+        }
+
+        let entry_states = [self.search_state, self.start_state];
+        self.dfa = Dfa::new(
+            &self.transitions,
+            &entry_states,
+            self.finish_state,
+            MAX_STATES,
+            self.dfa_storage,
+        )
+        .map(Arc::new);
+        self.dfa.is_some()
+    }
+
+    // Whether matching runs on the deterministic form of the automaton, see `compile_dfa`.
+    pub fn has_dfa(&self) -> bool {
+        self.dfa.is_some()
+    }
+
+    // How the moves of the deterministic form are stored, None without one.
+    pub fn dfa_storage(&self) -> Option<DfaStorage> {
+        self.dfa.as_ref().map(|dfa| dfa.storage())
+    }
+
+    // Where searches for a match anywhere within the string start.
+    fn entry_state(&self) -> State {
+        if self.anchored {
+            self.start_state
+        } else {
+            self.search_state
+        }
+    }
+
+    // Whether the pattern matches anywhere within the string.
+    pub fn is_match(&self, s: &str) -> bool {
+        self.is_match_bounded(s) == BoundedMatch::Match
+    }
+
+    // Like `is_match`, but tells apart strings cut by the maximum haystack length.
+    pub fn is_match_bounded(&self, s: &str) -> BoundedMatch {
+        let (s, is_truncated) = self.bound_haystack(s);
+        match (self.run(self.entry_state(), s, false), is_truncated) {
+            (true, _) => BoundedMatch::Match,
+            (false, false) => BoundedMatch::NoMatch,
+            (false, true) => BoundedMatch::Truncated {
+                processed_len: s.len(),
+            },
+        }
+    }
+
+    // Whether the pattern matches the whole string.
+    // Always false for strings longer than the maximum haystack length.
+    pub fn is_full_match(&self, s: &str) -> bool {
+        let (s, is_truncated) = self.bound_haystack(s);
+        !is_truncated && self.run(self.start_state, s, true)
+    }
+
+    // Like `is_match`, but over any `Input`, eg: bytes or a char iterator. The maximum haystack
+    // length doesn't apply, so bound unknown length input beforehand, eg: with `take`.
+    pub fn is_match_input(&self, input: impl Input) -> bool {
+        self.run(self.entry_state(), input, false)
+    }
+
+    // Like `is_full_match`, but over any `Input`, see `is_match_input`.
+    pub fn is_full_match_input(&self, input: impl Input) -> bool {
+        self.run(self.start_state, input, true)
+    }
+
+    // Where the wildcard stops reading in the part of the string within the maximum haystack
+    // length, see `Clusters`.
+    #[cfg(feature = "grapheme")]
+    fn clusters(&self, s: &str) -> Clusters {
+        if self.graphemes {
+            Clusters::graphemes(self.bound_haystack(s).0)
+        } else {
+            Clusters::default()
+        }
+    }
+
+    #[cfg(not(feature = "grapheme"))]
+    fn clusters(&self, _: &str) -> Clusters {
+        Clusters::default()
+    }
+
+    // The part of the string within the maximum haystack length + whether it was cut.
+    fn bound_haystack<'s>(&self, s: &'s str) -> (&'s str, bool) {
+        match self.max_haystack_len {
+            Some(max_len) if s.len() > max_len => (&s[..s.floor_char_boundary(max_len)], true),
+            _ => (s, false),
+        }
+    }
+
+    // Top level groups of the pattern with their nested groups. Empty when the engine was not
+    // built from a pattern string.
+    pub fn capture_group_tree(&self) -> &[CaptureGroup] {
+        &self.groups
+    }
+
+    pub fn explain(&self) -> String {
+        match &self.pattern {
+            Some(pattern) => format!("match {}", pattern.explain()),
+            None => "match by a transition table".into(),
+        }
+    }
+
+    pub fn features(&self) -> PatternFeatures {
+        match &self.pattern {
+            Some(pattern) => pattern.features(),
+            None => PatternFeatures {
+                has_captures: false,
+                has_backrefs: false,
+                has_lookaround: false,
+                is_literal: false,
+                is_anchored: false,
+                max_len: None,
+            },
+        }
+    }
+
+    // Whether the pattern matches anywhere within the string + the visited (state, char index)
+    // pairs in visiting order.
+    pub fn trace(&self, s: &str) -> (bool, Vec<(State, usize)>) {
+        let mut steps = vec![];
+        let is_match = self.run_with(self.entry_state(), s, false, |state, i| {
+            steps.push((state, i))
+        });
+        (is_match, steps)
+    }
+
+    // The non-overlapping matches from left to right. Each match starts as early as possible, then
+    // the earlier branch wins and greedy quantifiers repeat as much as they can, like backtracking
+    // engines (leftmost-first): `a|ab` on "abab" gives "a" twice and `a+?` on "aa" gives "a" twice.
+    // The first one is the match of `find`, `find_longest` is leftmost-longest. The whole buffer is
+    // searched at once, so matches can span lines, eg: `a.b` on "a\nb".
+    //
+    // After an empty match the search moves on by a char, and an empty match right where the
+    // previous match ended is skipped, like other engines do: `a*` on "baab" gives (0, 0), (1, 3)
+    // and (4, 4).
+    pub fn find_iter<'e, 'h>(&'e self, haystack: &'h str) -> FindIter<'e, 'h> {
+        FindIter {
+            engine: self,
+            haystack,
+            pos: Some(0),
+            last_end: None,
+            clusters: self.clusters(haystack),
+        }
+    }
+
+    // Substrings between the matches of `find_iter`, like `str::split` with the pattern as the
+    // separator, eg: `[,;]` on "a,b;;c" gives "a", "b", "" and "c".
+    pub fn split<'e, 'h>(&'e self, haystack: &'h str) -> Split<'e, 'h> {
+        Split {
+            matches: self.find_iter(haystack),
+            last: Some(0),
+            limit: None,
+        }
+    }
+
+    // At most `limit` substrings of `split`, the last one being the rest of the haystack.
+    pub fn splitn<'e, 'h>(&'e self, haystack: &'h str, limit: usize) -> Split<'e, 'h> {
+        Split {
+            limit: Some(limit),
+            ..self.split(haystack)
+        }
+    }
+
+    // Byte spans of the matches of `find_iter`.
+    pub fn find_iter_multiline<'h>(&'h self, haystack: &'h str) -> impl Iterator<Item = Span> + 'h {
+        self.find_iter(haystack).map(|m| m.span())
+    }
+
+    // Calls back with the events of each match of `find_iter_multiline`, without collecting them:
+    // its start once found, the groups it captures by index, then its end. The scan stops when the
+    // callback breaks, which is then returned, eg: breaking on the start skips reading the match.
+    pub fn scan(
+        &self,
+        haystack: &str,
+        mut on_event: impl FnMut(ScanEvent) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let clusters = self.clusters(haystack);
+        let mut pos = Some(0);
+        let mut last_end = None;
+        while let Some(start) = pos.and_then(|pos| self.leftmost_start(haystack, pos, &clusters)) {
+            // An empty match where the last one ended is skipped, which takes its end to tell.
+            let is_after_match = last_end == Some(start);
+            if !is_after_match {
+                on_event(ScanEvent::MatchStart(start))?;
+            }
+            let Some(end) = self.first_match_end(haystack, start, &clusters) else {
+                break;
+            };
+            pos = next_search(haystack, (start, end));
+            if is_after_match {
+                if start == end {
+                    continue;
+                }
+                on_event(ScanEvent::MatchStart(start))?;
+            }
+            last_end = Some(end);
+
+            if self.capture_count() > 0 {
+                let found = Match {
+                    start,
+                    end,
+                    slice: &haystack[start..end],
+                };
+                let caps = self.captures_with(haystack, found, &clusters);
+                for group in 1..caps.len() {
+                    if let Some(group_match) = caps.get(group) {
+                        on_event(ScanEvent::GroupCapture(group, group_match.span()))?;
+                    }
+                }
+            }
+            on_event(ScanEvent::MatchEnd((start, end)))?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Leftmost-first match anywhere within the haystack, the first match of `find_iter`, eg: `b+`
+    // on "abbc" finds "bb" at (1, 3) and `a|ab` on "ab" finds "a", like the regex crate.
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.find_with(haystack, &self.clusters(haystack))
+    }
+
+    fn find_with<'h>(&self, haystack: &'h str, clusters: &Clusters) -> Option<Match<'h>> {
+        let (start, end) = self.leftmost_first(haystack, 0, clusters)?;
+        Some(Match {
+            start,
+            end,
+            slice: &haystack[start..end],
+        })
+    }
+
+    // Leftmost-longest match anywhere within the haystack, eg: `a|ab` on "ab" finds "ab", like
+    // POSIX regexes.
+    pub fn find_longest<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.find_longest_with(haystack, &self.clusters(haystack))
+    }
+
+    fn find_longest_with<'h>(&self, haystack: &'h str, clusters: &Clusters) -> Option<Match<'h>> {
+        let (start, end) = self.leftmost_longest(haystack, 0, clusters)?;
+        Some(Match {
+            start,
+            end,
+            slice: &haystack[start..end],
+        })
+    }
+
+    // The match of `find` with the spans of its capture groups, indexed by their opening parens,
+    // by the branches the match took: `(a|ab)(c|bcd)` on "abcd" captures "a" and "bcd".
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        let clusters = self.clusters(haystack);
+        let found = self.find_with(haystack, &clusters)?;
+        Some(self.captures_with(haystack, found, &clusters))
+    }
+
+    // The match of `find_longest` with the spans of its capture groups. When a group could capture
+    // different parts of the match, it takes the one starting first, then the longest:
+    // `(a|ab)(c|bcd)` on "abcd" captures "a" and "bcd".
+    pub fn captures_longest<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        let clusters = self.clusters(haystack);
+        let found = self.find_longest_with(haystack, &clusters)?;
+        // Anchors hold where the match was found, at the end of the bounded haystack.
+        let (haystack, _) = self.bound_haystack(haystack);
+        let mut slots = self.capture_slots(haystack, found.span(), &clusters)?;
+        slots[0] = Some(found.start);
+        slots[1] = Some(found.end);
+
+        Some(Captures {
+            haystack,
+            names: Arc::clone(&self.group_names),
+            slots,
+        })
+    }
+
+    // Capture groups of a match of `find_iter`, by the branches the match took, eg: `ab|(a)b`
+    // matches "ab" by the first branch, so group 1 has no part in it.
+    pub(crate) fn captures_of<'h>(&self, haystack: &'h str, found: Match<'h>) -> Captures<'h> {
+        self.captures_with(haystack, found, &self.clusters(haystack))
+    }
+
+    fn captures_with<'h>(
+        &self,
+        haystack: &'h str,
+        found: Match<'h>,
+        clusters: &Clusters,
+    ) -> Captures<'h> {
+        let (haystack, _) = self.bound_haystack(haystack);
+        let (start, end) = found.span();
+        let slots = match &self.program {
+            Some(program) => program.first_match_captures(haystack, start, clusters),
+            None => self.capture_slots(haystack, (start, end), clusters),
+        };
+        debug_assert!(slots.is_some(), "Missing path of the match");
+        // Only the whole match without its path, as if no group took part.
+        let mut slots = slots.unwrap_or_else(|| vec![None; 2 * (self.capture_count() + 1)]);
+        slots[0] = Some(start);
+        slots[1] = Some(end);
+
+        Captures {
+            haystack,
+            names: Arc::clone(&self.group_names),
+            slots,
+        }
+    }
+
+    // Sorted ranges of the chars a full match can read, eg: `[ab]c|x` gives `a-c` and `x`. Useful
+    // for input generators.
+    pub fn alphabet(&self) -> impl Iterator<Item = ClassRange> {
+        let useful = self
+            .transitions
+            .useful_states(&[self.start_state], self.finish_state);
+
+        let mut ranges = vec![];
+        for ((from, c), to) in &self.transitions.base {
+            let Some(c) = c else {
+                continue;
+            };
+            if useful.contains(from) && to.iter().any(|to| useful.contains(to)) {
+                ranges.push(ClassRange::new(*c, *c));
+            }
+        }
+        for (from, submap) in self.transitions.classes.iter() {
+            for (class, to) in submap {
+                if useful.contains(from) && to.iter().any(|to| useful.contains(to)) {
+                    ranges.extend(class.iter());
+                }
+            }
+        }
+        for (from, to) in &self.transitions.wildcard {
+            if useful.contains(from) && to.iter().any(|to| useful.contains(to)) {
+                ranges.push(ClassRange::ALL);
+            }
+        }
+
+        ClassRange::normalize(ranges).into_iter()
+    }
+
+    // Classes of chars the automaton moves on the same way, eg: for the columns of a table.
+    pub fn char_classes(&self) -> CharClasses {
+        CharClasses::new(&self.transitions)
+    }
+
+    // Number of capturing groups of the pattern, 0 when the engine was not built from a pattern.
+    pub fn capture_count(&self) -> usize {
+        self.group_names.len() - 1
+    }
+
+    // Name of each capture group by index, None for unnamed groups and the whole match at 0.
+    pub fn group_names(&self) -> &[Option<String>] {
+        &self.group_names
+    }
+
+    // Capture offsets of a full match of the span of the haystack, which anchors are checked
+    // against. The threads are kept by the offset they reached, as the wildcard reads a whole
+    // grapheme cluster in grapheme mode.
+    fn capture_slots(
+        &self,
+        haystack: &str,
+        (start, end): Span,
+        clusters: &Clusters,
+    ) -> Option<Vec<Option<usize>>> {
+        let mut threads = HashMap::default();
+        let no_captures = vec![None; 2 * (self.capture_count() + 1)];
+        self.add_thread(haystack, &mut threads, self.start_state, no_captures, start);
+        // Every move reads forward, so the offsets are done in order.
+        let mut pending = BTreeMap::from([(start, threads)]);
+
+        while let Some((pos, mut threads)) = pending.pop_first() {
+            let c = match haystack[pos..end].chars().next() {
+                Some(c) => c,
+                None => return threads.remove(&self.finish_state),
+            };
+            let char_end = pos + c.len_utf8();
+            let wildcard_end = clusters.wildcard_end(pos, c);
+            for (&state, slots) in &threads {
+                for (to, to_pos) in self
+                    .transitions
+                    .states_from_with(state, Some(&c), pos, char_end, wildcard_end)
+                    .into_iter()
+                    .filter(|&(_, to_pos)| to_pos > pos && to_pos <= end)
+                {
+                    let next = pending.entry(to_pos).or_default();
+                    self.add_thread(haystack, next, to, slots.clone(), to_pos);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Adds the state with the captures so far, following the epsilon moves and recording the tags
+    // of the states on the way. A state reached more than once keeps the preferred captures.
+    fn add_thread(
+        &self,
+        haystack: &str,
+        threads: &mut HashMap<State, Vec<Option<usize>>>,
+        state: State,
+        slots: Vec<Option<usize>>,
+        pos: usize,
+    ) {
+        let mut todo = vec![(state, slots)];
+        while let Some((state, mut slots)) = todo.pop() {
+            if let Some(tag) = self.transitions.tags.get(&state) {
+                slots[tag.slot()] = Some(pos);
+            }
+            if threads
+                .get(&state)
+                .is_some_and(|old| !is_preferred_capture(&slots, old))
+            {
+                continue;
+            }
+
+            for to in self
+                .transitions
+                .epsilon_moves(state, pos == 0, pos == haystack.len())
+            {
+                todo.push((to, slots.clone()));
+            }
+            threads.insert(state, slots);
+        }
+    }
+
+    // Leftmost-longest match like the first of `find_iter_multiline`, but gives up once the
+    // deadline passes, eg: so a search box stays responsive on huge buffers.
+    pub fn find_with_deadline(&self, haystack: &str, deadline: Instant) -> DeadlineMatch {
+        let clusters = self.clusters(haystack);
+        match self.leftmost_longest_until(haystack, 0, &clusters, Some(deadline)) {
+            Ok(Some(span)) => DeadlineMatch::Match(span),
+            Ok(None) => DeadlineMatch::NoMatch,
+            Err(scanned_len) => DeadlineMatch::TimedOut { scanned_len },
+        }
+    }
+
+    // Starts where the leftmost-longest match does, as both are the leftmost start of a match.
+    fn leftmost_first(&self, haystack: &str, from: usize, clusters: &Clusters) -> Option<Span> {
+        let start = self.leftmost_start(haystack, from, clusters)?;
+        Some((start, self.first_match_end(haystack, start, clusters)?))
+    }
+
+    // End of the match of `find_iter` starting at byte `start`, None when it has none.
+    fn first_match_end(&self, haystack: &str, start: usize, clusters: &Clusters) -> Option<usize> {
+        let (haystack, _) = self.bound_haystack(haystack);
+        match &self.program {
+            Some(program) => program.first_match_end(haystack, start, clusters),
+            None => self.longest_match_end(haystack, start, clusters),
+        }
+    }
+
+    fn leftmost_longest(&self, haystack: &str, from: usize, clusters: &Clusters) -> Option<Span> {
+        // Without a deadline there is no error.
+        self.leftmost_longest_until(haystack, from, clusters, None)
+            .ok()
+            .flatten()
+    }
+
+    // The error is the offset scanned to when the deadline passed.
+    fn leftmost_longest_until(
+        &self,
+        haystack: &str,
+        from: usize,
+        clusters: &Clusters,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Span>, usize> {
+        let Some(start) = self.leftmost_start_until(haystack, from, clusters, deadline)? else {
+            return Ok(None);
+        };
+        let (haystack, _) = self.bound_haystack(haystack);
+        let end = self.longest_match_end(haystack, start, clusters);
+        Ok(end.map(|end| (start, end)))
+    }
+
+    fn leftmost_start(&self, haystack: &str, from: usize, clusters: &Clusters) -> Option<usize> {
+        // Without a deadline there is no error.
+        self.leftmost_start_until(haystack, from, clusters, None)
+            .ok()
+            .flatten()
+    }
+
+    // Start of the leftmost match from byte `from`, reading each candidate start only up to its
+    // shortest match. The error is the offset scanned to when the deadline passed.
+    fn leftmost_start_until(
+        &self,
+        haystack: &str,
+        from: usize,
+        clusters: &Clusters,
+        deadline: Option<Instant>,
+    ) -> Result<Option<usize>, usize> {
+        let (haystack, _) = self.bound_haystack(haystack);
+        if from > haystack.len() {
+            return Ok(None);
+        }
+        let is_late = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+        // The stream matcher reads by char, so in grapheme mode every cluster start is a candidate.
+        if let Some(starts) = clusters.starts_from(from) {
+            for start in starts {
+                if is_late() {
+                    return Err(start);
+                }
+                if self.has_match_at(haystack, start, clusters) {
+                    return Ok(Some(start));
+                }
+            }
+            return Ok(None);
+        }
+
+        // The first match to end bounds where the leftmost match can start.
+        let mut matcher = StreamMatcher::new(self, from);
+        let mut first_match = matcher.feed(&[]);
+        let mut scanned = from;
+        for chunk in haystack.as_bytes()[from..].chunks(DEADLINE_CHECK_LEN) {
+            if first_match.is_some() {
+                break;
+            }
+            if is_late() {
+                return Err(scanned);
+            }
+            first_match = matcher.feed(chunk);
+            scanned += chunk.len();
+        }
+        let Some((last_start, _)) = first_match.or_else(|| matcher.finish()) else {
+            return Ok(None);
+        };
+
+        let starts = haystack[from..last_start]
+            .char_indices()
+            .map(|(i, _)| from + i)
+            .chain(std::iter::once(last_start));
+        for start in starts {
+            if is_late() {
+                return Err(scanned);
+            }
+            if self.has_match_at(haystack, start, clusters) {
+                return Ok(Some(start));
+            }
+        }
+        Ok(None)
+    }
+
+    // Whether a match starts at byte `start`, stopping at the end of the shortest one.
+    fn has_match_at(&self, haystack: &str, start: usize, clusters: &Clusters) -> bool {
+        let mut is_match = false;
+        self.match_ends(haystack, start, clusters, |_| {
+            is_match = true;
+            ControlFlow::Break(())
+        });
+        is_match
+    }
+
+    // End of the longest match starting at byte `start`.
+    fn longest_match_end(
+        &self,
+        haystack: &str,
+        start: usize,
+        clusters: &Clusters,
+    ) -> Option<usize> {
+        let mut end = None;
+        self.match_ends(haystack, start, clusters, |match_end| {
+            end = Some(match_end);
+            ControlFlow::Continue(())
+        });
+        end
+    }
+
+    // Calls back with the end of each match starting at byte `start`, shortest first, until the
+    // callback breaks. The states are kept by the offset they reached, as the wildcard reads a
+    // whole grapheme cluster in grapheme mode.
+    fn match_ends(
+        &self,
+        haystack: &str,
+        start: usize,
+        clusters: &Clusters,
+        mut on_end: impl FnMut(usize) -> ControlFlow<()>,
+    ) {
+        // Every move reads forward, so the offsets are done in order.
+        let mut pending = BTreeMap::from([(start, vec![self.start_state])]);
+        while let Some((pos, states)) = pending.pop_first() {
+            let states = self.closure(states, pos == 0, pos == haystack.len());
+            if states.contains(&self.finish_state) && on_end(pos).is_break() {
+                return;
+            }
+            let Some(c) = haystack[pos..].chars().next() else {
+                return;
+            };
+
+            let char_end = pos + c.len_utf8();
+            let wildcard_end = clusters.wildcard_end(pos, c);
+            for &state in &states {
+                for (to, to_pos) in
+                    self.transitions
+                        .states_from_with(state, Some(&c), pos, char_end, wildcard_end)
+                {
+                    // Epsilon moves are followed by the closure already.
+                    if to_pos > pos {
+                        pending.entry(to_pos).or_insert_with(Vec::new).push(to);
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether the pattern matches anywhere within the string with at most `max_edits` chars
+    // inserted, deleted or substituted (Levenshtein distance), eg: `hello` matches "say helo"
+    // with 1.
+    pub fn is_match_fuzzy(&self, s: &str, max_edits: usize) -> bool {
+        let (s, _) = self.bound_haystack(s);
+        // Targets of the char reading moves of each state, whatever the char.
+        let mut any_char_moves: HashMap<State, Vec<State>> = HashMap::default();
+        for ((from, c), to) in &self.transitions.base {
+            if c.is_some() {
+                any_char_moves.entry(*from).or_default().extend(to);
+            }
+        }
+        for (from, submap) in &self.transitions.classes {
+            for to in submap.values() {
+                any_char_moves.entry(*from).or_default().extend(to);
+            }
+        }
+        for (from, to) in &self.transitions.wildcard {
+            any_char_moves.entry(*from).or_default().extend(to);
+        }
+
+        // Follows epsilon moves (and anchors holding at the position) for free and skips chars of
+        // the pattern (deletions) for an edit.
+        let relax = |edits: &mut HashMap<State, usize>, is_start: bool, is_end: bool| {
+            let mut todo = edits.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>();
+            while let Some((state, cost)) = todo.pop() {
+                let epsilon_moves = self.transitions.epsilon_moves(state, is_start, is_end);
+                let deletions = any_char_moves.get(&state).into_iter().flatten();
+                let moves = epsilon_moves
+                    .map(|to| (to, cost))
+                    .chain(deletions.map(|&to| (to, cost + 1)));
+
+                for (to, new_cost) in moves {
+                    if new_cost <= max_edits && new_cost < *edits.get(&to).unwrap_or(&usize::MAX) {
+                        edits.insert(to, new_cost);
+                        todo.push((to, new_cost));
+                    }
+                }
+            }
+        };
+
+        // Active states with the least edits they were reached with.
+        let mut edits = HashMap::from_iter([(self.start_state, 0)]);
+        relax(&mut edits, true, s.is_empty());
+
+        for (i, c) in s.char_indices() {
+            if edits.contains_key(&self.finish_state) {
+                return true;
+            }
+
+            let mut next: HashMap<State, usize> = HashMap::default();
+            if !self.anchored {
+                next.insert(self.start_state, 0);
+            }
+            let mut visit = |to: State, cost: usize| {
+                if cost <= max_edits && cost < *next.get(&to).unwrap_or(&usize::MAX) {
+                    next.insert(to, cost);
+                }
+            };
+
+            for (&state, &cost) in &edits {
+                for (to, _) in self
+                    .transitions
+                    .states_from(state, Some(&c), 0)
+                    .into_iter()
+                    .filter(|(_, i)| *i == 1)
+                {
+                    visit(to, cost);
+                }
+                // Substitution.
+                for &to in any_char_moves.get(&state).into_iter().flatten() {
+                    visit(to, cost + 1);
+                }
+                // Insertion.
+                visit(state, cost + 1);
+            }
+
+            relax(&mut next, false, i + c.len_utf8() == s.len());
+            edits = next;
+        }
+
+        edits.contains_key(&self.finish_state)
+    }
+
+    // Checks the string fully matches, otherwise points at where it stopped matching: the first
+    // char no state could read, or the end when the string is too short.
+    pub fn validate(&self, s: &str) -> Result<(), ValidationError> {
+        let outcome = self.match_outcome(s);
+        if outcome.matched {
+            return Ok(());
+        }
+
+        let found = s[outcome.furthest_pos..].chars().next();
+        let end = outcome.furthest_pos + found.map_or(0, char::len_utf8);
+        Err(ValidationError {
+            span: (outcome.furthest_pos, end),
+            found,
+        })
+    }
+
+    // Runs a full match over the string and reports how far it got.
+    pub fn match_outcome(&self, s: &str) -> MatchOutcome {
+        let (s, is_truncated) = self.bound_haystack(s);
+        let mut states = self.closure(vec![self.start_state], true, s.is_empty());
+        for (i, c) in s.char_indices() {
+            let next = self.step(&states, c);
+            if next.is_empty() {
+                return MatchOutcome {
+                    matched: false,
+                    furthest_pos: i,
+                    ended_in_state_count: states.len(),
+                };
+            }
+            states = self.closure(next, false, i + c.len_utf8() == s.len());
+        }
+
+        MatchOutcome {
+            matched: !is_truncated && states.contains(&self.finish_state),
+            furthest_pos: s.len(),
+            ended_in_state_count: states.len(),
+        }
+    }
+
+    // Share of the chars a full match could read before failing, from 0.0 to 1.0, eg: for ranking
+    // autocomplete candidates. A string that is a prefix of a match scores 1.0.
+    pub fn match_score(&self, s: &str) -> f32 {
+        let outcome = self.match_outcome(s);
+        if s.is_empty() {
+            return if outcome.matched { 1.0 } else { 0.0 };
+        }
+
+        s[..outcome.furthest_pos].chars().count() as f32 / s.chars().count() as f32
+    }
+
+    // Risk of the pattern for backtracking matchers, eg: for review bots looking for risky
+    // patterns. A string can run the automaton on many paths when loops overlap:
+    // - exponential: a state can loop back to itself on two different paths reading the same
+    //   chars, eg: `(a|a)*b`, `(a+)+` or `(aa|a)*b`
+    // - polynomial: a state loops and reaches another looping state on the same chars, eg: `a*a*`
+    pub fn complexity_estimate(&self) -> Complexity {
+        // Merging states hides paths a backtracking matcher tries, eg: the two branches of
+        // `(a|a)*` are one state once merged, so the pattern is compiled again without merging.
+        let compiled;
+        let (transitions, start_state) = match &self.pattern {
+            Some(pattern) => {
+                compiled = pattern.to_transition(START_STATE, START_STATE + 1).0;
+                (&compiled, START_STATE)
+            }
+            None => (self.transitions.as_ref(), self.start_state),
+        };
+
+        // Char reading edges, with the epsilon moves before them folded in. An edge reached on
+        // several epsilon paths is kept twice, eg: `(a*)*` reads `a` again through the inner or
+        // the outer loop.
+        let mut edges: HashMap<State, Vec<(CharLabel, State)>> = HashMap::default();
+        let mut todo = vec![start_state];
+        while let Some(state) = todo.pop() {
+            if edges.contains_key(&state) {
+                continue;
+            }
+
+            let mut out = vec![];
+            for (from, paths) in epsilon_path_counts(transitions, state) {
+                let mut from_edges = vec![];
+                for ((_, c), to) in transitions.base.iter().filter(|((f, _), _)| *f == from) {
+                    if let Some(c) = c {
+                        from_edges.extend(to.iter().map(|&to| (CharLabel::Char(*c), to)));
+                    }
+                }
+                for (class, to) in transitions.classes.get(&from).into_iter().flatten() {
+                    let label = CharLabel::Class(class.clone());
+                    from_edges.extend(to.iter().map(|&to| (label.clone(), to)));
+                }
+                for to in transitions.wildcard.get(&from).into_iter().flatten() {
+                    from_edges.push((CharLabel::Any, *to));
+                }
+                for _ in 0..paths {
+                    out.extend(from_edges.iter().cloned());
+                }
+            }
+            out.sort_by_key(|(_, to)| *to);
+            let mut kept: Vec<(CharLabel, State)> = vec![];
+            for edge in out {
+                if kept.iter().filter(|kept| **kept == edge).count() < 2 {
+                    kept.push(edge);
+                }
+            }
+
+            todo.extend(kept.iter().map(|(_, to)| *to));
+            edges.insert(state, kept);
+        }
+
+        let mut states = edges.keys().copied().collect::<Vec<_>>();
+        states.sort();
+
+        // Runs of the automata on the same chars, from the given states, with the indexes of the
+        // edges taken.
+        let runs = |tuple: &Vec<State>| {
+            let mut runs: Vec<(Vec<&CharLabel>, Vec<State>, Vec<usize>)> =
+                vec![(vec![], vec![], vec![])];
+            for state in tuple {
+                runs = runs
+                    .into_iter()
+                    .flat_map(|(labels, to_states, taken)| {
+                        edges[state]
+                            .iter()
+                            .enumerate()
+                            .map(move |(i, (label, to))| {
+                                let mut labels = labels.clone();
+                                labels.push(label);
+                                let mut to_states = to_states.clone();
+                                to_states.push(*to);
+                                let mut taken = taken.clone();
+                                taken.push(i);
+                                (labels, to_states, taken)
+                            })
+                    })
+                    .filter(|(labels, _, _)| CharLabel::overlap(labels))
+                    .collect::<Vec<_>>();
+            }
+            runs.into_iter()
+                .map(|(_, to_states, taken)| (to_states, taken))
+                .collect::<Vec<_>>()
+        };
+        let successors = |tuple: &Vec<State>| {
+            runs(tuple)
+                .into_iter()
+                .map(|(to_states, _)| to_states)
+                .collect()
+        };
+
+        // Pairs of runs from a state + whether they took different edges yet.
+        let diverging = |(pair, diverged): &(Vec<State>, bool)| {
+            runs(pair)
+                .into_iter()
+                .map(|(to_states, taken)| (to_states, *diverged || taken[0] != taken[1]))
+                .collect()
+        };
+        for &q in &states {
+            if reachable((vec![q, q], false), diverging).contains(&(vec![q, q], true)) {
+                return Complexity::Exponential;
+            }
+        }
+
+        for &p in &states {
+            for &q in states.iter().filter(|&&q| q != p) {
+                if reachable(vec![p, p, q], successors).contains(&vec![p, q, q]) {
+                    return Complexity::Polynomial;
+                }
+            }
+        }
+
+        Complexity::Linear
+    }
+
+    // The constructs this build can parse, eg. for frontends to grey out the rest.
+    pub fn supported_syntax() -> Vec<SyntaxFeature> {
+        SyntaxFeature::ALL
+            .iter()
+            .copied()
+            .filter(SyntaxFeature::is_supported)
+            .collect()
+    }
+
+    // Whether the pattern matches the empty string, so finding all matches yields an empty match
+    // wherever nothing else matches.
+    pub fn is_nullable(&self) -> bool {
+        self.closure(vec![self.start_state], true, true)
+            .contains(&self.finish_state)
+    }
+
+    // States reachable through epsilon moves and the anchors holding at the position.
+    fn closure(&self, states: Vec<State>, is_start: bool, is_end: bool) -> HashSet<State> {
+        states
+            .into_iter()
+            .flat_map(|state| self.transitions.epsilon_closure_at(state, is_start, is_end))
+            .collect()
+    }
+
+    // States reached by reading the char.
+    fn step(&self, states: &HashSet<State>, c: char) -> Vec<State> {
+        states
+            .iter()
+            .flat_map(|&state| self.transitions.states_from(state, Some(&c), 0))
+            .filter(|(_, i)| *i == 1)
+            .map(|(state, _)| state)
+            .collect()
+    }
+
+    // Masks every char of every match, keeping the char count, eg: for scrubbing PII from logs.
+    pub fn redact(&self, s: &str, mask: char) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut last_end = 0;
+
+        for (start, end) in self.find_iter_multiline(s) {
+            out.push_str(&s[last_end..start]);
+            out.extend(s[start..end].chars().map(|_| mask));
+            last_end = end;
+        }
+
+        out.push_str(&s[last_end..]);
+        out
+    }
+
+    // Masks only the given groups of every match, keeping the char count, eg: `user=(\w+)` with
+    // group "1" keeps the "user=". Groups are by index when they are numbers and by name otherwise,
+    // like `Captures::parse`. Groups taking no part in a match mask nothing.
+    pub fn redact_groups(&self, s: &str, groups: &[&str], mask: char) -> String {
+        let mut out = String::with_capacity(s.len());
+        let mut last_end = 0;
+        let clusters = self.clusters(s);
+
+        for found in self.find_iter(s) {
+            let caps = self.captures_with(s, found, &clusters);
+            let mut spans = groups
+                .iter()
+                .filter_map(|group| caps.reference(group))
+                .map(|group_match| group_match.span())
+                .collect::<Vec<_>>();
+            spans.sort_unstable();
+
+            // Nested groups overlap, each char is masked once.
+            for (start, end) in spans {
+                let start = start.max(last_end);
+                if start >= end {
+                    continue;
+                }
+                out.push_str(&s[last_end..start]);
+                out.extend(s[start..end].chars().map(|_| mask));
+                last_end = end;
+            }
+        }
+
+        out.push_str(&s[last_end..]);
+        out
+    }
+
+    // Replaces the first `n` matches with the replacement + the number of replaced matches, eg:
+    // for "replace at most once per line" migrations. The replacement is expanded like in
+    // `replace_all`.
+    pub fn replace_first_n(&self, s: &str, replacement: &str, n: usize) -> (String, usize) {
+        let mut out = String::new();
+        let n = self.replace_append(s, replacement, n, &mut out);
+        (out, n)
+    }
+
+    // Replaces the first match, see `replace_all`.
+    pub fn replace(&self, s: &str, replacement: &str) -> String {
+        self.replace_n(s, replacement, 1)
+    }
+
+    // Replaces every match. The replacement can refer to the capture groups of the match, see
+    // `Captures::expand`, eg: `(a+)(b+)` with "$2$1" turns "xaab" into "xbaa".
+    pub fn replace_all(&self, s: &str, replacement: &str) -> String {
+        self.replace_n(s, replacement, usize::MAX)
+    }
+
+    fn replace_n(&self, s: &str, replacement: &str, n: usize) -> String {
+        let mut out = String::with_capacity(s.len());
+        self.replace_append(s, replacement, n, &mut out);
+        out
+    }
+
+    // Appends `s` with its first `n` matches replaced to the end of `out`, eg: to reuse one buffer
+    // across many strings. Returns the number of replacements. The replacement is expanded like in
+    // `replace_all`.
+    pub fn replace_append(&self, s: &str, replacement: &str, n: usize, out: &mut String) -> usize {
+        let mut last_end = 0;
+        let mut count = 0;
+        let has_refs = replacement.contains('$');
+        let clusters = self.clusters(s);
+
+        for found in self.find_iter(s).take(n) {
+            out.push_str(&s[last_end..found.start]);
+            if has_refs {
+                self.captures_with(s, found, &clusters)
+                    .expand(replacement, out);
+            } else {
+                out.push_str(replacement);
+            }
+            last_end = found.end;
+            count += 1;
+        }
+
+        out.push_str(&s[last_end..]);
+        count
+    }
+
+    // Number of matching lines of the reader. Faster than matching the lines one by one: the line
+    // buffer is reused, no spans are built and lines without the required literal are skipped.
+    pub fn count_lines_matching(&self, mut reader: impl BufRead) -> std::io::Result<usize> {
+        let features = self.features();
+        let prefilter = self.pattern.as_ref().and_then(|p| p.required_literal());
+        // Containing the literal is enough only when it's all the pattern matches, eg: not for the
+        // `a` of `a(?:b)c`.
+        let is_whole = features.is_literal
+            && prefilter.as_ref().map(|s| s.chars().count()) == features.max_len;
+        let literal = prefilter.as_deref().map(str::as_bytes);
+        let mut line = vec![];
+        let mut count = 0;
+
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            let content = line_content(&line);
+            let is_match = match literal {
+                Some(literal) if !content.windows(literal.len()).any(|w| w == literal) => false,
+                Some(_) if is_whole && self.max_haystack_len.is_none() => true,
+                _ => self.is_match(&String::from_utf8_lossy(content)),
+            };
+
+            if is_match {
+                count += 1;
+            }
+            line.clear();
+        }
+
+        Ok(count)
+    }
+
+    // Writes the reader's data with every match replaced, like `replace_all` on all of it, without
+    // reading it all: a `StreamMatcher` runs over the chunks and the text up to the last offset no
+    // match attempt spans is written out. Only the text since then is kept, eg: the current run
+    // of `a`s for `(a+)b`, whatever the length of the lines. Invalid UTF-8 reads as U+FFFD.
+    // Returns the number of replacements.
+    pub fn replace_stream(
+        &self,
+        mut reader: impl BufRead,
+        out: &mut impl std::io::Write,
+        replacement: &str,
+    ) -> std::io::Result<usize> {
+        let mut matcher = StreamMatcher::new(self, 0);
+        // The text not written yet, after the char before it so `^` only holds at the start.
+        let mut carry = String::new();
+        let mut carry_offset = 0;
+        let mut from = 0;
+        // Whether a match ended at `from`, where an empty match is then skipped.
+        let mut is_after_match = false;
+        let mut pending = vec![];
+        let mut replaced = String::new();
+        let mut count = 0;
+
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            let mut settled = None;
+            decode_utf8(&mut pending, chunk, |c, _| {
+                carry.push(c);
+                matcher.read_char(c, c.len_utf8());
+                if matcher
+                    .active
+                    .values()
+                    .all(|&start| start >= matcher.offset)
+                {
+                    settled = Some(matcher.offset);
+                }
+                true
+            });
+            let len = chunk.len();
+            reader.consume(len);
+
+            if let Some(settled) = settled {
+                let end = settled - carry_offset;
+                let (replacements, ends_at_end) = self.replace_carried(
+                    &carry[..end],
+                    from,
+                    is_after_match,
+                    false,
+                    replacement,
+                    &mut replaced,
+                );
+                count += replacements;
+                is_after_match = ends_at_end;
+                out.write_all(replaced.as_bytes())?;
+                replaced.clear();
+
+                let kept = carry[..end]
+                    .char_indices()
+                    .next_back()
+                    .map_or(0, |(i, _)| i);
+                carry.drain(..kept);
+                carry_offset += kept;
+                from = end - kept;
+            }
+        }
+
+        if !pending.is_empty() {
+            carry.push(char::REPLACEMENT_CHARACTER);
+        }
+        let (replacements, _) = self.replace_carried(
+            &carry,
+            from,
+            is_after_match,
+            true,
+            replacement,
+            &mut replaced,
+        );
+        out.write_all(replaced.as_bytes())?;
+        Ok(count + replacements)
+    }
+
+    // Appends the carried text of `replace_stream` from `from` with the matches starting there
+    // replaced, up to the end of the input when `is_last`, else up to the end of the carried text.
+    // Returns the number of replacements and whether the last one ended at the end.
+    fn replace_carried(
+        &self,
+        carry: &str,
+        from: usize,
+        is_after_match: bool,
+        is_last: bool,
+        replacement: &str,
+        out: &mut String,
+    ) -> (usize, bool) {
+        let clusters = self.clusters(carry);
+        let matches = FindIter {
+            engine: self,
+            haystack: carry,
+            pos: Some(from),
+            last_end: is_after_match.then_some(from),
+            clusters: self.clusters(carry),
+        };
+        let has_refs = replacement.contains('$');
+        let mut last_end = from;
+        let mut count = 0;
+
+        for found in matches.take_while(|found| is_last || found.start < carry.len()) {
+            out.push_str(&carry[last_end..found.start]);
+            if has_refs {
+                self.captures_with(carry, found, &clusters)
+                    .expand(replacement, out);
+            } else {
+                out.push_str(replacement);
+            }
+            last_end = found.end;
+            count += 1;
+        }
+
+        out.push_str(&carry[last_end..]);
+        (count, count > 0 && last_end == carry.len())
+    }
+
+    pub fn stream_matcher(&self) -> StreamMatcher<'_> {
+        StreamMatcher::new(self, 0)
+    }
+
+    pub fn end_scanner(&self) -> EndScanner<'_> {
+        EndScanner {
+            engine: self,
+            active: HashSet::default(),
+            offset: 0,
+            pending: vec![],
+            started: false,
+        }
+    }
+
+    // Continues the scan of a `StreamMatcher::checkpoint`, eg: taken by another process. Fails
+    // when the checkpoint is of a different automaton, as its states would mean nothing here.
+    pub fn resume(&self, checkpoint: &Checkpoint) -> Result<StreamMatcher<'_>, String> {
+        if checkpoint.fingerprint != self.fingerprint() {
+            return Err("Checkpoint of a different automaton".into());
+        }
+
+        Ok(StreamMatcher {
+            engine: self,
+            active: checkpoint.active.iter().copied().collect(),
+            offset: checkpoint.offset,
+            origin: 0,
+            pending: checkpoint.pending.clone(),
+            found: checkpoint.found,
+        })
+    }
+
+    // FNV-1a hash of the automaton. Stable across processes, as the state numbers only depend on
+    // the pattern.
+    fn fingerprint(&self) -> u64 {
+        let automaton = format!(
+            "{}search {}\nanchored {}\n",
+            self.to_table(),
+            self.search_state,
+            self.anchored
+        );
+        automaton.bytes().fold(0xcbf29ce484222325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    // Byte span of the first match that ends in the reader's data, reading only as much as needed.
+    #[cfg(feature = "tokio")]
+    pub async fn find_stream(
+        &self,
+        reader: impl tokio::io::AsyncRead,
+    ) -> std::io::Result<Option<Span>> {
+        use tokio::io::AsyncReadExt;
+
+        let mut reader = std::pin::pin!(reader);
+        let mut matcher = self.stream_matcher();
+        let mut buf = [0; 8 * 1024];
+
+        let mut found = matcher.feed(&[]);
+        while found.is_none() {
+            let len = reader.read(&mut buf).await?;
+            if len == 0 {
+                return Ok(matcher.finish());
+            }
+            found = matcher.feed(&buf[..len]);
+        }
+
+        Ok(found)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn matching_lines<R: tokio::io::AsyncBufRead + Unpin>(
+        &self,
+        lines: tokio::io::Lines<R>,
+    ) -> crate::lines::MatchingLines<'_, R> {
+        crate::lines::MatchingLines::new(self, lines)
+    }
+
+    fn run(&self, entry_state: State, input: impl Input, to_end: bool) -> bool {
+        if let Some(dfa) = &self.dfa {
+            // Entries in the order of `compile_dfa`.
+            return dfa.run(usize::from(entry_state == self.start_state), input, to_end);
+        }
+        if self.safe_mode {
+            return self.simulate(entry_state, input, to_end).0;
+        }
+        self.run_with(entry_state, input, to_end, |_, _| {})
+    }
+
+    // Matches on the set of active states + the number of state visits.
+    fn simulate(&self, entry_state: State, input: impl Input, to_end: bool) -> (bool, usize) {
+        let mut states = self.closure(vec![entry_state], true, false);
+        let mut steps = states.len();
+        let mut is_start = true;
+
+        for c in input.chars() {
+            if !to_end && states.contains(&self.finish_state) {
+                return (true, steps);
+            }
+
+            states = self.closure(self.step(&states, c), false, false);
+            steps += states.len();
+            is_start = false;
+            if states.is_empty() {
+                return (false, steps);
+            }
+        }
+
+        // The end of the input is only known now.
+        states = self.closure(states.into_iter().collect(), is_start, true);
+        (states.contains(&self.finish_state), steps)
+    }
+
+    fn run_with(
+        &self,
+        entry_state: State,
+        input: impl Input,
+        to_end: bool,
+        on_step: impl FnMut(State, usize),
+    ) -> bool {
+        let mut scratch = if self.pool_scratch {
+            Scratch::take(self.id)
+        } else {
+            Scratch::default()
+        };
+
+        let is_match = self.search(&mut scratch, entry_state, input, to_end, on_step);
+
+        if self.pool_scratch {
+            scratch.give_back(self.id);
+        }
+        is_match
+    }
+
+    fn search(
+        &self,
+        scratch: &mut Scratch,
+        entry_state: State,
+        input: impl Input,
+        to_end: bool,
+        mut on_step: impl FnMut(State, usize),
+    ) -> bool {
+        let Scratch {
+            stack,
+            visited,
+            chars,
+            grapheme_ends,
+        } = scratch;
+        stack.push((entry_state, 0));
+        chars.extend(input.chars());
+
+        #[cfg(feature = "grapheme")]
+        if self.graphemes {
+            use unicode_segmentation::UnicodeSegmentation;
+
+            let s = chars.iter().collect::<String>();
+            for grapheme in s.graphemes(true) {
+                let end = grapheme_ends.len() + grapheme.chars().count();
+                grapheme_ends.resize(end, end);
+            }
+        }
+
+        while let Some((state, i)) = stack.pop() {
+            // Epsilon loops (eg: `(a*)*`) lead back to visited pairs, which can't match anymore.
+            if !visited.insert((state, i)) {
+                continue;
+            }
+            on_step(state, i);
+
+            if state == self.finish_state && (!to_end || i >= chars.len()) {
+                return true;
+            }
+
+            let wildcard_end = grapheme_ends.get(i).copied().unwrap_or(i + 1);
+            let mut new_states =
+                self.transitions
+                    .states_from_with(state, chars.get(i), i, i + 1, wildcard_end);
+            stack.append(&mut new_states);
+            for anchor in [Anchor::Start, Anchor::End] {
+                if anchor.holds(i == 0, i >= chars.len()) {
+                    let to_states = self.transitions.anchors.get(&(state, anchor));
+                    stack.extend(to_states.into_iter().flatten().map(|&to| (to, i)));
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn dump_table(&self) {
+        print!("{}", self.to_table());
+    }
+
+    // The automaton of the full match in the `from_table` format, sorted by state.
+    pub fn to_table(&self) -> String {
+        let mut out = format!("start {}\naccept {}\n", self.start_state, self.finish_state);
+        for (from, label, to) in self.transitions.edges() {
+            if from != self.search_state {
+                writeln!(out, "{} {} {}", from, label, to).unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn dump_dot(&self) {
+        print!("{}", self.to_dot());
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, options: &DotOptions) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "digraph {{").unwrap();
+        if let Some(rankdir) = &options.rankdir {
+            writeln!(out, "\trankdir={}", rankdir.as_str()).unwrap();
+        }
+        writeln!(out, "\tStart [color=\"blue\"]").unwrap();
+        writeln!(out, "\tFinish [color=\"orange\"]").unwrap();
+        writeln!(out, "\tSearch [color=\"gray\"]").unwrap();
+
+        let start = self.start_state;
+        let finish = self.finish_state;
+        let search = self.search_state;
+        let to_label = |s: State| {
+            if s == start {
+                "Start".into()
+            } else if s == finish {
+                "Finish".into()
+            } else if s == search {
+                "Search".into()
+            } else {
+                format!("S{}", s)
+            }
+        };
+
+        let (edges, accepting) = if options.collapse_epsilon {
+            self.dot_edges_without_epsilon()
+        } else {
+            (self.dot_edges(), vec![])
+        };
+
+        for state in accepting {
+            writeln!(out, "\t{} [shape=\"doublecircle\"]", to_label(state)).unwrap();
+        }
+
+        if !options.show_state_ids {
+            let states = edges
+                .iter()
+                .flat_map(|(from, to, _, _)| [*from, *to])
+                .filter(|s| *s != start && *s != finish && *s != search)
+                .collect::<HashSet<_>>();
+            for state in states {
+                writeln!(out, "\t{} [label=\"\",shape=\"point\"]", to_label(state)).unwrap();
+            }
+        }
+
+        let path = options.highlight_path.as_deref().unwrap_or(&[]);
+        let path_states = path.iter().map(|(s, _)| *s).collect::<HashSet<_>>();
+        let path_edges = path
+            .windows(2)
+            .map(|w| (w[0].0, w[1].0))
+            .collect::<HashSet<_>>();
+        for state in path_states {
+            writeln!(
+                out,
+                "\t{} [style=\"filled\",fillcolor=\"yellow\"]",
+                to_label(state)
+            )
+            .unwrap();
+        }
+
+        for (from, to, label, color) in edges {
+            writeln!(
+                out,
+                "\t{} -> {}[label=\"{}\",color=\"{}\"{}]",
+                to_label(from),
+                to_label(to),
+                label,
+                color,
+                if path_edges.contains(&(from, to)) {
+                    ",penwidth=3"
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "}}").unwrap();
+
+        out
+    }
+
+    // JFLAP (.jff) finite automaton of the full match. JFLAP has no wildcards, so `.` and negated
+    // char groups are expanded over the given alphabet. It has no anchors either, they become
+    // epsilon moves.
+    pub fn to_jflap(&self, alphabet: &[char]) -> String {
+        let mut transitions: Vec<(State, State, Option<char>)> = vec![];
+
+        for ((from, c), to_states) in &self.transitions.base {
+            if *from == self.search_state {
+                continue;
+            }
+
+            for to in to_states {
+                transitions.push((*from, *to, *c));
+            }
+        }
+
+        for ((from, _), to_states) in &self.transitions.anchors {
+            transitions.extend(to_states.iter().map(|to| (*from, *to, None)));
+        }
+
+        for (from, submap) in &self.transitions.classes {
+            if *from == self.search_state {
+                continue;
+            }
+
+            for (class, to_states) in submap {
+                for to in to_states {
+                    transitions.extend(
+                        alphabet
+                            .iter()
+                            .filter(|a| ClassRange::any_contains(class, **a))
+                            .map(|a| (*from, *to, Some(*a))),
+                    );
+                }
+            }
+        }
+
+        for (from, to_states) in &self.transitions.wildcard {
+            if *from == self.search_state {
+                continue;
+            }
+
+            for to in to_states {
+                transitions.extend(alphabet.iter().map(|a| (*from, *to, Some(*a))));
+            }
+        }
+        transitions.sort();
+
+        let mut states = transitions
+            .iter()
+            .flat_map(|(from, to, _)| [*from, *to])
+            .chain([self.start_state, self.finish_state])
+            .collect::<Vec<_>>();
+        states.sort();
+        states.dedup();
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+        )
+        .unwrap();
+        writeln!(out, "<structure>").unwrap();
+        writeln!(out, "\t<type>fa</type>").unwrap();
+        writeln!(out, "\t<automaton>").unwrap();
+
+        for state in states {
+            writeln!(out, "\t\t<state id=\"{}\" name=\"q{}\">", state, state).unwrap();
+            writeln!(out, "\t\t\t<x>{}</x>", 100 + (state % 10) * 100).unwrap();
+            writeln!(out, "\t\t\t<y>{}</y>", 100 + (state / 10) * 100).unwrap();
+            if state == self.start_state {
+                writeln!(out, "\t\t\t<initial/>").unwrap();
+            }
+            if state == self.finish_state {
+                writeln!(out, "\t\t\t<final/>").unwrap();
+            }
+            writeln!(out, "\t\t</state>").unwrap();
+        }
+
+        for (from, to, c) in transitions {
+            writeln!(out, "\t\t<transition>").unwrap();
+            writeln!(out, "\t\t\t<from>{}</from>", from).unwrap();
+            writeln!(out, "\t\t\t<to>{}</to>", to).unwrap();
+            match c {
+                Some(c) => writeln!(out, "\t\t\t<read>{}</read>", xml_escape(c)).unwrap(),
+                None => writeln!(out, "\t\t\t<read/>").unwrap(),
+            }
+            writeln!(out, "\t\t</transition>").unwrap();
+        }
+
+        writeln!(out, "\t</automaton>").unwrap();
+        writeln!(out, "</structure>").unwrap();
+
+        out
+    }
+
+    fn dot_edges(&self) -> Vec<DotEdge> {
+        let mut edges = vec![];
+
+        for (k, vs) in &self.transitions.base {
+            for v in vs {
+                // Epsilon moves into capture group boundaries show the group in blue, eg: `(1`
+                // and `1)`.
+                let (label, color) = match (k.1, self.transitions.tags.get(v)) {
+                    (Some('.'), _) => ("\\\\.".into(), "black"),
+                    (Some(c), _) => (c.to_string(), "black"),
+                    (None, Some(Tag::Open(group))) => (format!("({}", group), "blue"),
+                    (None, Some(Tag::Close(group))) => (format!("{})", group), "blue"),
+                    (None, None) => (" ".into(), "green"),
+                };
+                edges.push((k.0, *v, label, color));
+            }
+        }
+
+        for (from_state, submap) in &self.transitions.classes {
+            for (class, to_states) in submap {
+                let label = ClassRange::to_pattern(class)
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                for to_state in to_states {
+                    edges.push((*from_state, *to_state, label.clone(), "purple"));
+                }
+            }
+        }
+
+        for (from_state, to_states) in &self.transitions.wildcard {
+            for to_state in to_states {
+                edges.push((*from_state, *to_state, ".".into(), "black"));
+            }
+        }
+
+        for ((from_state, anchor), to_states) in &self.transitions.anchors {
+            for to_state in to_states {
+                edges.push((*from_state, *to_state, anchor.to_pattern().into(), "red"));
+            }
+        }
+
+        edges
+    }
+
+    // Edges where epsilon moves are folded into the char edges following them + the states
+    // accepting through epsilon moves.
+    fn dot_edges_without_epsilon(&self) -> (Vec<DotEdge>, Vec<State>) {
+        let char_edges = self
+            .dot_edges()
+            .into_iter()
+            .filter(|(_, _, _, color)| !matches!(*color, "green" | "blue"))
+            .collect::<Vec<_>>();
+
+        let mut edges = vec![];
+        let mut accepting = vec![];
+        let mut seen = HashSet::from_iter([self.start_state, self.search_state]);
+        let mut todo = vec![self.start_state, self.search_state];
+
+        while let Some(state) = todo.pop() {
+            let closure = self.transitions.epsilon_closure(state);
+            if state != self.finish_state && closure.contains(&self.finish_state) {
+                accepting.push(state);
+            }
+
+            for (from, to, label, color) in &char_edges {
+                let edge = (state, *to, label.clone(), *color);
+                if closure.contains(from) && !edges.contains(&edge) {
+                    edges.push(edge);
+                    if seen.insert(*to) {
+                        todo.push(*to);
+                    }
+                }
+            }
+        }
+
+        (edges, accepting)
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        // Views leave the pattern to the last engine sharing it.
+        if let Some(pattern) = self.pattern.take().and_then(Arc::into_inner) {
+            pattern.drop_flat();
+        }
+    }
+}
+
+pub struct FindIter<'e, 'h> {
+    engine: &'e Engine,
+    haystack: &'h str,
+    // Where the next search starts, None past the end.
+    pos: Option<usize>,
+    last_end: Option<usize>,
+    clusters: Clusters,
+}
+
+impl<'h> Iterator for FindIter<'_, 'h> {
+    type Item = Match<'h>;
+
+    fn next(&mut self) -> Option<Match<'h>> {
+        loop {
+            let (start, end) =
+                self.engine
+                    .leftmost_first(self.haystack, self.pos?, &self.clusters)?;
+            self.pos = next_search(self.haystack, (start, end));
+
+            if start == end && self.last_end == Some(start) {
+                continue;
+            }
+            self.last_end = Some(end);
+            return Some(Match {
+                start,
+                end,
+                slice: &self.haystack[start..end],
+            });
+        }
+    }
+}
+
+pub struct Split<'e, 'h> {
+    matches: FindIter<'e, 'h>,
+    // Start of the next substring, None when done.
+    last: Option<usize>,
+    // Substrings left to yield of `splitn`.
+    limit: Option<usize>,
+}
+
+impl<'h> Iterator for Split<'_, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        let start = self.last?;
+        let haystack = self.matches.haystack;
+
+        match self.limit.as_mut() {
+            Some(0) => {
+                self.last = None;
+                return None;
+            }
+            Some(1) => {
+                self.last = None;
+                return Some(&haystack[start..]);
+            }
+            Some(limit) => *limit -= 1,
+            None => {}
+        }
+
+        match self.matches.next() {
+            Some(m) => {
+                self.last = Some(m.end);
+                Some(&haystack[start..m.start])
+            }
+            None => {
+                self.last = None;
+                Some(&haystack[start..])
+            }
+        }
+    }
+}
+
+pub struct StreamMatcher<'a> {
+    engine: &'a Engine,
+    // Active states with the byte offset where their match attempt started.
+    active: HashMap<State, usize>,
+    // Bytes consumed so far.
+    offset: usize,
+    // Offset the scan started at, where anchored match attempts start.
+    origin: usize,
+    // Incomplete UTF-8 sequence at the end of the last chunk.
+    pending: Vec<u8>,
+    found: Option<Span>,
+}
+
+impl<'a> StreamMatcher<'a> {
+    // Scans from the offset of the input, `^` only holds at offset 0.
+    fn new(engine: &'a Engine, origin: usize) -> StreamMatcher<'a> {
+        let mut matcher = StreamMatcher {
+            engine,
+            active: HashMap::default(),
+            offset: origin,
+            origin,
+            pending: vec![],
+            found: None,
+        };
+        matcher.start_attempt();
+        matcher
+    }
+
+    // Feeds the next chunk and returns the byte span of the first match that ended so far. Chars
+    // may be split between chunks, invalid UTF-8 reads as U+FFFD.
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<Span> {
+        if self.found.is_some() {
+            return self.found;
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        decode_utf8(&mut pending, chunk, |c, len| {
+            self.read_char(c, len);
+            self.found.is_none()
+        });
+        self.pending = pending;
+        self.found
+    }
+
+    // Ends the input and returns the byte span of the first match, which may be one ending with
+    // `$`. An incomplete char left by the last chunk reads as U+FFFD.
+    pub fn finish(&mut self) -> Option<Span> {
+        if self.found.is_none() && !self.pending.is_empty() {
+            let len = std::mem::take(&mut self.pending).len();
+            self.read_char(char::REPLACEMENT_CHARACTER, len);
+        }
+        if self.found.is_none() {
+            self.follow_epsilon_moves(true);
+        }
+        self.found
+    }
+
+    // Everything needed to continue the scan later with `Engine::resume`, see
+    // `Checkpoint::to_bytes`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut active = self
+            .active
+            .iter()
+            .map(|(&state, &start)| (state, start))
+            .collect::<Vec<_>>();
+        active.sort_unstable();
+
+        Checkpoint {
+            fingerprint: self.engine.fingerprint(),
+            active,
+            offset: self.offset,
+            pending: self.pending.clone(),
+            found: self.found,
+        }
+    }
+
+    fn read_char(&mut self, c: char, len: usize) {
+        let mut next: HashMap<State, usize> = HashMap::default();
+        for (&state, &start) in &self.active {
+            for (new_state, i) in self.engine.transitions.states_from(state, Some(&c), 0) {
+                // Epsilon moves are followed by the closure already.
+                if i == 1 {
+                    let next_start = next.entry(new_state).or_insert(start);
+                    *next_start = start.min(*next_start);
+                }
+            }
+        }
+
+        self.active = next;
+        self.offset += len;
+        self.start_attempt();
+    }
+
+    // Starts a new match attempt at the current offset and follows the epsilon moves.
+    fn start_attempt(&mut self) {
+        if !self.engine.anchored || self.offset == self.origin {
+            self.active
+                .entry(self.engine.start_state)
+                .or_insert(self.offset);
+        }
+        self.follow_epsilon_moves(false);
+    }
+
+    // Follows the epsilon moves and the anchors holding at the current offset, eg: `$` once the
+    // input ended.
+    fn follow_epsilon_moves(&mut self, is_end: bool) {
+        let mut todo = self
+            .active
+            .iter()
+            .map(|(&k, &v)| (k, v))
+            .collect::<Vec<_>>();
+        while let Some((state, start)) = todo.pop() {
+            for new_state in self
+                .engine
+                .transitions
+                .epsilon_moves(state, self.offset == 0, is_end)
+            {
+                let new_start = self.active.entry(new_state).or_insert(usize::MAX);
+                if start < *new_start {
+                    *new_start = start;
+                    todo.push((new_state, start));
+                }
+            }
+        }
+
+        if let Some(&start) = self.active.get(&self.engine.finish_state) {
+            self.found = Some((start, self.offset));
+        }
+    }
+}
+
+// Reports the end of every match while scanning a stream, as soon as the match ends, eg: for
+// intrusion detection. Unlike `StreamMatcher` it never stops, and keeps no start offsets.
+pub struct EndScanner<'a> {
+    engine: &'a Engine,
+    active: HashSet<State>,
+    // Bytes consumed so far.
+    offset: usize,
+    // Incomplete UTF-8 sequence at the end of the last chunk.
+    pending: Vec<u8>,
+    // Whether the empty prefix was checked for a match, by the first feed.
+    started: bool,
+}
+
+impl<'a> EndScanner<'a> {
+    // Feeds the next chunk and calls back with the byte offset of each match end in it, once per
+    // offset, in order. Chars may be split between chunks, invalid UTF-8 reads as U+FFFD.
+    pub fn feed(&mut self, chunk: &[u8], mut on_end: impl FnMut(usize)) {
+        if !self.started {
+            self.started = true;
+            self.start_attempt(&mut on_end);
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        decode_utf8(&mut pending, chunk, |c, len| {
+            self.read_char(c, len);
+            self.start_attempt(&mut on_end);
+            true
+        });
+        self.pending = pending;
+    }
+
+    // Ends the input and calls back with the byte offset of a match ending with it through `$`,
+    // unless reported already. An incomplete char left by the last chunk reads as U+FFFD.
+    pub fn finish(&mut self, mut on_end: impl FnMut(usize)) {
+        self.feed(&[], &mut on_end);
+        if !self.pending.is_empty() {
+            let len = std::mem::take(&mut self.pending).len();
+            self.read_char(char::REPLACEMENT_CHARACTER, len);
+            self.start_attempt(&mut on_end);
+        }
+
+        if !self.active.contains(&self.engine.finish_state) {
+            self.follow_epsilon_moves(true);
+            if self.active.contains(&self.engine.finish_state) {
+                on_end(self.offset);
+            }
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn read_char(&mut self, c: char, len: usize) {
+        let mut next = HashSet::default();
+        for &state in &self.active {
+            for (new_state, i) in self.engine.transitions.states_from(state, Some(&c), 0) {
+                // Epsilon moves are followed by the closure already.
+                if i == 1 {
+                    next.insert(new_state);
+                }
+            }
+        }
+
+        self.active = next;
+        self.offset += len;
+    }
+
+    // Starts a new match attempt at the current offset, follows the epsilon moves and reports a
+    // match ending here.
+    fn start_attempt(&mut self, on_end: &mut impl FnMut(usize)) {
+        if !self.engine.anchored || self.offset == 0 {
+            self.active.insert(self.engine.start_state);
+        }
+        self.follow_epsilon_moves(false);
+
+        if self.active.contains(&self.engine.finish_state) {
+            on_end(self.offset);
+        }
+    }
+
+    // Follows the epsilon moves and the anchors holding at the current offset.
+    fn follow_epsilon_moves(&mut self, is_end: bool) {
+        let mut todo = self.active.iter().copied().collect::<Vec<_>>();
+        while let Some(state) = todo.pop() {
+            for new_state in self
+                .engine
+                .transitions
+                .epsilon_moves(state, self.offset == 0, is_end)
+            {
+                if self.active.insert(new_state) {
+                    todo.push(new_state);
+                }
+            }
+        }
+    }
+}
+
+// Decodes the chunk after the incomplete char left in `pending` by the previous chunk, calling back
+// with each char and its byte length until the callback returns false. Invalid UTF-8 reads as
+// U+FFFD. The incomplete char at the end of the chunk is left in `pending`.
+fn decode_utf8(pending: &mut Vec<u8>, chunk: &[u8], mut on_char: impl FnMut(char, usize) -> bool) {
+    let mut bytes = std::mem::take(pending);
+    bytes.extend_from_slice(chunk);
+
+    let mut consumed = 0;
+    while consumed < bytes.len() {
+        let (valid, invalid_len) = match std::str::from_utf8(&bytes[consumed..]) {
+            Ok(s) => (s.len(), None),
+            Err(err) => (err.valid_up_to(), err.error_len()),
+        };
+        let s = std::str::from_utf8(&bytes[consumed..consumed + valid]).unwrap();
+        for c in s.chars() {
+            if !on_char(c, c.len_utf8()) {
+                return;
+            }
+        }
+        consumed += valid;
+
+        match invalid_len {
+            Some(len) => {
+                if !on_char(char::REPLACEMENT_CHARACTER, len) {
+                    return;
+                }
+                consumed += len;
+            }
+            // The rest of the char is in the next chunk.
+            None if consumed < bytes.len() => break,
+            None => {}
+        }
+    }
+
+    bytes.drain(..consumed);
+    *pending = bytes;
+}
+
+// Chars read by an edge, see `Engine::complexity_estimate`.
+#[derive(Debug, PartialEq, Clone)]
+enum CharLabel {
+    Char(char),
+    // Any char of the ranges.
+    Class(CharClass),
+    Any,
+}
+
+impl CharLabel {
+    // Whether a char is read by all the edges.
+    fn overlap(labels: &[&CharLabel]) -> bool {
+        let mut chars = labels.iter().filter_map(|label| match label {
+            CharLabel::Char(c) => Some(*c),
+            _ => None,
+        });
+        let Some(c) = chars.next() else {
+            // Classes overlap only where their ranges do.
+            return ClassRange::overlap(labels.iter().filter_map(|label| match label {
+                CharLabel::Class(class) => Some(&class[..]),
+                _ => None,
+            }));
+        };
+
+        chars.all(|other| other == c)
+            && labels.iter().all(|label| match label {
+                CharLabel::Class(class) => ClassRange::any_contains(class, c),
+                _ => true,
+            })
+    }
+}
+
+// The line without its line break, `\n` or `\r\n`, eg: `$` holds before the `\r` of CRLF input.
+pub(crate) fn line_content(line: &[u8]) -> &[u8] {
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    content.strip_suffix(b"\r").unwrap_or(content)
+}
+
+// Where the search after a match starts, one char past an empty match so it is not found again.
+fn next_search(haystack: &str, (start, end): Span) -> Option<usize> {
+    if start < end {
+        Some(end)
+    } else {
+        haystack[end..].chars().next().map(|c| end + c.len_utf8())
+    }
+}
+
+// Whether the captures are preferred over the other ones: group by group, the earlier start wins,
+// then the later end, and a captured group wins over a missing one.
+fn is_preferred_capture(slots: &[Option<usize>], other: &[Option<usize>]) -> bool {
+    for (i, (slot, other_slot)) in slots.iter().zip(other).enumerate() {
+        let is_start = i % 2 == 0;
+        match (slot, other_slot) {
+            (Some(a), Some(b)) if a != b => return (a < b) == is_start,
+            (Some(_), None) => return true,
+            (None, Some(_)) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+// Number of epsilon paths (and anchors, which may hold) from the state to each state of its
+// closure, 2 standing for more, see `Engine::complexity_estimate`. The states after an epsilon
+// loop, eg: of `(a*)*`, have 2.
+fn epsilon_path_counts(transitions: &Transition, state: State) -> HashMap<State, usize> {
+    let mut counts = HashMap::from_iter([(state, 1)]);
+    let mut todo = vec![state];
+    while let Some(from) = todo.pop() {
+        let count = counts[&from];
+        for to in transitions.epsilon_moves(from, true, true) {
+            let to_count = counts.entry(to).or_insert(0);
+            let new_count = (*to_count + count).min(2);
+            if new_count != *to_count {
+                *to_count = new_count;
+                todo.push(to);
+            }
+        }
+    }
+    counts
+}
+
+// Nodes reachable from the node (itself included).
+fn reachable<N: Clone + Eq + std::hash::Hash>(
+    from: N,
+    successors: impl Fn(&N) -> Vec<N>,
+) -> HashSet<N> {
+    let mut seen = HashSet::from_iter([from.clone()]);
+    let mut todo = vec![from];
+    while let Some(node) = todo.pop() {
+        for next in successors(&node) {
+            if seen.insert(next.clone()) {
+                todo.push(next);
+            }
+        }
+    }
+    seen
+}
+
+fn xml_escape(c: char) -> String {
+    match c {
+        '&' => "&amp;".into(),
+        '<' => "&lt;".into(),
+        '>' => "&gt;".into(),
+        '"' => "&quot;".into(),
+        '\'' => "&apos;".into(),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::*;
+
+    #[test]
+    fn test_empty() {
+        assert!(Engine::new("").unwrap().is_full_match(""));
+        assert!(!Engine::new("").unwrap().is_full_match("a"));
+        assert!(!Engine::new("").unwrap().is_full_match("abc"));
+    }
+
+    #[test]
+    fn test_paren() {
+        assert!(Engine::new("a(a)a").unwrap().is_full_match("aaa"));
+        assert!(Engine::new("aa(a)").unwrap().is_full_match("aaa"));
+        assert!(Engine::new("(aa)a").unwrap().is_full_match("aaa"));
+
+        assert!(!Engine::new("a(a)a").unwrap().is_full_match("aaaa"));
+        assert!(!Engine::new("aa(a)").unwrap().is_full_match("aaaa"));
+        assert!(!Engine::new("(aa)a").unwrap().is_full_match("aaaa"));
+
+        assert!(!Engine::new("a(a)a").unwrap().is_full_match("aa"));
+        assert!(!Engine::new("aa(a)").unwrap().is_full_match("aa"));
+        assert!(!Engine::new("(aa)a").unwrap().is_full_match("aa"));
+    }
+
+    #[test]
+    fn test_or() {
+        assert!(Engine::new("a|b").unwrap().is_full_match("a"));
+        assert!(Engine::new("a|b").unwrap().is_full_match("b"));
+
+        assert!(!Engine::new("a|b").unwrap().is_full_match("ba"));
+        assert!(!Engine::new("a|b").unwrap().is_full_match("ab"));
+        assert!(!Engine::new("a|b").unwrap().is_full_match(""));
+    }
+
+    #[test]
+    fn test_mod_any() {
+        assert!(Engine::new("a*").unwrap().is_full_match(""));
+        assert!(Engine::new("a*").unwrap().is_full_match("a"));
+        assert!(Engine::new("a*")
+            .unwrap()
+            .is_full_match("aaaaaaaaaaaaaaaaaaaaaa"));
+
+        assert!(!Engine::new("a*").unwrap().is_full_match("aaaab"));
+
+        assert!(Engine::new("(aaa)*").unwrap().is_full_match(""));
+        assert!(Engine::new("(aaa)*").unwrap().is_full_match("aaa"));
+        assert!(Engine::new("(aaa)*").unwrap().is_full_match("aaaaaa"));
+
+        assert!(!Engine::new("(aaa)*").unwrap().is_full_match("a"));
+        assert!(!Engine::new("(aaa)*").unwrap().is_full_match("aa"));
+    }
+
+    #[test]
+    fn test_mod_one_or_more() {
+        assert!(Engine::new("a+").unwrap().is_full_match("a"));
+        assert!(Engine::new("a+").unwrap().is_full_match("aaaa"));
+
+        assert!(!Engine::new("a+").unwrap().is_full_match(""));
+        assert!(!Engine::new("a+").unwrap().is_full_match("b"));
+        assert!(!Engine::new("a+").unwrap().is_full_match("aab"));
+
+        assert!(Engine::new("(aaa)+").unwrap().is_full_match("aaa"));
+        assert!(Engine::new("(aaa)+").unwrap().is_full_match("aaaaaaaaa"));
+
+        assert!(!Engine::new("(aaa)+").unwrap().is_full_match("aa"));
+        assert!(!Engine::new("(aaa)+").unwrap().is_full_match("aab"));
+    }
+
+    #[test]
+    fn test_mod_zero_or_one() {
+        assert!(Engine::new("a?").unwrap().is_full_match(""));
+        assert!(Engine::new("a?").unwrap().is_full_match("a"));
+
+        assert!(!Engine::new("a?").unwrap().is_full_match("aaa"));
+        assert!(!Engine::new("a?").unwrap().is_full_match("b"));
+
+        assert!(Engine::new("(aaa)?").unwrap().is_full_match(""));
+        assert!(Engine::new("(aaa)?").unwrap().is_full_match("aaa"));
+
+        assert!(!Engine::new("(aaa)?").unwrap().is_full_match("a"));
+        assert!(!Engine::new("(aaa)?").unwrap().is_full_match("aa"));
+        assert!(!Engine::new("(aaa)?").unwrap().is_full_match("aab"));
+    }
+
+    #[test]
+    fn test_epsilon_loop() {
+        assert!(Engine::new("(a*)*").unwrap().is_full_match("aa"));
+        assert!(Engine::new("(a?b*)+c").unwrap().is_full_match("abbac"));
+
+        assert!(!Engine::new("(a*)*").unwrap().is_full_match("ab"));
+        assert!(!Engine::new("(a?b*)+c").unwrap().is_full_match("abba"));
+    }
+
+    #[test]
+    fn test_complex() {
+        assert!(Engine::new("cc?|cc").unwrap().is_full_match("c"));
+
+        assert!(Engine::new("abc|abd|ab").unwrap().is_full_match("abd"));
+        assert!(Engine::new("abc|abd|ab").unwrap().is_full_match("ab"));
+        assert!(!Engine::new("abc|abd|ab").unwrap().is_full_match("a"));
+
+        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?")
+            .unwrap()
+            .is_full_match(""));
+        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?")
+            .unwrap()
+            .is_full_match("aaa"));
+        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?")
+            .unwrap()
+            .is_full_match("ac"));
+        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?")
+            .unwrap()
+            .is_full_match("acc"));
+        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?")
+            .unwrap()
+            .is_full_match("acdddddc"));
+    }
+
+    #[test]
+    fn test_alternation_mods() {
+        // Modifiers bind to the last atom, alternation binds loosest.
+        let strings = [
+            "", "a", "b", "c", "d", "ab", "ac", "ba", "bb", "bc", "bd", "aa", "abab", "abc", "cc",
+            "aab", "bcb",
+        ];
+        let cases: [(&str, &[&str]); 16] = [
+            ("a|b|c?", &["", "a", "b", "c"]),
+            ("a|b?", &["", "a", "b"]),
+            ("a?|b", &["", "a", "b"]),
+            ("(a|b)?c|d", &["c", "d", "ac", "bc"]),
+            ("a|b+", &["a", "b", "bb"]),
+            ("a+|b", &["a", "b", "aa"]),
+            ("a|b*c", &["a", "c", "bc"]),
+            ("a+b+", &["ab", "aab"]),
+            (
+                "(a|b)+c?",
+                &[
+                    "a", "b", "ab", "ac", "ba", "bb", "bc", "aa", "abab", "abc", "aab",
+                ],
+            ),
+            ("ab|c*", &["", "c", "ab", "cc"]),
+            ("(a+)?", &["", "a", "aa"]),
+            ("(a?)+b", &["b", "ab", "aab"]),
+            ("(a{2})?", &["", "aa"]),
+            ("a{2}|b+", &["b", "bb", "aa"]),
+            ("(ab)+|c", &["c", "ab", "abab"]),
+            ("a|(b|c)*", &["", "a", "b", "c", "bb", "bc", "cc", "bcb"]),
+        ];
+
+        for (pattern, matching) in cases {
+            let engine = Engine::new(pattern).unwrap();
+            for s in strings {
+                assert_eq!(
+                    matching.contains(&s),
+                    engine.is_full_match(s),
+                    "{} {:?}",
+                    pattern,
+                    s
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_mods_after_groups_and_classes() {
+        // Each repetition of a range gets its own copy of the group or class automaton.
+        let strings = [
+            "", "a", "b", "c", "d", "ab", "ba", "abb", "abab", "abc", "bcbc", "abcd", "bcd", "aab",
+            "aaab", "aaaa", "aabb", "aaac", "cd", "xy", "xaby", "xabay", "xababy",
+        ];
+        let cases: [(&str, &[&str]); 14] = [
+            ("[ab]{2,3}", &["ab", "ba", "abb", "aab"]),
+            ("(ab){2}", &["abab"]),
+            ("(a|bc){1,2}", &["a", "abc", "bcbc"]),
+            ("(a|bc){0,2}d", &["d", "abcd", "bcd"]),
+            ("[^a]{2}", &["cd", "xy"]),
+            ("(a+|b){2}", &["ab", "ba", "aab", "aaab", "aaaa"]),
+            ("(a*|b){2,3}c", &["c", "abc", "aaac"]),
+            ("(ab|a){2}b", &["abab", "aab", "aabb"]),
+            ("(a{2}|b){2}", &["aab", "aaaa"]),
+            ("x(a|b){1,3}y", &["xaby", "xabay"]),
+            ("[ab]*c", &["c", "abc", "aaac"]),
+            ("[ab]?c", &["c"]),
+            ("(ab)?c", &["c", "abc"]),
+            (
+                "(a|b)+",
+                &[
+                    "a", "b", "ab", "ba", "abb", "abab", "aab", "aaab", "aaaa", "aabb",
+                ],
+            ),
+        ];
+
+        for (pattern, matching) in cases {
+            let engine = Engine::new(pattern).unwrap();
+            for s in strings {
+                assert_eq!(
+                    matching.contains(&s),
+                    engine.is_full_match(s),
+                    "{} {:?}",
+                    pattern,
+                    s
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_optional_groups_ending_in_loops() {
+        // Skipping the group must not land in its trailing loop, tag states don't hide it here.
+        let strings = [
+            "", "a", "b", "c", "ab", "abb", "bbb", "bc", "cb", "ca", "cc", "cab", "cac", "cbbb",
+            "acac", "abbc", "a1",
+        ];
+        let cases: [(&str, &[&str]); 6] = [
+            ("(?:ab*)?", &["", "a", "ab", "abb"]),
+            ("(?:ab+)?", &["", "ab", "abb"]),
+            ("c(?:ab*)?", &["c", "ca", "cab"]),
+            ("(?:x.*)?|b", &["", "b"]),
+            ("(?:ab*){0,2}", &["", "a", "ab", "abb"]),
+            ("(?:(?:ab*)?c)*", &["", "c", "cc", "cac", "acac", "abbc"]),
+        ];
+
+        for (pattern, matching) in cases {
+            let engine = EngineBuilder::new(pattern).captures(false).build().unwrap();
+            for s in strings {
+                assert_eq!(
+                    matching.contains(&s),
+                    engine.is_full_match(s),
+                    "{} {:?}",
+                    pattern,
+                    s
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_char_group() {
+        assert!(Engine::new("ab[cd]").unwrap().is_full_match("abc"));
+        assert!(Engine::new("ab[cd]").unwrap().is_full_match("abd"));
+
+        assert!(!Engine::new("ab[cd]").unwrap().is_full_match("abe"));
+        assert!(!Engine::new("ab[cd]").unwrap().is_full_match("abcd"));
+
+        assert!(Engine::new("ab[cd]*").unwrap().is_full_match("ab"));
+        assert!(Engine::new("ab[cd]*").unwrap().is_full_match("abc"));
+        assert!(Engine::new("ab[cd]*").unwrap().is_full_match("abccccc"));
+        assert!(Engine::new("ab[cd]*").unwrap().is_full_match("abddccdccc"));
+
+        assert!(!Engine::new("ab[cd]*").unwrap().is_full_match("abddccdcccr"));
+    }
+
+    #[test]
+    fn test_negated_char_group() {
+        assert!(Engine::new("a[^bc]d").unwrap().is_full_match("aed"));
+        assert!(Engine::new("a[^bc]d").unwrap().is_full_match("aad"));
+        assert!(Engine::new("a[^bc]d").unwrap().is_full_match("add"));
+
+        assert!(!Engine::new("a[^bc]d").unwrap().is_full_match("abd"));
+        assert!(!Engine::new("a[^bc]d").unwrap().is_full_match("acd"));
+        assert!(!Engine::new("a[^bc]d").unwrap().is_full_match("ad"));
+    }
+
+    #[test]
+    fn test_mod_range() {
+        assert!(Engine::new("ab{3}c").unwrap().is_full_match("abbbc"));
+
+        assert!(!Engine::new("ab{3}c").unwrap().is_full_match("abbc"));
+        assert!(!Engine::new("ab{3}c").unwrap().is_full_match("abbbbc"));
+
+        assert!(Engine::new("ab{1,3}c").unwrap().is_full_match("abc"));
+        assert!(Engine::new("ab{1,3}c").unwrap().is_full_match("abbc"));
+        assert!(Engine::new("ab{1,3}c").unwrap().is_full_match("abbbc"));
+
+        assert!(!Engine::new("ab{1,3}c").unwrap().is_full_match("ac"));
+        assert!(!Engine::new("ab{1,3}c").unwrap().is_full_match("abbbbc"));
+
+        assert!(Engine::new("ab{2,}c").unwrap().is_full_match("abbc"));
+        assert!(Engine::new("ab{2,}c").unwrap().is_full_match("abbbbbc"));
+        assert!(!Engine::new("ab{2,}c").unwrap().is_full_match("abc"));
+        assert!(Engine::new("ab{0,}c").unwrap().is_full_match("ac"));
+
+        // Lazy quantifiers match the same strings, `find` and `find_iter` repeat them as few times
+        // as they can.
+        assert!(Engine::new("ab+?c").unwrap().is_full_match("abbc"));
+        assert_eq!("x##bbx", Engine::new("ab+?").unwrap().redact("xabbbx", '#'));
+        assert_eq!(
+            Some("ab"),
+            Engine::new("ab+?").unwrap().find("xabbbx").map(|m| m.slice)
+        );
+        assert_eq!(
+            Some("abbb"),
+            Engine::new("ab+?")
+                .unwrap()
+                .find_longest("xabbbx")
+                .map(|m| m.slice)
+        );
+    }
+
+    #[test]
+    fn test_is_match_unanchored() {
+        assert!(Engine::new("").unwrap().is_match(""));
+        assert!(Engine::new("").unwrap().is_match("abc"));
+
+        assert!(Engine::new("bc").unwrap().is_match("abcd"));
+        assert!(Engine::new("bc").unwrap().is_match("bc"));
+        assert!(Engine::new("b+").unwrap().is_match("abbbd"));
+        assert!(Engine::new("a[^bc]d").unwrap().is_match("xxaedxx"));
+        assert!(Engine::new("ab{2}").unwrap().is_match("cabbb"));
+
+        assert!(!Engine::new("bc").unwrap().is_match("acbd"));
+        assert!(!Engine::new("bc").unwrap().is_match(""));
+        assert!(!Engine::new("a|b").unwrap().is_match("cde"));
+    }
+
+    #[test]
+    fn test_literal() {
+        assert!(Engine::new("abc").unwrap().is_full_match("abc"));
+        assert!(Engine::new("(ab)+c").unwrap().is_full_match("ababc"));
+        assert!(Engine::new("x(ab){2}").unwrap().is_full_match("xabab"));
+
+        assert!(!Engine::new("abc").unwrap().is_full_match("ab"));
+        assert!(!Engine::new("(ab)+c").unwrap().is_full_match("abac"));
+        assert!(!Engine::new("x(ab){2}").unwrap().is_full_match("xab"));
+    }
+
+    #[test]
+    fn test_from_ast() {
+        let engine = Engine::from_ast(PatternSection::And(
+            vec![
+                PatternSection::Literal("ab".into(), Quantifier::ONE, SourceSpan::default()),
+                PatternSection::CharGroup(
+                    vec![ClassRange::new('c', 'd')],
+                    Quantifier::ONE_OR_MORE,
+                    false,
+                    SourceSpan::default(),
+                ),
+            ],
+            Quantifier::ONE,
+            SourceSpan::default(),
+        ))
+        .unwrap();
+
+        assert!(engine.is_full_match("abc"));
+        assert!(engine.is_full_match("abdcd"));
+        assert!(!engine.is_full_match("ab"));
+
+        assert!(Engine::from_ast(PatternSection::And(
+            vec![],
+            Quantifier::ONE,
+            SourceSpan::default()
+        ))
+        .unwrap()
+        .is_full_match(""));
+    }
+
+    #[test]
+    fn test_to_dot_with() {
+        let engine = Engine::new("a?b").unwrap();
+
+        let dot = engine.to_dot_with(&DotOptions {
+            rankdir: Some(RankDir::LeftRight),
+            ..DotOptions::default()
+        });
+        assert!(dot.contains("\trankdir=LR\n"));
+        assert!(dot.contains("\tStart -> S1[label=\" \",color=\"green\"]\n"));
+
+        let dot = engine.to_dot_with(&DotOptions {
+            collapse_epsilon: true,
+            show_state_ids: false,
+            ..DotOptions::default()
+        });
+        assert!(!dot.contains("green"));
+        assert!(dot.contains("\tStart -> Finish[label=\"b\",color=\"black\"]\n"));
+        assert!(dot.contains("\tS1 [label=\"\",shape=\"point\"]\n"));
+
+        // The start state is not always 0.
+        let engine = Engine::from_table("start s\naccept f\nm b f\ns a m\n").unwrap();
+        let dot = engine.to_dot_with(&DotOptions {
+            show_state_ids: false,
+            ..DotOptions::default()
+        });
+        assert!(!dot.contains("\tStart [label=\"\",shape=\"point\"]\n"));
+        assert!(dot.contains("\tS0 [label=\"\",shape=\"point\"]\n"));
+
+        let engine = Engine::new("a?b").unwrap();
+        let (_, trace) = engine.trace("b");
+        let dot = engine.to_dot_with(&DotOptions {
+            highlight_path: Some(trace),
+            ..DotOptions::default()
+        });
+        assert!(dot.contains("\tS1 [style=\"filled\",fillcolor=\"yellow\"]\n"));
+        assert!(dot.contains("\tS1 -> Finish[label=\"b\",color=\"black\",penwidth=3]\n"));
+    }
+
+    #[test]
+    fn test_to_jflap() {
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>
+<structure>
+\t<type>fa</type>
+\t<automaton>
+\t\t<state id=\"0\" name=\"q0\">
+\t\t\t<x>100</x>
+\t\t\t<y>100</y>
+\t\t\t<initial/>
+\t\t</state>
+\t\t<state id=\"1\" name=\"q1\">
+\t\t\t<x>200</x>
+\t\t\t<y>100</y>
+\t\t</state>
+\t\t<state id=\"2\" name=\"q2\">
+\t\t\t<x>300</x>
+\t\t\t<y>100</y>
+\t\t\t<final/>
+\t\t</state>
+\t\t<transition>
+\t\t\t<from>0</from>
+\t\t\t<to>1</to>
+\t\t\t<read/>
+\t\t</transition>
+\t\t<transition>
+\t\t\t<from>0</from>
+\t\t\t<to>1</to>
+\t\t\t<read>a</read>
+\t\t</transition>
+\t\t<transition>
+\t\t\t<from>1</from>
+\t\t\t<to>2</to>
+\t\t\t<read>&lt;</read>
+\t\t</transition>
+\t\t<transition>
+\t\t\t<from>1</from>
+\t\t\t<to>2</to>
+\t\t\t<read>b</read>
+\t\t</transition>
+\t</automaton>
+</structure>
+",
+            Engine::new("a?[^a]").unwrap().to_jflap(&['a', 'b', '<'])
+        );
+
+        assert_eq!(
+            6,
+            Engine::new("a.b")
+                .unwrap()
+                .to_jflap(&['a', 'b', 'c', 'd'])
+                .matches("<transition>")
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_from_table() {
+        let engine = Engine::from_table(
+            "# a(b|.c)*
+            start q0
+            accept q1
+            q0 a q1
+            q1 b q1
+            q1 . q2
+            q2 c q1",
+        )
+        .unwrap();
+
+        assert!(engine.is_full_match("a"));
+        assert!(engine.is_full_match("abxcb"));
+        assert!(!engine.is_full_match("abx"));
+        assert!(engine.is_match("zzabz"));
+
+        let engine = Engine::from_table("start s\naccept s t\ns x t\ns eps t").unwrap();
+        assert!(engine.is_full_match(""));
+        assert!(engine.is_full_match("x"));
+        assert!(!engine.is_full_match("xx"));
+
+        assert_eq!(
+            Err("Missing start state".into()),
+            Engine::from_table("accept a").map(|_| ())
+        );
+        assert_eq!(
+            Err("Invalid line 2: a bb c".into()),
+            Engine::from_table("start a\na bb c").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_to_table() {
+        let engine = Engine::new("a[^bc]?").unwrap();
+        assert_eq!(
+            "start 0\naccept 2\n0 a 1\n1 eps 2\n1 [^bc] 2\n",
+            engine.to_table()
+        );
+        assert_eq!(
+            "start 0\naccept 2\n0 \\. 1\n1 . 2\n",
+            Engine::new("\\..").unwrap().to_table()
+        );
+
+        let from_table = Engine::from_table(&engine.to_table()).unwrap();
+        for s in ["a", "ax", "ab", "b"] {
+            assert_eq!(
+                engine.is_full_match(s),
+                from_table.is_full_match(s),
+                "{}",
+                s
+            );
         }
     }
 
-    pub fn is_match(&self, s: &str) -> bool {
-        let mut stack: Vec<(State, usize)> = vec![(0, 0)];
-        let chars = s.chars().collect::<Vec<_>>();
+    #[test]
+    fn test_stats() {
+        // `ab(c|d)`, the literals are split into chars.
+        let stats = Engine::new("abc|abd").unwrap().stats();
+        assert_eq!(3, stats.ast_nodes);
+        assert_eq!(5, stats.factored_ast_nodes);
+        assert_eq!(stats.compiled, stats.pruned);
+        assert_eq!(
+            AutomatonSize {
+                states: 5,
+                edges: 6
+            },
+            stats.merged
+        );
 
-        while let Some((state, i)) = stack.pop() {
-            if state == self.finish_state && i >= chars.len() {
-                return true;
+        let stats = Engine::from_table("start s\naccept f\ns a f\ns b dead\n")
+            .unwrap()
+            .stats();
+        assert_eq!(0, stats.ast_nodes);
+        assert_eq!(
+            AutomatonSize {
+                states: 4,
+                edges: 4
+            },
+            stats.pruned
+        );
+        assert!(stats.compiled.edges > stats.pruned.edges);
+    }
+
+    #[test]
+    fn test_start_state_not_first() {
+        let engine = Engine::from_table("start s\naccept f\nq x f\ns a q\n").unwrap();
+        assert_eq!(2, engine.start_state);
+        assert_eq!(
+            "start 2\naccept 3\n0 x 1\n1 eps 3\n2 a 0\n",
+            engine.to_table()
+        );
+
+        assert!(engine.is_full_match("ax"));
+        assert!(!engine.is_full_match("x"));
+        assert!(engine.is_match("bax"));
+        assert!(!engine.is_nullable());
+        assert_eq!(
+            vec![(1, 3)],
+            engine.find_iter_multiline("bax").collect::<Vec<_>>()
+        );
+        assert!(engine.to_dot().contains("Start -> S0"));
+    }
+
+    #[test]
+    fn test_input() {
+        let engine = Engine::new("a[^b]c").unwrap();
+        assert!(engine.is_match_input(&b"xa\xffc"[..]));
+        assert!(!engine.is_match_input(&b"abc"[..]));
+        assert!(engine.is_full_match_input(CharIter("cxa".chars().rev())));
+        assert!(!engine.is_full_match_input(CharIter("axc!".chars())));
+
+        let safe = EngineBuilder::new("a[^b]c").safe_mode().build().unwrap();
+        assert!(safe.is_match_input(&b"xa\xffc"[..]));
+        assert!(safe.is_full_match_input(CharIter("axc".chars())));
+    }
+
+    #[test]
+    fn test_prune_states() {
+        // q2 is a dead end, q3 is unreachable.
+        let engine = Engine::from_table(
+            "start q0\n\
+             accept q1\n\
+             q0 a q1\n\
+             q0 b q2\n\
+             q2 c q2\n\
+             q3 d q1\n",
+        )
+        .unwrap();
+        assert_eq!("start 0\naccept 4\n0 a 1\n1 eps 4\n", engine.to_table());
+        assert!(engine.is_full_match("a"));
+        assert!(!engine.is_match("bc"));
+
+        let pruned = Engine::new("(ab|cd)?e").unwrap();
+        let unpruned = EngineBuilder::new("(ab|cd)?e")
+            .prune_states(false)
+            .build()
+            .unwrap();
+        assert_eq!(pruned.to_table(), unpruned.to_table());
+    }
+
+    #[test]
+    fn test_no_unreachable_states() {
+        for pattern in [
+            "(aaa)*", "a*", "a*b", "a+|b*", "(ab|c)*d", "a?b{1,2}", "[^ab]*c",
+        ] {
+            let stats = EngineBuilder::new(pattern)
+                .prune_states(false)
+                .merge_states(false)
+                .build()
+                .unwrap()
+                .stats();
+            assert_eq!(stats.compiled, stats.pruned, "{}", pattern);
+        }
+        assert_eq!(
+            "start 0\naccept 5\n0 eps 1\n1 eps 5\n1 a 2\n2 a 3\n3 a 0\n",
+            Engine::new("(?:aaa)*").unwrap().to_table()
+        );
+    }
+
+    #[test]
+    fn test_merge_states() {
+        // The states after `a` and `c` move the same way.
+        let engine = Engine::new("ab|cb").unwrap();
+        assert_eq!(
+            "start 0\naccept 2\n0 a 1\n0 c 1\n1 b 2\n",
+            engine.to_table()
+        );
+
+        for pattern in ["(ab|cd|ef)?e", "a{0,3}b", "(a|b)*c", "[^x]?y|z"] {
+            let merged = Engine::new(pattern).unwrap();
+            let unmerged = EngineBuilder::new(pattern)
+                .merge_states(false)
+                .build()
+                .unwrap();
+            assert!(merged.to_table().len() <= unmerged.to_table().len());
+            for s in [
+                "", "e", "abe", "efe", "aab", "aaaab", "abc", "y", "xy", "ay", "z",
+            ] {
+                assert_eq!(
+                    unmerged.is_full_match(s),
+                    merged.is_full_match(s),
+                    "{} {}",
+                    pattern,
+                    s
+                );
             }
+        }
+    }
 
-            let mut new_states = self.transitions.states_from(state, chars.get(i), i);
-            stack.append(&mut new_states);
+    #[test]
+    fn test_capture_group_tree() {
+        let engine = Engine::new("a(b(c))(d)").unwrap();
+        let groups = engine.capture_group_tree();
+
+        assert_eq!(
+            vec![1, 3],
+            groups.iter().map(|group| group.index).collect::<Vec<_>>()
+        );
+        assert_eq!(2, groups[0].children[0].index);
+        assert_eq!((3, 6), groups[0].children[0].span);
+        assert!(Engine::new("abc").unwrap().capture_group_tree().is_empty());
+
+        let engine = Engine::new("(?:a(b))+").unwrap();
+        assert_eq!(1, engine.capture_group_tree()[0].index);
+        assert_eq!((4, 7), engine.capture_group_tree()[0].span);
+        assert!(engine.is_full_match("abab"));
+    }
+
+    #[test]
+    fn test_compile_many() {
+        let engines = Engine::compile_many(&["a[^\"]+", "[^\"]b", "[^x]"]).unwrap();
+        assert!(engines[0].is_full_match("axy"));
+        assert!(!engines[0].is_full_match("a\""));
+        assert!(engines[1].is_full_match("xb"));
+
+        let class_of = |engine: &Engine| {
+            let mut classes = engine.transitions.classes.values().flat_map(|s| s.keys());
+            classes.next().unwrap().clone()
+        };
+        assert!(CharClass::ptr_eq(
+            &class_of(&engines[0]),
+            &class_of(&engines[1])
+        ));
+        assert!(!CharClass::ptr_eq(
+            &class_of(&engines[0]),
+            &class_of(&engines[2])
+        ));
+
+        assert_eq!(
+            Err((1, ErrorCode::UnterminatedGroup)),
+            Engine::compile_many(&["a", "b(", "c("])
+                .map(|_| ())
+                .map_err(|(i, err)| (i, err.code()))
+        );
+    }
+
+    #[test]
+    fn test_builder_budget() {
+        let engine = EngineBuilder::new("a{3}b")
+            .max_compile_steps(100)
+            .build()
+            .unwrap();
+        assert!(engine.is_full_match("aaab"));
+
+        assert_eq!(
+            Err("Compilation exceeded the budget of 100 steps".into()),
+            EngineBuilder::new("(ab{100}){100}")
+                .max_compile_steps(100)
+                .build()
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        );
+        assert_eq!(
+            Err("Compilation exceeded the budget of 0ns".into()),
+            EngineBuilder::new("(ab{100}){100}")
+                .max_compile_time(Duration::ZERO)
+                .build()
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        );
+        assert_eq!(
+            Err("Missing end of char group at (1, 3)".into()),
+            EngineBuilder::new("a[b")
+                .build()
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        );
+
+        assert_eq!(
+            Some(ErrorCode::UnmatchedParen),
+            Engine::new("a)").err().map(|err| err.code())
+        );
+
+        let err = EngineBuilder::new("a[b").build().unwrap_err();
+        assert_eq!(ErrorCode::UnterminatedClass, err.code());
+        assert_eq!("E0001", err.code().as_str());
+        assert_eq!(Some((1, 3)), err.span());
+        assert_eq!(
+            Some("add ']' to close the char group opened at offset 1"),
+            err.suggestion()
+        );
+        assert_eq!(
+            ErrorCode::CompileBudgetExceeded,
+            EngineBuilder::new("(ab{100}){100}")
+                .max_compile_steps(100)
+                .build()
+                .unwrap_err()
+                .code()
+        );
+    }
+
+    #[test]
+    fn test_no_panic() {
+        for pattern in [
+            "a{0}",
+            "a{5,2}",
+            "a{18446744073709551615}",
+            "a{1,18446744073709551615}",
+            "a{,3}",
+            "(",
+            ")",
+            "((",
+            "[",
+            "[^]",
+            "{",
+            "*",
+            "(?)",
+            "||",
+            "(|)",
+            "a**",
+            "\\",
+        ] {
+            // Either way, without panicking.
+            let _ = EngineBuilder::new(pattern)
+                .max_compile_steps(10_000)
+                .build()
+                .map(|engine| engine.is_match("aaa"));
         }
+    }
 
-        false
+    #[test]
+    fn test_scratch_pooling() {
+        let engine = Engine::new("a(b|c)*").unwrap();
+        assert!(engine.is_match("xabcb"));
+        assert!(Scratch::is_pooled(engine.id));
+
+        // A match within a match of the same engine gets its own buffers.
+        let (is_match, _) = engine.trace("abc");
+        let mut nested = vec![];
+        engine.run_with(engine.start_state, "ab", true, |_, _| {
+            nested.push(engine.is_full_match("ac"))
+        });
+        assert!(is_match);
+        assert!(nested.iter().all(|&is_match| is_match));
+
+        let engine = EngineBuilder::new("a(b|c)*")
+            .scratch_pooling(false)
+            .build()
+            .unwrap();
+        assert!(engine.is_match("xabcb"));
+        assert!(!Scratch::is_pooled(engine.id));
     }
 
-    pub fn dump_dot(&self) {
-        println!("digraph {{");
-        println!("\tStart [color=\"blue\"]");
-        println!("\tFinish [color=\"orange\"]");
+    #[test]
+    fn test_stream_matcher() {
+        let engine = Engine::new("ab+c").unwrap();
+        let mut matcher = engine.stream_matcher();
+        assert_eq!(None, matcher.feed(b"xxab"));
+        assert_eq!(None, matcher.feed(b"bb"));
+        assert_eq!(Some((2, 7)), matcher.feed(b"cab"));
+        assert_eq!(Some((2, 7)), matcher.feed(b"c"));
 
-        let finish = self.finish_state;
-        let to_label = |s: State| {
-            if s == 0 {
-                "Start".into()
-            } else if s == finish {
-                "Finish".into()
-            } else {
-                format!("S{}", s)
+        // `é` is split between the chunks.
+        let engine = Engine::new("é+").unwrap();
+        let mut matcher = engine.stream_matcher();
+        assert_eq!(None, matcher.feed(&"aé".as_bytes()[..2]));
+        assert_eq!(Some((1, 3)), matcher.feed(&"aé".as_bytes()[2..]));
+
+        assert_eq!(
+            Some((0, 0)),
+            Engine::new("").unwrap().stream_matcher().feed(b"")
+        );
+        assert_eq!(
+            Some((0, 3)),
+            Engine::new("a.b").unwrap().stream_matcher().feed(b"a\xffb")
+        );
+        assert_eq!(
+            None,
+            Engine::new("a[^b]").unwrap().stream_matcher().feed(b"ab")
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_find_stream() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        // Reading a slice never waits, so a single poll completes the search.
+        let engine = Engine::new("b+c").unwrap();
+        let mut context = Context::from_waker(Waker::noop());
+
+        let future = std::pin::pin!(engine.find_stream(&b"aabbcd"[..]));
+        assert!(matches!(
+            future.poll(&mut context),
+            Poll::Ready(Ok(Some((2, 5))))
+        ));
+
+        let future = std::pin::pin!(engine.find_stream(&b"aabbd"[..]));
+        assert!(matches!(future.poll(&mut context), Poll::Ready(Ok(None))));
+    }
+
+    #[test]
+    fn test_find_iter_multiline() {
+        let engine = Engine::new("a.b").unwrap();
+        assert_eq!(
+            vec![(0, 3), (4, 7)],
+            engine.find_iter_multiline("a\nbxa\nb").collect::<Vec<_>>()
+        );
+
+        let engine = Engine::new("abcd|c|bb").unwrap();
+        assert_eq!(
+            vec![(0, 4), (5, 7), (8, 9)],
+            engine.find_iter_multiline("abcdxbbbc").collect::<Vec<_>>()
+        );
+
+        let engine = Engine::new("é*").unwrap();
+        assert_eq!(
+            vec![(0, 0), (1, 5)],
+            engine.find_iter_multiline("xéé").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(0, 0), (1, 3), (4, 4)],
+            Engine::new("a*")
+                .unwrap()
+                .find_iter_multiline("baab")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(0, 0), (1, 1), (2, 2)],
+            Engine::new("a*")
+                .unwrap()
+                .find_iter_multiline("bb")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(0, 2), (3, 3)],
+            Engine::new("a*")
+                .unwrap()
+                .find_iter_multiline("aab")
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let engine = Engine::new("b+").unwrap();
+        let found = engine.find("abbc").unwrap();
+        assert_eq!((1, 3), found.span());
+        assert_eq!("bb", found.slice);
+        assert_eq!(None, engine.find("ac"));
+
+        // Multi-byte chars before the match and an empty match.
+        assert_eq!(
+            Some(Match {
+                start: 3,
+                end: 6,
+                slice: "bé",
+            }),
+            Engine::new("bé").unwrap().find("éxbé")
+        );
+        assert_eq!(
+            Some((0, 0)),
+            Engine::new("a*").unwrap().find("bc").map(|m| m.span())
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        let engine = Engine::new("a\\.b|\\(x\\)").unwrap();
+        assert!(engine.is_full_match("a.b"));
+        assert!(!engine.is_full_match("axb"));
+        assert!(engine.is_full_match("(x)"));
+        assert_eq!(
+            vec![(1, 4)],
+            engine.find_iter_multiline("xa.bxaxb").collect::<Vec<_>>()
+        );
+
+        // The wildcard still reads dots, and other chars.
+        let engine = Engine::new("\\..").unwrap();
+        assert!(engine.is_full_match(".."));
+        assert!(engine.is_full_match(".x"));
+        assert!(!engine.is_full_match("x."));
+        assert_eq!(vec![ClassRange::ALL], engine.alphabet().collect::<Vec<_>>());
+        assert_eq!(3, Engine::new("a\\.").unwrap().char_classes().count());
+
+        // Factored prefixes keep the dot literal.
+        let engine = Engine::new("a\\.b|a\\.c").unwrap();
+        assert!(engine.is_full_match("a.c"));
+        assert!(!engine.is_full_match("axc"));
+    }
+
+    #[test]
+    fn test_compile_dfa() {
+        let mut engine = Engine::new("a(b|c)*d").unwrap();
+        assert!(engine.compile_dfa());
+        assert!(engine.has_dfa());
+        assert!(engine.is_match("xabcdx"));
+        assert!(!engine.is_match("abc"));
+        assert!(engine.is_full_match("abd"));
+        assert!(!engine.is_full_match("xabd"));
+        // Views share the DFA, and start from the start state.
+        let anchored = engine.anchored_view();
+        assert!(anchored.has_dfa());
+        assert!(anchored.is_match("abdx"));
+        assert!(!anchored.is_match("xabd"));
+
+        // Falls back to the automaton when the DFA blows up.
+        let mut engine = Engine::new("(a|b)*a(a|b){14}").unwrap();
+        assert!(!engine.compile_dfa());
+        assert!(!engine.has_dfa());
+        assert!(engine.is_match("abbbbbbbbbbbbbbb"));
+
+        // Small tables are dense, unless asked otherwise.
+        assert_eq!(None, engine.dfa_storage());
+        let mut engine = Engine::new("a(b|c)*d").unwrap();
+        engine.compile_dfa();
+        assert_eq!(Some(DfaStorage::Dense), engine.dfa_storage());
+        let mut engine = EngineBuilder::new("a(b|c)*d")
+            .dfa_storage(DfaStorage::Sparse)
+            .build()
+            .unwrap();
+        engine.compile_dfa();
+        assert_eq!(Some(DfaStorage::Sparse), engine.dfa_storage());
+        assert!(engine.is_match("xabcdx"));
+        assert!(!engine.is_match("abc"));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let engine = Engine::new("^ab|c$").unwrap();
+        assert!(engine.is_match("abx"));
+        assert!(!engine.is_match("xab"));
+        assert!(engine.is_match("xc"));
+        assert!(!engine.is_match("cx"));
+        assert!(engine.is_full_match("ab"));
+        assert!(!engine.is_full_match("abc"));
+        assert_eq!(
+            vec![(0, 2), (5, 6)],
+            engine.find_iter_multiline("abcabc").collect::<Vec<_>>()
+        );
+
+        // Anchors are checked against the whole haystack, not where the search goes on from.
+        let engine = Engine::new("^a").unwrap();
+        assert_eq!(
+            vec![(0, 1)],
+            engine.find_iter_multiline("aaa").collect::<Vec<_>>()
+        );
+        let engine = Engine::new("a*$").unwrap();
+        assert_eq!(
+            vec![(1, 3)],
+            engine.find_iter_multiline("baa").collect::<Vec<_>>()
+        );
+        assert!(Engine::new("^$").unwrap().is_nullable());
+        assert!(!Engine::new("a^").unwrap().is_match("a"));
+
+        let engine = Engine::new("(a+)$").unwrap();
+        let captures = engine.captures("aba").unwrap();
+        assert_eq!(Some("a"), captures.get(1).map(|m| m.slice));
+        assert_eq!(Some((2, 3)), captures.get(0).map(|m| m.span()));
+
+        let engine = EngineBuilder::new("^a|b$").safe_mode().build().unwrap();
+        assert!(engine.is_match("ax"));
+        assert!(engine.is_match("xb"));
+        assert!(!engine.is_match("xa"));
+        assert!(!engine.is_match("bx"));
+
+        let engine = Engine::new("^ab$").unwrap();
+        assert!(engine.is_match_fuzzy("b", 1));
+        assert!(!engine.is_match_fuzzy("xxab", 1));
+        assert_eq!(
+            Err(ValidationError {
+                span: (2, 3),
+                found: Some('c')
+            }),
+            engine.validate("abc")
+        );
+
+        // Matches ending with the input are only found once it ended.
+        let engine = Engine::new("b$").unwrap();
+        let mut matcher = engine.stream_matcher();
+        assert_eq!(None, matcher.feed(b"abab"));
+        assert_eq!(Some((3, 4)), matcher.finish());
+        let mut scanner = engine.end_scanner();
+        let mut ends = vec![];
+        scanner.feed(b"bb", |end| ends.push(end));
+        scanner.finish(|end| ends.push(end));
+        assert_eq!(vec![2], ends);
+
+        let engine = Engine::new("^\\^a$|\\$").unwrap();
+        let table = engine.to_table();
+        assert_eq!(
+            "start 0\naccept 4\n0 ^ 1\n0 \\$ 4\n1 \\^ 2\n2 a 3\n3 $ 4\n",
+            table
+        );
+        let engine = Engine::from_table(&table).unwrap();
+        assert!(engine.is_match("^a"));
+        assert!(engine.is_match("x$y"));
+        assert!(!engine.is_match("x^a"));
+    }
+
+    #[test]
+    fn test_perl_classes() {
+        let engine = Engine::new("\\d{3}-\\d{4}").unwrap();
+        assert_eq!(
+            Some("555-0123"),
+            engine.find("call 555-01234").map(|m| m.slice)
+        );
+        assert!(!engine.is_match("555-012"));
+
+        let engine = Engine::new("\\w+\\s+\\W\\S\\D").unwrap();
+        assert!(engine.is_full_match("snake_42 \t\n!xy"));
+        assert!(!engine.is_full_match("snake_42 a!xy"));
+        assert!(!engine.is_full_match("a !x1"));
+    }
+
+    #[test]
+    fn test_char_group_ranges() {
+        let engine = Engine::new("[a-zA-Z0-9_]+").unwrap();
+        assert_eq!(
+            Some("snake_Case_42"),
+            engine.find("(snake_Case_42)").map(|m| m.slice)
+        );
+        assert!(!engine.is_match("-+é"));
+
+        let engine = Engine::new("[^a-z-]").unwrap();
+        assert!(engine.is_full_match("B"));
+        assert!(!engine.is_full_match("q"));
+        assert!(!engine.is_full_match("-"));
+    }
+
+    #[test]
+    fn test_full_unicode_char_group() {
+        // A range is one move, however many chars it holds.
+        let started_at = Instant::now();
+        let engine = Engine::new("[ -\u{10FFFF}]+").unwrap();
+        let negated = Engine::new("[^ -\u{10FFFF}]x").unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+
+        assert!(engine.is_full_match("a \u{10FFFF}é"));
+        assert!(!engine.is_match("\t\n"));
+        assert_eq!(Some("ab"), engine.find("\tab\n").map(|m| m.slice));
+        assert!(negated.is_full_match("\tx"));
+        assert!(!negated.is_match("ax"));
+        assert_eq!(Some((1, 3)), negated.find("a\tx").map(|m| (m.start, m.end)));
+    }
+
+    #[test]
+    fn test_split() {
+        let engine = Engine::new("[,;]").unwrap();
+        let split = |s| engine.split(s).collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b", "", "c"], split("a,b;;c"));
+        assert_eq!(vec!["", "a", ""], split(",a,"));
+        assert_eq!(vec![""], split(""));
+        assert_eq!(vec!["ü", "é"], split("ü,é"));
+        assert_eq!(
+            vec!["", "a", "b", ""],
+            Engine::new("x*").unwrap().split("ab").collect::<Vec<_>>()
+        );
+
+        let splitn = |s, n| engine.splitn(s, n).collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b;c"], splitn("a,b;c", 2));
+        assert_eq!(vec!["a,b;c"], splitn("a,b;c", 1));
+        assert_eq!(vec!["a", "b", "c"], splitn("a,b;c", 5));
+        assert!(splitn("a,b;c", 0).is_empty());
+    }
+
+    #[test]
+    fn test_captures() {
+        let engine = Engine::new("(a+)(b+)").unwrap();
+        assert_eq!(2, engine.capture_count());
+        let caps = engine.captures("xaabbbc").unwrap();
+        assert_eq!(3, caps.len());
+        assert_eq!(
+            vec![Some("aabbb"), Some("aa"), Some("bbb")],
+            caps.iter().map(|m| m.map(|m| m.slice)).collect::<Vec<_>>()
+        );
+        assert_eq!(Some((3, 6)), caps.span(2));
+        assert_eq!(None, caps.get(3));
+        assert_eq!(None, engine.captures("xbb"));
+
+        // Groups not taking part, nested and non-capturing groups.
+        let caps = Engine::new("(a)|b").unwrap().captures("b").unwrap();
+        assert_eq!((Some((0, 1)), None), (caps.span(0), caps.span(1)));
+        let caps = Engine::new("((a)b)(?:c)(d)")
+            .unwrap()
+            .captures("abcd")
+            .unwrap();
+        assert_eq!(
+            vec![Some((0, 4)), Some((0, 2)), Some((0, 1)), Some((3, 4))],
+            (0..4).map(|i| caps.span(i)).collect::<Vec<_>>()
+        );
+
+        // The groups of the branches the match took, or for the longest match, the earliest start,
+        // then the longest wins. Repeated groups keep the last repetition.
+        let caps = Engine::new("(a|ab)(c|bcd)")
+            .unwrap()
+            .captures("abcd")
+            .unwrap();
+        assert_eq!((Some((0, 1)), Some((1, 4))), (caps.span(1), caps.span(2)));
+        let engine = Engine::new("(a|ab)(b*)").unwrap();
+        let caps = engine.captures("abb").unwrap();
+        assert_eq!((Some((0, 1)), Some((1, 3))), (caps.span(1), caps.span(2)));
+        let caps = engine.captures_longest("abb").unwrap();
+        assert_eq!((Some((0, 2)), Some((2, 3))), (caps.span(1), caps.span(2)));
+        let caps = Engine::new("(?:(a)b)+")
+            .unwrap()
+            .captures("xababx")
+            .unwrap();
+        assert_eq!(Some((3, 4)), caps.span(1));
+        let caps = Engine::new("(a*)*").unwrap().captures("").unwrap();
+        assert_eq!(Some((0, 0)), caps.span(0));
+
+        // Equal groups of different branches are not merged by the optimizer.
+        let engine = Engine::new("(a)x|(a)y").unwrap();
+        assert_eq!(2, engine.capture_count());
+        let caps = engine.captures("ay").unwrap();
+        assert_eq!((None, Some((0, 1))), (caps.span(1), caps.span(2)));
+
+        // Named groups count among the others.
+        let engine = Engine::new("(?P<year>[12]+)x(y)?(?<day>[34]+)").unwrap();
+        assert_eq!(
+            &[None, Some("year".into()), None, Some("day".into())],
+            engine.group_names()
+        );
+        let caps = engine.captures("on 21x43").unwrap();
+        assert_eq!(Some("21"), caps.name("year").map(|m| m.slice));
+        assert_eq!(Some("43"), caps.name("day").map(|m| m.slice));
+        assert_eq!(None, caps.name("month"));
+        assert_eq!(None, caps.get(2));
+
+        // Parsed by name or by index.
+        assert_eq!(Ok(21), caps.parse::<u32>("year"));
+        assert_eq!(Ok(43), caps.parse::<u8>("3"));
+        assert_eq!(
+            Err(CaptureParseError::Missing("2".into())),
+            caps.parse::<u32>("2")
+        );
+        assert!(matches!(
+            caps.parse::<u8>("0"),
+            Err(CaptureParseError::Invalid(_))
+        ));
+
+        let engine = Engine::new("(a)").unwrap();
+        let dot = engine.to_dot();
+        assert!(dot.contains("\tStart -> S1[label=\"(1\",color=\"blue\"]\n"));
+        assert!(dot.contains("\tS2 -> Finish[label=\"1)\",color=\"blue\"]\n"));
+
+        let dot = engine.to_dot_with(&DotOptions {
+            collapse_epsilon: true,
+            ..DotOptions::default()
+        });
+        assert!(!dot.contains("(1"));
+        assert!(dot.contains("\tStart -> S2[label=\"a\",color=\"black\"]\n"));
+    }
+
+    #[test]
+    fn test_auto_optimize() {
+        let engine = EngineBuilder::new(".*a[0-9]+.*")
+            .auto_optimize(true)
+            .build()
+            .unwrap();
+        assert!(engine.is_match("xxa12yy"));
+        assert!(!engine.is_match("xxayy"));
+        assert_eq!(Some((2, 5)), engine.find("xxa12yy").map(|m| m.span()));
+
+        let engine = Engine::new(".*a[0-9]+.*").unwrap();
+        assert_eq!(Some((0, 7)), engine.find("xxa12yy").map(|m| m.span()));
+    }
+
+    #[test]
+    fn test_capture_limits() {
+        let err = EngineBuilder::new("(a)(?:b)(c)(d)")
+            .max_capture_groups(2)
+            .build()
+            .unwrap_err();
+        assert_eq!(ErrorCode::TooManyCaptureGroups, err.code());
+        assert_eq!(Some((11, 14)), err.span());
+        assert!(EngineBuilder::new("(a)(?:b)(c)")
+            .max_capture_groups(2)
+            .build()
+            .is_ok());
+
+        // Without captures the groups only set the precedence.
+        let full = EngineBuilder::new("(ab)+(c)").build().unwrap();
+        let engine = EngineBuilder::new("(ab)+(c)")
+            .captures(false)
+            .max_capture_groups(0)
+            .build()
+            .unwrap();
+        assert_eq!(0, engine.capture_count());
+        assert!(engine.capture_group_tree().is_empty());
+        assert_eq!(2, full.capture_group_tree().len());
+        assert!(!engine.features().has_captures);
+        assert!(engine.stats().merged.states < full.stats().merged.states);
+        let caps = engine.captures("xababc").unwrap();
+        assert_eq!((1, Some((1, 6))), (caps.len(), caps.span(0)));
+    }
+
+    #[test]
+    fn test_alphabet() {
+        assert_eq!(
+            vec![ClassRange::new('a', 'c'), ClassRange::new('x', 'x')],
+            Engine::new("[ab]c|x")
+                .unwrap()
+                .alphabet()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![ClassRange::ALL],
+            Engine::new("[ab]c|d.")
+                .unwrap()
+                .alphabet()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                ClassRange::new('\0', 'a'),
+                ClassRange::new('c', 'c'),
+                ClassRange::new('e', char::MAX)
+            ],
+            Engine::new("[^bd]").unwrap().alphabet().collect::<Vec<_>>()
+        );
+        assert_eq!(0, Engine::new("").unwrap().alphabet().count());
+
+        // Chars only on dead ends are left out.
+        let engine = Engine::from_table("start q0\naccept q1\nq0 a q1\nq0 b q2").unwrap();
+        assert_eq!(
+            vec![ClassRange::new('a', 'a')],
+            engine.alphabet().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_char_classes() {
+        let engine = Engine::new("[ab]c|[^x]").unwrap();
+        let classes = engine.char_classes();
+        assert_eq!(4, classes.count());
+        assert_eq!(4, engine.stats().char_classes);
+        assert_eq!(classes.class_of('a'), classes.class_of('b'));
+        assert_eq!(classes.class_of('d'), classes.class_of('\0'));
+        assert_eq!(classes.class_of('d'), classes.class_of(char::MAX));
+        let distinct = ['a', 'c', 'x', 'd']
+            .iter()
+            .map(|c| classes.class_of(*c))
+            .collect::<HashSet<_>>();
+        assert_eq!(4, distinct.len());
+
+        // The wildcard reads every char the same way.
+        assert_eq!(1, Engine::new(".*").unwrap().char_classes().count());
+        assert_eq!(3, Engine::new("a.b").unwrap().char_classes().count());
+    }
+
+    #[test]
+    fn test_find_iter() {
+        // Leftmost-first: the earlier branch wins, unlike in `find_longest`.
+        let cases: [(&str, &str, &[&str]); 8] = [
+            ("a|ab", "abab", &["a", "a"]),
+            ("b*[^c].|b*", "bcb", &["bc", "b"]),
+            ("(?:b?|a)*", "a", &["", ""]),
+            ("ab|a", "abab", &["ab", "ab"]),
+            ("(?:a|ab)(?:c|bcd)", "abcd", &["abcd"]),
+            ("a+?", "aab", &["a", "a"]),
+            ("a{2,3}", "aaaaa", &["aaa", "aa"]),
+            ("a{2,3}?", "aaaaa", &["aa", "aa"]),
+        ];
+        for (pattern, haystack, matches) in cases {
+            let engine = Engine::new(pattern).unwrap();
+            assert_eq!(
+                matches,
+                engine
+                    .find_iter(haystack)
+                    .map(|m| m.slice)
+                    .collect::<Vec<_>>(),
+                "{}",
+                pattern
+            );
+            assert_eq!(
+                engine.find_iter(haystack).next(),
+                engine.find(haystack),
+                "{}",
+                pattern
+            );
+        }
+        assert_eq!(
+            Some("ab"),
+            Engine::new("a|ab")
+                .unwrap()
+                .find_longest("abab")
+                .map(|m| m.slice)
+        );
+        assert_eq!(
+            vec![(1, 3), (6, 7)],
+            Engine::new("b+")
+                .unwrap()
+                .find_iter("abbcéb")
+                .map(|m| m.span())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, Engine::new("x").unwrap().find_iter("abc").count());
+    }
+
+    #[test]
+    fn test_find_with_deadline() {
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            DeadlineMatch::Match((2, 5)),
+            Engine::new("b+")
+                .unwrap()
+                .find_with_deadline("aabbbc", later)
+        );
+        assert_eq!(
+            DeadlineMatch::NoMatch,
+            Engine::new("b+").unwrap().find_with_deadline("aac", later)
+        );
+
+        let haystack = "a".repeat(100_000) + "b";
+        assert_eq!(
+            DeadlineMatch::TimedOut { scanned_len: 0 },
+            Engine::new("b")
+                .unwrap()
+                .find_with_deadline(&haystack, Instant::now())
+        );
+        assert_eq!(
+            DeadlineMatch::Match((100_000, 100_001)),
+            Engine::new("b")
+                .unwrap()
+                .find_with_deadline(&haystack, later)
+        );
+    }
+
+    #[test]
+    fn test_anchored_view() {
+        let engine = Engine::new("ab+").unwrap();
+        let anchored = engine.anchored_view();
+        assert!(Arc::ptr_eq(&engine.transitions, &anchored.transitions));
+
+        assert!(engine.is_match("xab"));
+        assert!(!anchored.is_match("xab"));
+        assert!(anchored.is_match("abbx"));
+        assert!(anchored.is_full_match("abb"));
+        assert!(!anchored.is_match_fuzzy("xxab", 1));
+        assert!(anchored.is_match_fuzzy("xab", 1));
+        assert_eq!(
+            vec![(0, 3), (3, 5)],
+            anchored.find_iter_multiline("abbabxab").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            DeadlineMatch::NoMatch,
+            anchored.find_with_deadline("xab", Instant::now() + Duration::from_secs(60))
+        );
+
+        // The view outlives the engine it came from.
+        drop(engine);
+        assert_eq!("match 'a', then one or more of 'b'", anchored.explain());
+    }
+
+    #[test]
+    fn test_scan() {
+        let engine = Engine::new("ab").unwrap();
+        let mut events = vec![];
+        let flow = engine.scan("xabab", |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(ControlFlow::Continue(()), flow);
+        assert_eq!(
+            vec![
+                ScanEvent::MatchStart(1),
+                ScanEvent::MatchEnd((1, 3)),
+                ScanEvent::MatchStart(3),
+                ScanEvent::MatchEnd((3, 5)),
+            ],
+            events
+        );
+
+        // Stops at the first match end.
+        let mut count = 0;
+        let flow = engine.scan("xabab", |event| {
+            count += 1;
+            match event {
+                ScanEvent::MatchEnd(_) => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
             }
-        };
+        });
+        assert_eq!(ControlFlow::Break(()), flow);
+        assert_eq!(2, count);
 
-        for (k, vs) in &self.transitions.base {
-            for v in vs {
-                println!(
-                    "\t{} -> {}[label=\"{}\",color=\"{}\"]",
-                    to_label(k.0),
-                    to_label(*v),
-                    k.1.unwrap_or(' '),
-                    k.1.map(|_| "black").unwrap_or("green")
-                );
+        // Groups come by index between the start and end of their match, skipping the ones taking
+        // no part in it.
+        let engine = Engine::new("((a)|b)(c)?").unwrap();
+        let mut events = vec![];
+        let _ = engine.scan("xab", |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(
+            vec![
+                ScanEvent::MatchStart(1),
+                ScanEvent::GroupCapture(1, (1, 2)),
+                ScanEvent::GroupCapture(2, (1, 2)),
+                ScanEvent::MatchEnd((1, 2)),
+                ScanEvent::MatchStart(2),
+                ScanEvent::GroupCapture(1, (2, 3)),
+                ScanEvent::MatchEnd((2, 3)),
+            ],
+            events
+        );
+
+        // Empty matches where the last one ended are skipped like by `find_iter`.
+        let engine = Engine::new("a*").unwrap();
+        let mut starts = vec![];
+        let _ = engine.scan("baab", |event| {
+            if let ScanEvent::MatchStart(start) = event {
+                starts.push(start);
             }
-        }
+            ControlFlow::Continue(())
+        });
+        assert_eq!(vec![0, 1, 4], starts);
 
-        for (from_state, submap) in &self.transitions.negated {
-            for (not_chars, to_states) in submap {
-                for to_state in to_states {
-                    println!(
-                        "\t{} -> {}[label=\"^{}\",color=\"purple\"]",
-                        to_label(*from_state),
-                        to_label(*to_state),
-                        not_chars
-                            .iter()
-                            .map(|c| c.to_string())
-                            .collect::<Vec<_>>()
-                            .join("")
+        // Stops at the first match start without reading the match.
+        let mut count = 0;
+        let flow = Engine::new("x.*").unwrap().scan("axyz", |_| {
+            count += 1;
+            ControlFlow::Break(())
+        });
+        assert_eq!(ControlFlow::Break(()), flow);
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn test_redact() {
+        let engine = Engine::new("[0123456789][0123456789]*").unwrap();
+        assert_eq!("card ****, pin **", engine.redact("card 1234, pin 42", '*'));
+        assert_eq!("né", Engine::new("x").unwrap().redact("né", '#'));
+        assert_eq!("##", Engine::new("é.").unwrap().redact("éa", '#'));
+        assert_eq!("ab", Engine::new("").unwrap().redact("ab", '#'));
+    }
+
+    #[test]
+    fn test_redact_groups() {
+        let engine = Engine::new("(?P<user>[a-z]+)\\@([a-z]+)(\\.com)?").unwrap();
+        assert_eq!(
+            "to ***@mail.org, ***@web.com",
+            engine.redact_groups("to bob@mail.org, eve@web.com", &["user"], '*')
+        );
+        assert_eq!(
+            "bob@mail.org, eve@web****",
+            engine.redact_groups("bob@mail.org, eve@web.com", &["3"], '*')
+        );
+        assert_eq!(
+            "***@****.org",
+            engine.redact_groups("bob@mail.org", &["1", "2", "3", "9"], '*')
+        );
+        assert_eq!(
+            "a##b",
+            Engine::new("a((é)x)b")
+                .unwrap()
+                .redact_groups("aéxb", &["2", "1"], '#')
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let engine = Engine::new("ab+[cd]").unwrap();
+        assert_eq!(Ok(()), engine.validate("abbd"));
+        assert_eq!(
+            Err(ValidationError {
+                span: (3, 4),
+                found: Some('x'),
+            }),
+            engine.validate("abbxd")
+        );
+        assert_eq!(
+            Err(ValidationError {
+                span: (3, 3),
+                found: None,
+            }),
+            engine.validate("abb")
+        );
+        assert_eq!(
+            "Unexpected 'é' at 1",
+            Engine::new("a")
+                .unwrap()
+                .validate("aé")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "Unexpected end of input at 0",
+            Engine::new("a")
+                .unwrap()
+                .validate("")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_match_outcome() {
+        let engine = Engine::new("ab*c").unwrap();
+        assert_eq!(
+            MatchOutcome {
+                matched: true,
+                furthest_pos: 4,
+                ended_in_state_count: 1,
+            },
+            engine.match_outcome("abbc")
+        );
+        assert!(!engine.match_outcome("abbcd").matched);
+        assert_eq!(4, engine.match_outcome("abbcd").furthest_pos);
+        assert_eq!(2, engine.match_outcome("abxc").furthest_pos);
+        assert_eq!(3, engine.match_outcome("abb").furthest_pos);
+    }
+
+    #[test]
+    fn test_is_match_fuzzy() {
+        let engine = Engine::new("hello").unwrap();
+        assert!(engine.is_match_fuzzy("say hello", 0));
+        assert!(!engine.is_match_fuzzy("say helo", 0));
+        assert!(engine.is_match_fuzzy("say helo", 1));
+        assert!(engine.is_match_fuzzy("hxllo", 1));
+        assert!(engine.is_match_fuzzy("heello", 1));
+        assert!(!engine.is_match_fuzzy("hlo", 1));
+        assert!(engine.is_match_fuzzy("hlo", 2));
+
+        let engine = Engine::new("a[^b]+c").unwrap();
+        assert!(engine.is_match_fuzzy("abc", 1));
+        assert!(!engine.is_match_fuzzy("xyz", 1));
+        assert!(engine.is_match_fuzzy("xyz", 2));
+    }
+
+    #[test]
+    fn test_match_score() {
+        let engine = Engine::new("abc[de]").unwrap();
+        assert_eq!(1.0, engine.match_score("abcd"));
+        assert_eq!(1.0, engine.match_score("ab"));
+        assert_eq!(0.5, engine.match_score("abxx"));
+        assert_eq!(0.0, engine.match_score("x"));
+        assert_eq!(0.0, engine.match_score(""));
+        assert_eq!(1.0, Engine::new("a*").unwrap().match_score(""));
+    }
+
+    #[test]
+    fn test_max_haystack_len() {
+        let engine = EngineBuilder::new("ab")
+            .max_haystack_len(4)
+            .build()
+            .unwrap();
+
+        assert_eq!(BoundedMatch::Match, engine.is_match_bounded("xabxx"));
+        assert_eq!(BoundedMatch::NoMatch, engine.is_match_bounded("xxx"));
+        assert_eq!(
+            BoundedMatch::Truncated { processed_len: 4 },
+            engine.is_match_bounded("xxxxab")
+        );
+        // `é` would be split at 4.
+        assert_eq!(
+            BoundedMatch::Truncated { processed_len: 3 },
+            engine.is_match_bounded("xxxéab")
+        );
+        assert!(!engine.is_match("xxxxab"));
+
+        let engine = EngineBuilder::new("a*")
+            .max_haystack_len(2)
+            .build()
+            .unwrap();
+        assert!(engine.is_full_match("aa"));
+        assert!(!engine.is_full_match("aaa"));
+
+        // The searches agree with `is_match`, and keep the rest of the haystack as is.
+        let engine = EngineBuilder::new("abc")
+            .max_haystack_len(2)
+            .build()
+            .unwrap();
+        assert!(!engine.is_match("abc"));
+        assert_eq!(None, engine.find("abc"));
+        assert_eq!(None, engine.captures("abc"));
+        assert_eq!(0, engine.find_iter("abcabc").count());
+        assert_eq!("abc", engine.replace_all("abc", "x"));
+        assert!(!engine.is_match_fuzzy("abc", 0));
+        let lines = "abc\nab\n".as_bytes();
+        assert_eq!(0, engine.count_lines_matching(lines).unwrap());
+
+        let engine = EngineBuilder::new("a$")
+            .max_haystack_len(2)
+            .build()
+            .unwrap();
+        assert!(engine.is_match("xab"));
+        assert_eq!(Some((1, 2)), engine.find("xab").map(|m| m.span()));
+        assert_eq!(Some((1, 2)), engine.captures("xab").and_then(|c| c.span(0)));
+        assert_eq!("xXb", engine.replace_all("xab", "X"));
+    }
+
+    #[test]
+    fn test_match_range() {
+        let haystack = "é😀a\u{301}b";
+        let found = Engine::new(".a.").unwrap().find(haystack).unwrap();
+        assert_eq!(2..9, found.range());
+        assert_eq!("😀a\u{301}", &haystack[found.range()]);
+    }
+
+    #[test]
+    fn test_spans_on_char_boundaries() {
+        let haystacks = ["a😀b", "e\u{301}e", "😀😀", "éa\u{301}😀b", ""];
+
+        for pattern in [".", "a.b", "..", "a*", "[^a]b?", "e.?"] {
+            let engine = Engine::new(pattern).unwrap();
+
+            for haystack in haystacks {
+                for found in engine.find_iter(haystack) {
+                    assert_eq!(found.slice, &haystack[found.range()]);
+                }
+                for (start, end) in engine.find_iter_multiline(haystack) {
+                    assert!(
+                        haystack.is_char_boundary(start),
+                        "{} on {}",
+                        pattern,
+                        haystack
                     );
+                    assert!(
+                        haystack.is_char_boundary(end),
+                        "{} on {}",
+                        pattern,
+                        haystack
+                    );
+                }
+
+                // Fed byte by byte, so every char is split between chunks.
+                let mut matcher = engine.stream_matcher();
+                let mut found = matcher.feed(&[]);
+                for byte in haystack.as_bytes() {
+                    found = matcher.feed(&[*byte]);
+                }
+                if let Some((start, end)) = found {
+                    assert!(
+                        haystack.is_char_boundary(start),
+                        "{} on {}",
+                        pattern,
+                        haystack
+                    );
+                    assert!(
+                        haystack.is_char_boundary(end),
+                        "{} on {}",
+                        pattern,
+                        haystack
+                    );
+                    assert!(engine.is_match(&haystack[start..end]));
+                }
+
+                if let Err(err) = engine.validate(haystack) {
+                    assert!(haystack.is_char_boundary(err.span.0));
+                    assert!(haystack.is_char_boundary(err.span.1));
                 }
+
+                assert_eq!(
+                    haystack.chars().count(),
+                    engine.redact(haystack, '*').chars().count()
+                );
             }
         }
 
-        println!("}}");
+        assert_eq!(
+            vec![(0, 6)],
+            Engine::new("a.b")
+                .unwrap()
+                .find_iter_multiline("a😀b")
+                .collect::<Vec<_>>()
+        );
+        // A combining mark is a char of its own.
+        assert_eq!(
+            vec![(0, 1), (1, 3)],
+            Engine::new(".")
+                .unwrap()
+                .find_iter_multiline("e\u{301}")
+                .collect::<Vec<_>>()
+        );
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::engine::*;
+    #[cfg(feature = "grapheme")]
+    #[test]
+    fn test_grapheme_mode() {
+        let engine = EngineBuilder::new("a.b")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        assert!(engine.is_full_match("ae\u{301}b"));
+        assert!(engine.is_full_match("a🇫🇷b"));
+        assert!(engine.is_full_match("a👍🏽b"));
+        assert!(!engine.is_full_match("aeeb"));
+        assert!(engine.is_match("xa👍🏽bx"));
+
+        // Matches start at cluster boundaries only.
+        let engine = EngineBuilder::new(".{2}")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        assert!(engine.is_full_match("e\u{301}x"));
+        assert!(!Engine::new("a.b").unwrap().is_full_match("ae\u{301}b"));
+    }
 
+    #[cfg(feature = "grapheme")]
     #[test]
-    fn test_empty() {
-        assert!(Engine::new("").is_match(""));
-        assert!(!Engine::new("").is_match("a"));
-        assert!(!Engine::new("").is_match("abc"));
+    fn test_grapheme_mode_find() {
+        let engine = EngineBuilder::new("a(.)b")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        let haystack = "xae\u{301}b";
+        assert!(engine.is_match(haystack));
+        assert_eq!(Some((1, 6)), engine.find(haystack).map(|m| m.span()));
+        assert_eq!(
+            Some((1, 6)),
+            engine.find_longest(haystack).map(|m| m.span())
+        );
+        assert_eq!(Some((2, 5)), engine.captures(haystack).unwrap().span(1));
+        assert_eq!(
+            Some((2, 5)),
+            engine.captures_longest(haystack).unwrap().span(1)
+        );
+
+        // Matches start at cluster boundaries, like for `is_match`.
+        let engine = EngineBuilder::new("\\\u{301}")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        assert!(!engine.is_match("e\u{301}"));
+        assert_eq!(None, engine.find("e\u{301}"));
+        assert!(engine.is_match("\u{301}"));
+        assert_eq!(Some((0, 2)), engine.find("\u{301}").map(|m| m.span()));
     }
 
+    #[cfg(feature = "grapheme")]
     #[test]
-    fn test_paren() {
-        assert!(Engine::new("a(a)a").is_match("aaa"));
-        assert!(Engine::new("aa(a)").is_match("aaa"));
-        assert!(Engine::new("(aa)a").is_match("aaa"));
+    fn test_grapheme_mode_find_iter() {
+        let engine = EngineBuilder::new(".").grapheme_mode(true).build().unwrap();
+        let haystack = "e\u{301}a👍🏽";
+        let spans = engine
+            .find_iter(haystack)
+            .map(|m| m.span())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(0, 3), (3, 4), (4, 12)], spans);
+        assert_eq!("xxx", engine.replace_all(haystack, "x"));
 
-        assert!(!Engine::new("a(a)a").is_match("aaaa"));
-        assert!(!Engine::new("aa(a)").is_match("aaaa"));
-        assert!(!Engine::new("(aa)a").is_match("aaaa"));
+        let engine = EngineBuilder::new("a(.)")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        let haystack = "ae\u{301}ae\u{301}";
+        assert!(engine.is_match(haystack));
+        let spans = engine
+            .find_iter(haystack)
+            .map(|m| m.span())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(0, 4), (4, 8)], spans);
+        assert_eq!("[e\u{301}][e\u{301}]", engine.replace_all(haystack, "[$1]"));
+    }
 
-        assert!(!Engine::new("a(a)a").is_match("aa"));
-        assert!(!Engine::new("aa(a)").is_match("aa"));
-        assert!(!Engine::new("(aa)a").is_match("aa"));
+    #[cfg(feature = "grapheme")]
+    #[test]
+    fn test_grapheme_mode_multiline() {
+        let engine = EngineBuilder::new("a.b")
+            .grapheme_mode(true)
+            .build()
+            .unwrap();
+        let haystack = "ae\u{301}b\na👍🏽b";
+        let spans = engine.find_iter_multiline(haystack).collect::<Vec<_>>();
+        assert_eq!(vec![(0, 5), (6, 16)], spans);
+        assert!(Engine::new("a.b")
+            .unwrap()
+            .find_iter_multiline(haystack)
+            .next()
+            .is_none());
     }
 
     #[test]
-    fn test_or() {
-        assert!(Engine::new("a|b").is_match("a"));
-        assert!(Engine::new("a|b").is_match("b"));
+    fn test_safe_mode() {
+        for (pattern, s) in [
+            ("a*b[^xyz]?(12|24|48)", "xaab24"),
+            ("a*b[^xyz]?(12|24|48)", "aabx12"),
+            ("ab{2,3}c", "abbbc"),
+            ("ab{2,3}c", "abbbbc"),
+            ("(a|b)?c", ""),
+        ] {
+            let engine = Engine::new(pattern).unwrap();
+            let safe = EngineBuilder::new(pattern).safe_mode().build().unwrap();
+            assert_eq!(engine.is_match(s), safe.is_match(s), "{} {}", pattern, s);
+            assert_eq!(engine.is_full_match(s), safe.is_full_match(s));
+        }
 
-        assert!(!Engine::new("a|b").is_match("ba"));
-        assert!(!Engine::new("a|b").is_match("ab"));
-        assert!(!Engine::new("a|b").is_match(""));
+        // Each char is read once by each state at most.
+        let engine = EngineBuilder::new("(a|a)*b").safe_mode().build().unwrap();
+        let haystack = "a".repeat(10_000);
+        let (is_match, steps) = engine.simulate(engine.search_state, &haystack, false);
+        assert!(!is_match);
+        assert!(steps <= (haystack.len() + 1) * (engine.search_state + 1));
+        assert!(!engine.is_full_match(&haystack));
+        assert!(engine.is_match(&(haystack + "b")));
     }
 
+    #[cfg(feature = "grapheme")]
     #[test]
-    fn test_mod_any() {
-        assert!(Engine::new("a*").is_match(""));
-        assert!(Engine::new("a*").is_match("a"));
-        assert!(Engine::new("a*").is_match("aaaaaaaaaaaaaaaaaaaaaa"));
+    fn test_safe_mode_without_graphemes() {
+        let err = EngineBuilder::new("a.")
+            .safe_mode()
+            .grapheme_mode(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(ErrorCode::UnsupportedInSafeMode, err.code());
+    }
 
-        assert!(!Engine::new("a*").is_match("aaaab"));
+    #[test]
+    fn test_complexity_estimate() {
+        for (pattern, complexity) in [
+            ("abc", Complexity::Linear),
+            ("a*b", Complexity::Linear),
+            ("(a|b)*c", Complexity::Linear),
+            ("x*y*", Complexity::Linear),
+            ("a*a*", Complexity::Polynomial),
+            (".*a.*b", Complexity::Polynomial),
+            ("a*[^b]*", Complexity::Polynomial),
+            ("(aa|a)*b", Complexity::Exponential),
+            ("(a|a)*b", Complexity::Exponential),
+            ("(a+)+", Complexity::Exponential),
+            ("(a*)*", Complexity::Exponential),
+            ("a?b", Complexity::Linear),
+            ("(a|b)+", Complexity::Linear),
+            ("(a?)*", Complexity::Exponential),
+        ] {
+            assert_eq!(
+                complexity,
+                Engine::new(pattern).unwrap().complexity_estimate(),
+                "{}",
+                pattern
+            );
+        }
+    }
 
-        assert!(Engine::new("(aaa)*").is_match(""));
-        assert!(Engine::new("(aaa)*").is_match("aaa"));
-        assert!(Engine::new("(aaa)*").is_match("aaaaaa"));
+    #[test]
+    fn test_supported_syntax() {
+        let supported = Engine::supported_syntax();
+        assert!(supported.contains(&SyntaxFeature::Alternation));
+        assert!(supported.contains(&SyntaxFeature::CountedRepetition));
+        assert!(!supported.contains(&SyntaxFeature::Backreferences));
+        assert_eq!(
+            cfg!(feature = "grapheme"),
+            supported.contains(&SyntaxFeature::GraphemeWildcard)
+        );
 
-        assert!(!Engine::new("(aaa)*").is_match("a"));
-        assert!(!Engine::new("(aaa)*").is_match("aa"));
+        // Every supported construct parses.
+        for feature in supported {
+            assert!(
+                EngineBuilder::new(feature.example()).build().is_ok(),
+                "{:?}",
+                feature
+            );
+        }
     }
 
     #[test]
-    fn test_mod_one_or_more() {
-        assert!(Engine::new("a+").is_match("a"));
-        assert!(Engine::new("a+").is_match("aaaa"));
+    fn test_is_nullable() {
+        assert!(Engine::new("a*").unwrap().is_nullable());
+        assert!(Engine::new("a?(b|)").unwrap().is_nullable());
+        assert!(Engine::new("").unwrap().is_nullable());
+        assert!(!Engine::new("a+").unwrap().is_nullable());
+        assert!(!Engine::new("a*b").unwrap().is_nullable());
 
-        assert!(!Engine::new("a+").is_match(""));
-        assert!(!Engine::new("a+").is_match("b"));
-        assert!(!Engine::new("a+").is_match("aab"));
+        let err = EngineBuilder::new("a*")
+            .reject_nullable(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(ErrorCode::NullablePattern, err.code());
+        assert!(EngineBuilder::new("a+")
+            .reject_nullable(true)
+            .build()
+            .is_ok());
+    }
 
-        assert!(Engine::new("(aaa)+").is_match("aaa"));
-        assert!(Engine::new("(aaa)+").is_match("aaaaaaaaa"));
+    #[test]
+    fn test_replace() {
+        let engine = Engine::new("(a+)(b+)").unwrap();
+        assert_eq!("xbaa ab", engine.replace("xaab ab", "$2$1"));
+        assert_eq!("xbaa ba", engine.replace_all("xaab ab", "$2$1"));
+        assert_eq!("x-", engine.replace_all("xab", "-"));
+        assert_eq!("none", engine.replace_all("none", "$1"));
 
-        assert!(!Engine::new("(aaa)+").is_match("aa"));
-        assert!(!Engine::new("(aaa)+").is_match("aab"));
+        let engine = Engine::new("(?P<first>[ab]+)x(?<second>[cd]+)(y)?").unwrap();
+        assert_eq!(
+            "[dc/ab] [c/b]",
+            engine.replace_all("abxdc bxc", "[${second}/$first]")
+        );
+        // Literal dollars, unknown and missing groups.
+        assert_eq!("$$ 5 ${3} ", engine.replace("abxc", "$$$$ 5 $${3}$x$9 $3"));
+        assert_eq!("abc$", engine.replace("abxc", "${1}${2}$"));
+        assert_eq!("${", engine.replace("abxc", "${"));
     }
 
     #[test]
-    fn test_mod_zero_or_one() {
-        assert!(Engine::new("a?").is_match(""));
-        assert!(Engine::new("a?").is_match("a"));
+    fn test_replace_append() {
+        let engine = Engine::new("(a+)(b)").unwrap();
+        let mut out = String::from(">");
+        assert_eq!(
+            2,
+            engine.replace_append("xabyaab", "$2$1", usize::MAX, &mut out)
+        );
+        assert_eq!(">xbaybaa", out);
+        assert_eq!(1, engine.replace_append(" ab ab", "-", 1, &mut out));
+        assert_eq!(">xbaybaa - ab", out);
+        assert_eq!(0, engine.replace_append("xy", "-", usize::MAX, &mut out));
+        assert_eq!(">xbaybaa - abxy", out);
+    }
 
-        assert!(!Engine::new("a?").is_match("aaa"));
-        assert!(!Engine::new("a?").is_match("b"));
+    #[test]
+    fn test_replace_stream() {
+        let engine = Engine::new("(a+)b").unwrap();
+        let mut out = vec![];
+        let count = engine
+            .replace_stream("xaab\nab ab\n\nb".as_bytes(), &mut out, "[$1]")
+            .unwrap();
+        assert_eq!(3, count);
+        assert_eq!("x[aa]\n[a] [a]\n\nb", String::from_utf8(out).unwrap());
+
+        // Matches span lines and chunks, like `replace_all` on the whole input.
+        let haystacks = ["ba\nab", "aab\nab", "xaxbaa", "", "é\u{fffd}ab"];
+        for pattern in ["a[^x]*", "^a|b$", "a*", "(a|ab)(b*)", "x|$", ".b"] {
+            let engine = Engine::new(pattern).unwrap();
+            for haystack in haystacks {
+                for capacity in [1, 2, 3, 64] {
+                    let reader = std::io::BufReader::with_capacity(capacity, haystack.as_bytes());
+                    let mut out = vec![];
+                    engine.replace_stream(reader, &mut out, "<$1>").unwrap();
+                    assert_eq!(
+                        engine.replace_all(haystack, "<$1>"),
+                        String::from_utf8(out).unwrap(),
+                        "{} on {:?} by {}",
+                        pattern,
+                        haystack,
+                        capacity
+                    );
+                }
+            }
+        }
 
-        assert!(Engine::new("(aaa)?").is_match(""));
-        assert!(Engine::new("(aaa)?").is_match("aaa"));
+        let mut out = vec![];
+        Engine::new("b")
+            .unwrap()
+            .replace_stream(&[b'a', 0xff, b'b', 0xc3][..], &mut out, "-")
+            .unwrap();
+        assert_eq!("a\u{fffd}-\u{fffd}", String::from_utf8(out).unwrap());
 
-        assert!(!Engine::new("(aaa)?").is_match("a"));
-        assert!(!Engine::new("(aaa)?").is_match("aa"));
-        assert!(!Engine::new("(aaa)?").is_match("aab"));
+        // Text no match attempt spans is written as it is read, even without line breaks.
+        struct Writes(Vec<usize>);
+        impl std::io::Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let haystack = "xaab".repeat(10_000);
+        let reader = std::io::BufReader::with_capacity(256, haystack.as_bytes());
+        let mut writes = Writes(vec![]);
+        let count = Engine::new("(a+)b")
+            .unwrap()
+            .replace_stream(reader, &mut writes, "$1")
+            .unwrap();
+        assert_eq!(10_000, count);
+        assert_eq!(30_000, writes.0.iter().sum::<usize>());
+        assert!(writes.0.iter().all(|&len| len <= 256));
     }
 
     #[test]
-    fn test_complex() {
-        assert!(Engine::new("cc?|cc").is_match("c"));
+    fn test_end_scanner() {
+        let ends = |engine: &Engine, chunks: &[&str]| {
+            let mut scanner = engine.end_scanner();
+            let mut ends = vec![];
+            for chunk in chunks {
+                scanner.feed(chunk.as_bytes(), |end| ends.push(end));
+            }
+            ends
+        };
 
-        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?").is_match(""));
-        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?").is_match("aaa"));
-        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?").is_match("ac"));
-        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?").is_match("acc"));
-        assert!(Engine::new("a*(bb|cc?|(aaa|cd+c|d+))?").is_match("acdddddc"));
+        let engine = Engine::new("a+|ab").unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5], ends(&engine, &["aab", "aa"]));
+        assert_eq!(vec![1, 2], ends(&engine.anchored_view(), &["ab", "aa"]));
+        assert_eq!(
+            vec![0, 1, 2],
+            ends(&Engine::new("x*").unwrap(), &["", "ab"])
+        );
     }
 
     #[test]
-    fn test_char_group() {
-        assert!(Engine::new("ab[cd]").is_match("abc"));
-        assert!(Engine::new("ab[cd]").is_match("abd"));
-
-        assert!(!Engine::new("ab[cd]").is_match("abe"));
-        assert!(!Engine::new("ab[cd]").is_match("abcd"));
+    fn test_checkpoint() {
+        let engine = Engine::new("ab+cd").unwrap();
+        let mut matcher = engine.stream_matcher();
+        assert_eq!(None, matcher.feed("xxab".as_bytes()));
+        // Split inside 'é', so the pending bytes are saved too.
+        assert_eq!(None, matcher.feed(&"bb\u{e9}".as_bytes()[..3]));
+        let bytes = matcher.checkpoint().to_bytes();
 
-        assert!(Engine::new("ab[cd]*").is_match("ab"));
-        assert!(Engine::new("ab[cd]*").is_match("abc"));
-        assert!(Engine::new("ab[cd]*").is_match("abccccc"));
-        assert!(Engine::new("ab[cd]*").is_match("abddccdccc"));
+        // Resumed by another engine of the same pattern.
+        let other = Engine::new("ab+cd").unwrap();
+        let checkpoint = Checkpoint::from_bytes(&bytes).unwrap();
+        assert_eq!(6, checkpoint.offset());
+        let mut resumed = other.resume(&checkpoint).unwrap();
+        assert_eq!(None, resumed.feed(&"\u{e9}".as_bytes()[1..]));
+        assert_eq!(None, resumed.feed("abbc".as_bytes()));
+        assert_eq!(Some((8, 13)), resumed.feed("d".as_bytes()));
 
-        assert!(!Engine::new("ab[cd]*").is_match("abddccdcccr"));
+        assert!(Engine::new("ab+ce").unwrap().resume(&checkpoint).is_err());
+        assert!(engine.anchored_view().resume(&checkpoint).is_err());
+        assert!(Checkpoint::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Checkpoint::from_bytes(&[&[2, 0, 0, 0], &bytes[4..]].concat()).is_err());
     }
 
     #[test]
-    fn test_negated_char_group() {
-        assert!(Engine::new("a[^bc]d").is_match("aed"));
-        assert!(Engine::new("a[^bc]d").is_match("aad"));
-        assert!(Engine::new("a[^bc]d").is_match("add"));
-
-        assert!(!Engine::new("a[^bc]d").is_match("abd"));
-        assert!(!Engine::new("a[^bc]d").is_match("acd"));
-        assert!(!Engine::new("a[^bc]d").is_match("ad"));
+    fn test_replace_first_n() {
+        let engine = Engine::new("ab*").unwrap();
+        assert_eq!(
+            ("x-x-abb-a".to_string(), 2),
+            engine.replace_first_n("a-ab-abb-a", "x", 2)
+        );
+        assert_eq!(
+            ("x-x-x-x".to_string(), 4),
+            engine.replace_first_n("a-ab-abb-a", "x", 10)
+        );
+        assert_eq!(
+            ("a-ab".to_string(), 0),
+            engine.replace_first_n("a-ab", "x", 0)
+        );
+        assert_eq!(
+            ("-b-b-".to_string(), 3),
+            Engine::new("a*").unwrap().replace_first_n("bb", "-", 3)
+        );
+        // Group references are expanded.
+        assert_eq!(
+            ("x[a]xa".to_string(), 1),
+            Engine::new("a").unwrap().replace_first_n("xaxa", "[$0]", 1)
+        );
     }
 
     #[test]
-    fn test_mod_range() {
-        assert!(Engine::new("ab{3}c").is_match("abbbc"));
-
-        assert!(!Engine::new("ab{3}c").is_match("abbc"));
-        assert!(!Engine::new("ab{3}c").is_match("abbbbc"));
+    fn test_count_lines_matching() {
+        let input = "abc\nxbx\nabbc\n\nac";
+        assert_eq!(
+            2,
+            Engine::new("ab+c")
+                .unwrap()
+                .count_lines_matching(input.as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            3,
+            Engine::new("b")
+                .unwrap()
+                .count_lines_matching(input.as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            5,
+            Engine::new("")
+                .unwrap()
+                .count_lines_matching(input.as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            2,
+            Engine::new("c$")
+                .unwrap()
+                .count_lines_matching("abc\r\nc\r\ncx".as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            0,
+            Engine::new("b(b)")
+                .unwrap()
+                .count_lines_matching("b\nxbx\nab".as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            Engine::new("a(?:b)c")
+                .unwrap()
+                .count_lines_matching(input.as_bytes())
+                .unwrap()
+        );
+        assert_eq!(
+            0,
+            Engine::from_table("start q0\naccept q1\nq0 d q1")
+                .unwrap()
+                .count_lines_matching(input.as_bytes())
+                .unwrap()
+        );
+    }
 
-        assert!(Engine::new("ab{1,3}c").is_match("abc"));
-        assert!(Engine::new("ab{1,3}c").is_match("abbc"));
-        assert!(Engine::new("ab{1,3}c").is_match("abbbc"));
+    #[test]
+    fn test_deeply_nested() {
+        let depth = 10_000;
+        let pattern = "(.".repeat(depth) + &")".repeat(depth);
+        let engine = Engine::new(&pattern).unwrap();
 
-        assert!(!Engine::new("ab{1,3}c").is_match("ac"));
-        assert!(!Engine::new("ab{1,3}c").is_match("abbbbc"));
+        assert!(engine.is_full_match(&"a".repeat(depth)));
+        assert!(!engine.is_full_match(&"a".repeat(depth - 1)));
     }
 }
@@ -0,0 +1,166 @@
+use crate::engine::*;
+use crate::grep::unescape;
+use std::io::{BufRead, Write};
+
+// Column names of the rows of `table`: the name of each capture group or its index for unnamed
+// groups, eg: `ip` and `2` for `(?P<ip>[0-9.]+)-([0-9]+)`. A pattern without groups has the whole
+// match as its only column, named `0`.
+pub fn header(engine: &Engine) -> Vec<String> {
+    if engine.capture_count() == 0 {
+        return vec!["0".to_string()];
+    }
+
+    engine
+        .group_names()
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(group, name)| name.clone().unwrap_or_else(|| group.to_string()))
+        .collect()
+}
+
+// A row of the groups of the first match of each matching line, in the order of `header`. Groups
+// taking no part in the match are empty, lines without a match have no row.
+pub fn table<'a>(
+    engine: &'a Engine,
+    mut reader: impl BufRead + 'a,
+) -> impl Iterator<Item = std::io::Result<Vec<String>>> + 'a {
+    let mut line = vec![];
+    std::iter::from_fn(move || loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(err) => return Some(Err(err)),
+        }
+
+        let content = line_content(&line);
+        let content = String::from_utf8_lossy(content);
+        let Some(caps) = engine.captures(&content) else {
+            continue;
+        };
+        let groups = if engine.capture_count() == 0 {
+            0..1
+        } else {
+            1..caps.len()
+        };
+        return Some(Ok(groups
+            .map(|group| caps.get(group).map_or("", |found| found.slice).to_string())
+            .collect()));
+    })
+}
+
+// Writes the rows of `table` as CSV, after a row of the `header`. Returns the number of rows
+// without the header.
+pub fn write_csv(
+    engine: &Engine,
+    reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    write_csv_row(&header(engine), out)?;
+    let mut count = 0;
+    for row in table(engine, reader) {
+        write_csv_row(&row?, out)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Writes the first match of each matching line as the template with its group references filled
+// in, like `grep::grep_format` writes every match, eg: `$user\t$code` for TSV columns without a
+// header. Returns the number of written lines.
+pub fn write_format(
+    engine: &Engine,
+    template: &str,
+    mut reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let template = unescape(template);
+    let mut line = vec![];
+    let mut formatted = String::new();
+    let mut count = 0;
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let content = line_content(&line);
+        let content = String::from_utf8_lossy(content);
+        if let Some(caps) = engine.captures(&content) {
+            caps.expand(&template, &mut formatted);
+            writeln!(out, "{}", formatted)?;
+            formatted.clear();
+            count += 1;
+        }
+        line.clear();
+    }
+
+    Ok(count)
+}
+
+// Fields with a comma, a quote or a line break are quoted, with their quotes doubled (RFC 4180).
+fn write_csv_row(row: &[String], out: &mut impl Write) -> std::io::Result<()> {
+    let fields = row
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", fields.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::extract::*;
+
+    #[test]
+    fn test_table() {
+        let engine = Engine::new(r"(?P<user>\w+)-(?P<status>\d+)(?:-(\w+))?").unwrap();
+        assert_eq!(vec!["user", "status", "3"], header(&engine));
+
+        let rows = table(&engine, "x ann-200-ok\nnothing\r\nbob-404\r\n".as_bytes())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(vec![vec!["ann", "200", "ok"], vec!["bob", "404", ""]], rows);
+
+        let engine = Engine::new("a+").unwrap();
+        assert_eq!(vec!["0"], header(&engine));
+        let rows = table(&engine, "baa\nb".as_bytes())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(vec![vec!["aa"]], rows);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let engine = Engine::new(r"(?P<key>\w+)\=(?P<value>[^;]*)\;").unwrap();
+        let mut out = vec![];
+        assert_eq!(
+            2,
+            write_csv(&engine, "a=1,2;\nb=\"q\";\nc".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!(
+            "key,value\na,\"1,2\"\nb,\"\"\"q\"\"\"\n",
+            String::from_utf8_lossy(&out)
+        );
+    }
+
+    #[test]
+    fn test_write_format() {
+        let engine = Engine::new(r"(?P<key>\w+)\=(\d+)").unwrap();
+        let mut out = vec![];
+        assert_eq!(
+            2,
+            write_format(
+                &engine,
+                "$2\\t${key}",
+                "a=1 b=2\nc\nd=3".as_bytes(),
+                &mut out
+            )
+            .unwrap()
+        );
+        assert_eq!("1\ta\n3\td\n", String::from_utf8_lossy(&out));
+    }
+}
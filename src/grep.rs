@@ -0,0 +1,656 @@
+use crate::engine::*;
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+// How often `--follow` looks for appended data.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+// Line by line search over chunks of input. Chunks may end anywhere, the partial line at the end
+// of a chunk continues with the next chunk.
+pub struct LineGrep<'a> {
+    engine: &'a Engine,
+    line: Vec<u8>,
+    matcher: StreamMatcher<'a>,
+    matched_lines: usize,
+    // A `\r` ending the last chunk, only fed to the matcher when no `\n` follows it.
+    pending_cr: bool,
+}
+
+impl<'a> LineGrep<'a> {
+    pub fn new(engine: &'a Engine) -> LineGrep<'a> {
+        LineGrep {
+            engine,
+            line: vec![],
+            matcher: engine.stream_matcher(),
+            matched_lines: 0,
+            pending_cr: false,
+        }
+    }
+
+    // Writes the matching complete lines of the chunk. Lines end with `\n` or `\r\n`, like for
+    // `line_content`.
+    pub fn feed(&mut self, chunk: &[u8], out: &mut impl Write) -> std::io::Result<()> {
+        for part in chunk.split_inclusive(|&b| b == b'\n') {
+            let (content, is_complete) = match part.strip_suffix(b"\n") {
+                Some(content) => (content, true),
+                None => (part, false),
+            };
+
+            if std::mem::take(&mut self.pending_cr) && part != b"\n" {
+                self.matcher.feed(b"\r");
+            }
+            let content = match content.strip_suffix(b"\r") {
+                Some(stripped) => {
+                    self.pending_cr = !is_complete;
+                    stripped
+                }
+                None => content,
+            };
+            self.matcher.feed(content);
+            self.line.extend_from_slice(part);
+
+            if is_complete {
+                self.end_line(out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Ends the last line when the input does not end with a newline.
+    pub fn finish(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        if !self.line.is_empty() {
+            self.line.push(b'\n');
+            self.end_line(out)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn matched_lines(&self) -> usize {
+        self.matched_lines
+    }
+
+    fn end_line(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        // `$` holds once the line ended.
+        if self.matcher.finish().is_some() {
+            out.write_all(&self.line)?;
+            self.matched_lines += 1;
+        }
+
+        self.line.clear();
+        self.matcher = self.engine.stream_matcher();
+        self.pending_cr = false;
+        Ok(())
+    }
+}
+
+// Writes the matching lines of the reader. Returns the number of matching lines.
+pub fn grep(
+    engine: &Engine,
+    mut reader: impl Read,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut line_grep = LineGrep::new(engine);
+    let mut buf = [0; 8 * 1024];
+
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        line_grep.feed(&buf[..len], out)?;
+    }
+
+    line_grep.finish(out)?;
+    Ok(line_grep.matched_lines())
+}
+
+// Searches the whole input at once, so matches can span lines. Writes the lines each match touches,
+// lines shared by several matches once. Returns the number of matches.
+pub fn grep_multiline(
+    engine: &Engine,
+    mut reader: impl Read,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let haystack = String::from_utf8_lossy(&bytes);
+
+    let mut count = 0;
+    let mut written_to = 0;
+    for (start, end) in engine.find_iter_multiline(&haystack) {
+        count += 1;
+
+        let line_start = haystack[..start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1)
+            .max(written_to);
+        let line_end = match haystack[end..].find('\n') {
+            Some(i) => end + i + 1,
+            None => haystack.len(),
+        };
+        if line_start < line_end {
+            out.write_all(haystack[line_start..line_end].as_bytes())?;
+            written_to = line_end;
+        }
+    }
+
+    if !haystack[..written_to].ends_with('\n') && written_to > 0 {
+        out.write_all(b"\n")?;
+    }
+    Ok(count)
+}
+
+// A match with where it was found, so consumers don't have to scan the source again.
+#[derive(Debug, PartialEq)]
+pub struct SourcedMatch {
+    pub path: String,
+    // Starting with 1, like editors do.
+    pub line: usize,
+    // Byte offset of the match in the source, counting invalid UTF-8 by its bytes, not by the
+    // U+FFFD it reads as.
+    pub offset: usize,
+    pub text: String,
+}
+
+// Matches of the lines of a named source, see `SourcedMatch`.
+pub struct SourcedMatches<'a, R> {
+    engine: &'a Engine,
+    path: String,
+    reader: R,
+    line: Vec<u8>,
+    line_number: usize,
+    line_offset: usize,
+    pending: VecDeque<SourcedMatch>,
+}
+
+impl<'a, R: BufRead> SourcedMatches<'a, R> {
+    pub fn new(engine: &'a Engine, path: &str, reader: R) -> SourcedMatches<'a, R> {
+        SourcedMatches {
+            engine,
+            path: path.into(),
+            reader,
+            line: vec![],
+            line_number: 0,
+            line_offset: 0,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for SourcedMatches<'_, R> {
+    type Item = std::io::Result<SourcedMatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            self.line.clear();
+            let len = match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(len) => len,
+                Err(err) => return Some(Err(err)),
+            };
+            self.line_number += 1;
+
+            let raw_content = line_content(&self.line);
+            let content = String::from_utf8_lossy(raw_content);
+            for (start, end) in self.engine.find_iter_multiline(&content) {
+                self.pending.push_back(SourcedMatch {
+                    path: self.path.clone(),
+                    line: self.line_number,
+                    offset: self.line_offset + raw_offset(raw_content, start),
+                    text: content[start..end].into(),
+                });
+            }
+
+            self.line_offset += len;
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+// Offset in the raw bytes of an offset in their `String::from_utf8_lossy` text, where each run of
+// invalid bytes is a 3 byte U+FFFD.
+fn raw_offset(raw: &[u8], text_offset: usize) -> usize {
+    let (mut raw_pos, mut text_pos) = (0, 0);
+    for chunk in raw.utf8_chunks() {
+        let valid_end = text_pos + chunk.valid().len();
+        if text_offset <= valid_end {
+            return raw_pos + text_offset - text_pos;
+        }
+        raw_pos += chunk.valid().len() + chunk.invalid().len();
+        text_pos = valid_end;
+        if !chunk.invalid().is_empty() {
+            text_pos += char::REPLACEMENT_CHARACTER.len_utf8();
+        }
+    }
+    raw_pos
+}
+
+// Writes each match as `path:line:offset:text`. Returns the number of matches.
+pub fn grep_sourced(
+    engine: &Engine,
+    path: &str,
+    reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    for found in SourcedMatches::new(engine, path, reader) {
+        let found = found?;
+        writeln!(
+            out,
+            "{}:{}:{}:{}",
+            found.path, found.line, found.offset, found.text
+        )?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Writes the text of each non-empty match of each line on a line of its own, like `grep -o`.
+// Returns the number of written matches.
+pub fn grep_only_matching(
+    engine: &Engine,
+    reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut count = 0;
+    for found in SourcedMatches::new(engine, "", reader) {
+        let found = found?;
+        if !found.text.is_empty() {
+            writeln!(out, "{}", found.text)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+// Writes the text of the group of each non-empty match of each line on a line of its own, like
+// `grep -o` for the group. Matches the group took no part in are skipped. Returns the number of
+// written groups.
+pub fn grep_group(
+    engine: &Engine,
+    group: usize,
+    mut reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut line = vec![];
+    let mut count = 0;
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let content = line_content(&line);
+        let content = String::from_utf8_lossy(content);
+        for found in engine.find_iter(&content) {
+            if found.slice.is_empty() {
+                continue;
+            }
+            if let Some(text) = engine.captures_of(&content, found).get(group) {
+                writeln!(out, "{}", text.slice)?;
+                count += 1;
+            }
+        }
+        line.clear();
+    }
+
+    Ok(count)
+}
+
+// Writes each non-empty match of each line as the template with its group references filled in,
+// see `Captures::expand`, eg: `$1\t$2` for TSV columns. `\t`, `\n` and `\\` in the template are a
+// tab, a newline and a backslash. Returns the number of written matches.
+pub fn grep_format(
+    engine: &Engine,
+    template: &str,
+    mut reader: impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let template = unescape(template);
+    let mut line = vec![];
+    let mut formatted = String::new();
+    let mut count = 0;
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let content = line_content(&line);
+        let content = String::from_utf8_lossy(content);
+        for found in engine.find_iter(&content) {
+            if found.slice.is_empty() {
+                continue;
+            }
+            engine
+                .captures_of(&content, found)
+                .expand(&template, &mut formatted);
+            writeln!(out, "{}", formatted)?;
+            formatted.clear();
+            count += 1;
+        }
+        line.clear();
+    }
+
+    Ok(count)
+}
+
+// The template of `grep_format` with its `\t`, `\n` and `\\` escapes turned into the chars.
+pub(crate) fn unescape(template: &str) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('t' | 'n' | '\\'))) => {
+                chars.next();
+                out.push(match escaped {
+                    't' => '\t',
+                    'n' => '\n',
+                    _ => '\\',
+                });
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Splits the input into records at the matches of the separator, or at blank lines (paragraphs)
+// without a separator. Empty records are skipped.
+pub fn records<'h>(haystack: &'h str, separator: Option<&Engine>) -> Vec<&'h str> {
+    let mut bounds = vec![];
+    match separator {
+        Some(separator) => {
+            let mut start = 0;
+            for (sep_start, sep_end) in separator.find_iter_multiline(haystack) {
+                bounds.push((start, sep_start));
+                start = sep_end;
+            }
+            bounds.push((start, haystack.len()));
+        }
+        None => {
+            let mut start = 0;
+            let mut end = 0;
+            for line in haystack.split_inclusive('\n') {
+                if line.trim().is_empty() {
+                    bounds.push((start, end));
+                    start = end + line.len();
+                }
+                end += line.len();
+            }
+            bounds.push((start, end));
+        }
+    }
+
+    bounds
+        .into_iter()
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &haystack[start..end])
+        .collect()
+}
+
+// Matches each record as a unit, see `records`. Writes the matching records with a blank line
+// between them. Returns the number of matching records.
+pub fn grep_records(
+    engine: &Engine,
+    mut reader: impl Read,
+    separator: Option<&Engine>,
+    out: &mut impl Write,
+) -> std::io::Result<usize> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    let haystack = String::from_utf8_lossy(&bytes);
+
+    let mut count = 0;
+    for record in records(&haystack, separator) {
+        if !engine.is_match(record) {
+            continue;
+        }
+
+        if count > 0 {
+            out.write_all(b"\n")?;
+        }
+        out.write_all(record.as_bytes())?;
+        if !record.ends_with('\n') {
+            out.write_all(b"\n")?;
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Writes the matching lines of the file, then keeps polling it for appended data like `tail -f`.
+// Starts over when the file gets truncated (eg: by log rotation).
+pub fn follow_file(engine: &Engine, path: &str, out: &mut impl Write) -> std::io::Result<usize> {
+    let mut file = std::fs::File::open(path)?;
+    let mut line_grep = LineGrep::new(engine);
+    let mut buf = [0; 8 * 1024];
+
+    loop {
+        let len = file.read(&mut buf)?;
+        if len > 0 {
+            line_grep.feed(&buf[..len], out)?;
+            continue;
+        }
+
+        out.flush()?;
+        thread::sleep(FOLLOW_INTERVAL);
+
+        let position = file.stream_position()?;
+        if file.metadata()?.len() < position {
+            file.seek(SeekFrom::Start(0))?;
+            line_grep = LineGrep::new(engine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grep::*;
+
+    #[test]
+    fn test_line_grep() {
+        let engine = Engine::new("b+c").unwrap();
+        let mut line_grep = LineGrep::new(&engine);
+        let mut out = vec![];
+
+        // Lines split between chunks.
+        for chunk in ["ab", "c\nxy", "z\nbb", "bc\n\nb", "c"] {
+            line_grep.feed(chunk.as_bytes(), &mut out).unwrap();
+        }
+        assert_eq!("abc\nbbbc\n", String::from_utf8_lossy(&out));
+
+        line_grep.finish(&mut out).unwrap();
+        assert_eq!("abc\nbbbc\nbc\n", String::from_utf8_lossy(&out));
+        assert_eq!(3, line_grep.matched_lines());
+
+        // CRLF lines, the `\r` split from its `\n` or not.
+        let engine = Engine::new("c$").unwrap();
+        let mut line_grep = LineGrep::new(&engine);
+        let mut out = vec![];
+        for chunk in ["abc\r", "\nc\r", "x\nc\r\n"] {
+            line_grep.feed(chunk.as_bytes(), &mut out).unwrap();
+        }
+        assert_eq!("abc\r\nc\r\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_grep() {
+        let engine = Engine::new("a.c").unwrap();
+        let mut out = vec![];
+
+        assert_eq!(1, grep(&engine, "abc\nac\n".as_bytes(), &mut out).unwrap());
+        assert_eq!("abc\n", String::from_utf8_lossy(&out));
+
+        // `$` holds at the end of each line, with or without the last newline.
+        let engine = Engine::new("a$").unwrap();
+        let mut out = vec![];
+        assert_eq!(2, grep(&engine, "xa\nab\nba".as_bytes(), &mut out).unwrap());
+        assert_eq!("xa\nba\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_grep_multiline() {
+        let engine = Engine::new("b.c").unwrap();
+        let mut out = vec![];
+
+        assert_eq!(
+            3,
+            grep_multiline(&engine, "xx\nab\ncd\nbxcbyc".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!("ab\ncd\nbxcbyc\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_grep_only_matching() {
+        let mut out = vec![];
+        assert_eq!(
+            3,
+            grep_only_matching(
+                &Engine::new("b*c").unwrap(),
+                "abcbbc\nx\nc".as_bytes(),
+                &mut out
+            )
+            .unwrap()
+        );
+        assert_eq!("bc\nbbc\nc\n", String::from_utf8_lossy(&out));
+
+        // Empty matches are not written.
+        let mut out = vec![];
+        assert_eq!(
+            1,
+            grep_only_matching(&Engine::new("b*").unwrap(), "xbbx".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!("bb\n", String::from_utf8_lossy(&out));
+
+        // The `\r` of CRLF input is part of the line break.
+        let mut out = vec![];
+        grep_only_matching(&Engine::new("b.*").unwrap(), "abc\r\n".as_bytes(), &mut out).unwrap();
+        assert_eq!("bc\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_grep_group() {
+        let engine = Engine::new("([a-z]+)-(\\d+)?").unwrap();
+        let mut out = vec![];
+        assert_eq!(
+            2,
+            grep_group(&engine, 2, "a-1 b- c-22\nx".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!("1\n22\n", String::from_utf8_lossy(&out));
+
+        let mut out = vec![];
+        assert_eq!(
+            3,
+            grep_group(&engine, 1, "a-1 b- c-22\nx".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!("a\nb\nc\n", String::from_utf8_lossy(&out));
+
+        let mut out = vec![];
+        assert_eq!(
+            2,
+            grep_group(&engine, 2, "a-1\r\nb-22\r\n".as_bytes(), &mut out).unwrap()
+        );
+        assert_eq!("1\n22\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_grep_format() {
+        let engine = Engine::new("(?P<key>[a-z]+)-([0-9]+)").unwrap();
+        let mut out = vec![];
+        assert_eq!(
+            3,
+            grep_format(
+                &engine,
+                "$2\\t${key}",
+                "a-1 b-22\nx\nc-3".as_bytes(),
+                &mut out
+            )
+            .unwrap()
+        );
+        assert_eq!("1\ta\n22\tb\n3\tc\n", String::from_utf8_lossy(&out));
+
+        let engine = Engine::new("(\\w+)$").unwrap();
+        let mut out = vec![];
+        grep_format(&engine, "<$1>", "a b\r\nc\r\n".as_bytes(), &mut out).unwrap();
+        assert_eq!("<b>\n<c>\n", String::from_utf8_lossy(&out));
+
+        assert_eq!("a\tb\nc\\t$1", unescape("a\\tb\\nc\\\\t$1"));
+    }
+
+    #[test]
+    fn test_records() {
+        assert_eq!(
+            vec!["a\nb\n", "c\n", "d"],
+            records("a\nb\n\n  \nc\n\nd", None)
+        );
+        assert_eq!(
+            vec!["a", "b", "c"],
+            records("axbxxxcx", Some(&Engine::new("xx*").unwrap()))
+        );
+        // Empty separators split between chars, but not right after a separator.
+        assert_eq!(
+            vec!["a", "b", "c"],
+            records("axbc", Some(&Engine::new("x*").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_grep_records() {
+        let engine = Engine::new("b.c").unwrap();
+        let mut out = vec![];
+
+        assert_eq!(
+            2,
+            grep_records(&engine, "ab\ncd\n\nbc\n\nbxc".as_bytes(), None, &mut out).unwrap()
+        );
+        assert_eq!("ab\ncd\n\nbxc\n", String::from_utf8_lossy(&out));
+    }
+
+    #[test]
+    fn test_sourced_matches() {
+        let engine = Engine::new("b+c").unwrap();
+        let matches = SourcedMatches::new(&engine, "log.txt", "abc\nxx\nbcbbc".as_bytes())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        let mut out = vec![];
+        grep_sourced(&engine, "log.txt", "xbc\nbc".as_bytes(), &mut out).unwrap();
+        assert_eq!(
+            "log.txt:1:1:bc\nlog.txt:2:4:bc\n",
+            String::from_utf8_lossy(&out)
+        );
+
+        assert_eq!(
+            vec![
+                SourcedMatch {
+                    path: "log.txt".into(),
+                    line: 1,
+                    offset: 1,
+                    text: "bc".into(),
+                },
+                SourcedMatch {
+                    path: "log.txt".into(),
+                    line: 3,
+                    offset: 7,
+                    text: "bc".into(),
+                },
+                SourcedMatch {
+                    path: "log.txt".into(),
+                    line: 3,
+                    offset: 9,
+                    text: "bbc".into(),
+                },
+            ],
+            matches
+        );
+
+        // Offsets count the invalid bytes, not the U+FFFD they read as.
+        let mut out = vec![];
+        grep_sourced(&engine, "log.txt", &b"\xff\xfebc\n\xffx bc"[..], &mut out).unwrap();
+        assert_eq!(
+            "log.txt:1:2:bc\nlog.txt:2:8:bc\n",
+            String::from_utf8_lossy(&out)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Haystacks the matcher runs on, read as a sequence of chars.
+pub trait Input {
+    fn chars(self) -> impl Iterator<Item = char>;
+}
+
+impl Input for &str {
+    fn chars(self) -> impl Iterator<Item = char> {
+        str::chars(self)
+    }
+}
+
+impl Input for &String {
+    fn chars(self) -> impl Iterator<Item = char> {
+        str::chars(self)
+    }
+}
+
+// Invalid UTF-8 sequences are read as U+FFFD, like `String::from_utf8_lossy` does.
+impl Input for &[u8] {
+    fn chars(self) -> impl Iterator<Item = char> {
+        self.utf8_chunks().flat_map(|chunk| {
+            let replacement = (!chunk.invalid().is_empty()).then_some(char::REPLACEMENT_CHARACTER);
+            chunk.valid().chars().chain(replacement)
+        })
+    }
+}
+
+// Chars from an iterator, eg: `CharIter("ab".chars().rev())`.
+pub struct CharIter<I>(pub I);
+
+impl<I: Iterator<Item = char>> Input for CharIter<I> {
+    fn chars(self) -> impl Iterator<Item = char> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::input::*;
+
+    #[test]
+    fn test_chars() {
+        assert_eq!(
+            "aé".chars().collect::<Vec<_>>(),
+            Input::chars("aé").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!['a', '\u{fffd}', 'b', 'é'],
+            Input::chars(&b"a\xffb\xc3\xa9"[..]).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!['b', 'a'],
+            CharIter("ab".chars().rev()).chars().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Toy regular expression engine. `Regex` is the entry point, the public modules have the full API:
+// - `syntax`: the parser and the pattern tree
+// - `nfa`: the automata patterns compile to
+// - `dfa`: the deterministic form of the automata
+// - `meta`: the engines and their matches, eg: `meta::EngineBuilder` for the options
+// - `util`: the errors and the inputs
+// These are stable, the modules behind them are internal and keep changing. The other public
+// modules are the tools built on them, eg: `grep`.
+
+#[cfg(feature = "check")]
+pub mod check;
+pub mod compat;
+#[cfg(test)]
+mod conformance;
+pub mod dfa;
+mod engine;
+pub mod extract;
+pub mod grep;
+mod input;
+#[cfg(feature = "tokio")]
+pub mod lines;
+pub mod meta;
+pub mod nfa;
+mod optimizer;
+mod parser;
+mod pike;
+#[cfg(feature = "playground")]
+pub mod playground;
+pub mod recipes;
+mod scratch;
+mod set;
+pub mod syntax;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+mod types;
+pub mod util;
+
+pub use crate::meta::{Captures, Engine, EngineBuilder, EngineSet, Match};
+pub use crate::syntax::{Parser, PatternSection};
+pub use crate::util::{ErrorCode, Input, RegexError, Span};
+
+pub type Error = RegexError;
+
+// A compiled pattern, eg:
+//
+//     let regex = Regex::new("ab+c")?;
+//     assert!(regex.is_match("xabbc"));
+#[derive(Debug)]
+pub struct Regex {
+    pattern: String,
+    engine: Engine,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            engine: EngineBuilder::new(pattern).build()?,
+        })
+    }
+
+    // Whether the pattern matches anywhere within the haystack.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.engine.is_match(haystack)
+    }
+
+    // Leftmost-first match anywhere within the haystack, the first one `replace_all` replaces.
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.engine.find(haystack)
+    }
+
+    // The match of `find` with the spans of its capture groups.
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        self.engine.captures(haystack)
+    }
+
+    // Replaces every match, with `$1` or `${name}` referring to the groups of the match.
+    pub fn replace_all(&self, haystack: &str, replacement: &str) -> String {
+        self.engine.replace_all(haystack, replacement)
+    }
+
+    // Whether the pattern matches the whole haystack.
+    pub fn is_full_match(&self, haystack: &str) -> bool {
+        self.engine.is_full_match(haystack)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // The engine behind, for the rest of the matching API.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_regex() {
+        let regex = Regex::new("ab+c").unwrap();
+        assert!(regex.is_match("xabbc"));
+        assert!(!regex.is_match("xac"));
+        assert_eq!(Some("abbc"), regex.find("xabbcx").map(|m| m.slice));
+        let caps = Regex::new("a(b+)c").unwrap().captures("xabbc").unwrap();
+        assert_eq!(Some("bb"), caps.get(1).map(|m| m.slice));
+        assert_eq!("x-abbc-", regex.replace_all("xabbc", "-$0-"));
+        assert!(regex.is_full_match("abc"));
+        assert!(!regex.is_full_match("xabc"));
+        assert_eq!("ab+c", regex.as_str());
+
+        // `find` takes the match `replace_all` replaces first.
+        let regex = Regex::new("a|ab").unwrap();
+        assert_eq!(Some("a"), regex.find("ab").map(|m| m.slice));
+        assert_eq!("-b", regex.replace_all("ab", "-"));
+
+        assert_eq!(
+            ErrorCode::UnterminatedGroup,
+            Regex::new("a(b").unwrap_err().code()
+        );
+    }
+
+    // Downstream code depends on these paths, they don't move with the internals.
+    #[test]
+    fn test_api_modules() {
+        let pattern = syntax::Parser::parse("a[bc]").unwrap();
+        let (transition, _) = pattern.to_transition(nfa::START_STATE, 1);
+        assert_eq!(3, dfa::CharClasses::new(&transition).count());
+
+        let engine: meta::Engine = meta::EngineBuilder::new("a[bc]").build().unwrap();
+        assert_eq!(3, util::Input::chars("xab").count());
+        assert!(engine.is_match_input("xab"));
+        assert!(matches!(
+            meta::Engine::new("a(").unwrap_err().code(),
+            util::ErrorCode::UnterminatedGroup
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use crate::engine::*;
+use crate::types::*;
+use tokio::io::{AsyncBufRead, Lines};
+
+// Yields the matching lines of an async line stream. The next line is only read when the
+// consumer asks for the next match, so a slow consumer holds back the reader.
+pub struct MatchingLines<'a, R> {
+    engine: &'a Engine,
+    lines: Lines<R>,
+}
+
+impl<'a, R: AsyncBufRead + Unpin> MatchingLines<'a, R> {
+    pub fn new(engine: &'a Engine, lines: Lines<R>) -> MatchingLines<'a, R> {
+        MatchingLines { engine, lines }
+    }
+
+    // The next matching line with the byte span of its first match, as `Engine::find` reports it,
+    // None at the end of the stream.
+    pub async fn next_line(&mut self) -> std::io::Result<Option<(String, Span)>> {
+        while let Some(line) = self.lines.next_line().await? {
+            if let Some(found) = self.engine.find(&line) {
+                let span = found.span();
+                return Ok(Some((line, span)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn into_inner(self) -> Lines<R> {
+        self.lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lines::*;
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+    use tokio::io::AsyncBufReadExt;
+
+    #[test]
+    fn test_matching_lines() {
+        let engine = Engine::new("b+c").unwrap();
+        let mut lines = MatchingLines::new(&engine, b"abc\nxyz\nbbbc\n\nbc".lines());
+        let mut context = Context::from_waker(Waker::noop());
+
+        let mut found = vec![];
+        // Reading a slice never waits, so each poll completes.
+        while let Poll::Ready(Ok(Some(line))) = std::pin::pin!(lines.next_line()).poll(&mut context)
+        {
+            found.push(line);
+        }
+
+        assert_eq!(
+            vec![
+                ("abc".to_string(), (1, 3)),
+                ("bbbc".to_string(), (0, 4)),
+                ("bc".to_string(), (0, 2)),
+            ],
+            found
+        );
+
+        let engine = Engine::new("b$").unwrap();
+        let mut lines = MatchingLines::new(&engine, b"ab\nbc\nb".lines());
+        let mut found = vec![];
+        while let Poll::Ready(Ok(Some(line))) = std::pin::pin!(lines.next_line()).poll(&mut context)
+        {
+            found.push(line);
+        }
+        assert_eq!(
+            vec![("ab".to_string(), (1, 2)), ("b".to_string(), (0, 1))],
+            found
+        );
+
+        // The leftmost match, not the one ending first.
+        let engine = Engine::new("bcd|c").unwrap();
+        let mut lines = MatchingLines::new(&engine, b"abcd\nc".lines());
+        let mut found = vec![];
+        while let Poll::Ready(Ok(Some(line))) = std::pin::pin!(lines.next_line()).poll(&mut context)
+        {
+            found.push(line);
+        }
+        assert_eq!(
+            vec![("abcd".to_string(), (1, 4)), ("c".to_string(), (0, 1))],
+            found
+        );
+    }
+}
@@ -1,21 +1,416 @@
-mod engine;
-mod parser;
-mod types;
+mod config;
 
-use crate::engine::*;
+use crate::config::*;
+use regexp::meta::*;
+use regexp::syntax::*;
+use regexp::*;
+use std::io::BufRead;
+
+const USAGE: &str = "\
+./bin PATTERN                    prints the automaton in DOT
+./bin PATTERN STRING             prints whether the pattern matches within / fully matches the string
+./bin explain PATTERN            describes the pattern in English
+./bin table PATTERN              prints the automaton as a sorted transition table
+./bin lint PATTERN               prints the warnings of the pattern, exits with 1 when there are any
+./bin --stats PATTERN            prints the pattern and automaton sizes after each compilation pass
+./bin grep [OPTIONS] PATTERN [FILE]
+                                 prints the matching lines, see the README for the options
+./bin replace PATTERN REPLACEMENT [FILE]
+                                 replaces the matches, `$1` or `${name}` refer to the groups
+./bin extract [--format TEMPLATE] PATTERN [FILE]
+                                 prints the groups of the first match of each line as CSV rows,
+                                 or as the template with `$1` or `${name}` filled in
+./bin classify RULES [FILE]      labels each line by the first matching `pattern => label` rule
+./bin --man                      prints this help as a man page
+";
+
+fn help() -> String {
+    format!("Usage:\n{}\nSyntax:\n{}", USAGE, Parser::syntax_help())
+}
+
+// The help in roff, eg: `./bin --man > regexp.1`.
+fn man_page() -> String {
+    let mut out =
+        String::from(".TH REGEXP 1\n.SH NAME\nregexp \\- toy regular expression engine\n");
+    out.push_str(".SH SYNOPSIS\n.nf\n");
+    for line in USAGE.lines() {
+        // `\&` keeps lines starting with `.` from being read as requests.
+        out.push_str(&format!("\\&{}\n", line.replace('\\', "\\\\")));
+    }
+    out.push_str(".fi\n.SH SYNTAX\n");
+    for item in SYNTAX {
+        out.push_str(&format!(".TP\n.B {}\n{}\n", item.example, item.description));
+    }
+    out
+}
 
 fn main() {
     let args = std::env::args().collect::<Vec<_>>();
-    let eng = Engine::new(args[1].as_str());
+
+    if args.len() < 2 || args[1] == "--help" || args[1] == "-h" {
+        print!("{}", help());
+        return;
+    }
+
+    if args[1] == "--man" {
+        print!("{}", man_page());
+        return;
+    }
+
+    #[cfg(feature = "check")]
+    if args.len() == 4 && args[1] == "check" {
+        match check::check(args[2].as_str(), args[3].as_str()) {
+            Some(report) => {
+                print!("{}", report);
+                std::process::exit(1);
+            }
+            None => println!("ok"),
+        }
+        return;
+    }
+
+    if (3..=4).contains(&args.len()) && args[1] == "classify" {
+        std::process::exit(run_classify(&args[2], args.get(3)));
+    }
+
+    if args.len() >= 3 && args[1] == "grep" {
+        std::process::exit(run_grep(&args[2..]));
+    }
+
+    if (4..=5).contains(&args.len()) && args[1] == "replace" {
+        std::process::exit(run_replace(&args[2], &args[3], args.get(4)));
+    }
+
+    if args.len() >= 3 && args[1] == "extract" {
+        std::process::exit(run_extract(&args[2..]));
+    }
+
+    if args.len() == 3 && args[1] == "explain" {
+        println!("{}", build_engine(&args[2]).explain());
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "lint" {
+        std::process::exit(run_lint(&args[2]));
+    }
+
+    if args.len() == 3 && args[1] == "--stats" {
+        print!("{}", build_engine(&args[2]).stats());
+        return;
+    }
+
+    if args.len() == 3 && args[1] == "table" {
+        build_engine(&args[2]).dump_table();
+        return;
+    }
+
+    let eng = build_engine(&args[1]);
 
     if args.len() == 2 {
         eng.dump_dot();
     } else if args.len() == 3 {
-        dbg!(eng.is_match(args[2].as_str()));
+        println!("is_match: {}", eng.is_match(args[2].as_str()));
+        println!("is_full_match: {}", eng.is_full_match(args[2].as_str()));
     } else {
-        panic!(
-            "Invalid call with {} args. Do ./bin PATTERN or ./bin PATTERN STRING",
-            args.len()
-        )
+        panic!("Invalid call with {} args.\n{}", args.len(), help())
+    }
+}
+
+// Exits with the error and its likely fix on an invalid pattern.
+fn build_engine(pattern: &str) -> Engine {
+    match EngineBuilder::new(pattern).build() {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("error[{}]: {}", err.code().as_str(), err);
+            if let Some(suggestion) = err.suggestion() {
+                eprintln!("help: {}", suggestion);
+            }
+            std::process::exit(2);
+        }
+    }
+}
+
+// `lint PATTERN`, prints the warnings of a valid pattern with their likely fixes.
+fn run_lint(pattern: &str) -> i32 {
+    build_engine(pattern);
+    let warnings = Parser::lint(pattern);
+    for warning in &warnings {
+        println!(
+            "warning[{}]: {} at {:?}",
+            warning.code.as_str(),
+            warning.message,
+            warning.span
+        );
+        if let Some(suggestion) = Parser::suggest(pattern, warning) {
+            println!("help: {}", suggestion);
+        }
+    }
+    i32::from(!warnings.is_empty())
+}
+
+// `grep [--no-config] [--follow] [--multiline | --paragraph | --record-separator SEP | -o |
+// --group N | --format TEMPLATE] PATTERN [FILE]` or `grep --with-source PATTERN [FILE...]`, exits
+// with 0 when anything matched, like grep. Defaults come from the config file, see `Config`.
+fn run_grep(mut args: &[String]) -> i32 {
+    let no_config = args.first().is_some_and(|arg| arg == "--no-config");
+    if no_config {
+        args = &args[1..];
     }
+
+    let mut follow = false;
+    // The flags choosing how matches are printed, only one can be given.
+    let mut modes: Vec<&str> = vec![];
+    let mut separator = None;
+    let mut format = None;
+    let mut group = None;
+    while let [flag, rest @ ..] = args {
+        match flag.as_str() {
+            "--follow" => follow = true,
+            "--multiline" | "--paragraph" | "--with-source" => modes.push(flag.as_str()),
+            "-o" | "--only-matching" => modes.push("-o"),
+            "--record-separator" => {
+                let [pattern, rest @ ..] = rest else {
+                    return usage_error("--record-separator needs a SEP pattern");
+                };
+                separator = Some(build_engine(pattern));
+                modes.push("--record-separator");
+                args = rest;
+                continue;
+            }
+            "--format" => {
+                let [template, rest @ ..] = rest else {
+                    return usage_error("--format needs a TEMPLATE");
+                };
+                format = Some(template.as_str());
+                modes.push("--format");
+                args = rest;
+                continue;
+            }
+            "--group" => {
+                let Some((index, rest)) = rest
+                    .split_first()
+                    .and_then(|(index, rest)| Some((index.parse::<usize>().ok()?, rest)))
+                else {
+                    return usage_error("--group needs a group index N");
+                };
+                group = Some(index);
+                modes.push("--group");
+                args = rest;
+                continue;
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+
+    // The modes of the command line override the ones of the config file, and `--follow` only
+    // prints whole lines, so the file is only read without either of them.
+    if modes.is_empty() && !follow && !no_config {
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 2;
+            }
+        };
+        let config_modes = [
+            (config.multiline, "--multiline"),
+            (config.paragraph, "--paragraph"),
+            (config.only_matching, "-o"),
+            (config.with_source, "--with-source"),
+            (config.record_separator.is_some(), "--record-separator"),
+        ];
+        modes = config_modes
+            .into_iter()
+            .filter_map(|(is_set, mode)| is_set.then_some(mode))
+            .collect();
+        separator = config.record_separator.as_deref().map(build_engine);
+    }
+    // Giving a flag twice is no conflict.
+    if let Some(other) = modes.iter().find(|mode| **mode != modes[0]) {
+        return usage_error(&format!("{} can't be combined with {}", modes[0], other));
+    }
+    if let (true, Some(mode)) = (follow, modes.first()) {
+        return usage_error(&format!(
+            "--follow prints whole lines, it can't be combined with {}",
+            mode
+        ));
+    }
+    let multiline = modes.contains(&"--multiline");
+    let paragraph = modes.contains(&"--paragraph");
+    let with_source = modes.contains(&"--with-source");
+    let only_matching = modes.contains(&"-o");
+
+    let Some(pattern) = args.first() else {
+        return usage_error("grep needs a PATTERN");
+    };
+    let engine = build_engine(pattern);
+    if let Some(group) = group.filter(|&group| group > engine.capture_count()) {
+        return usage_error(&format!(
+            "--group {} is not a group of the pattern, it has {}",
+            group,
+            engine.capture_count()
+        ));
+    }
+    let mut out = std::io::stdout().lock();
+    if (with_source || multiline) && engine.is_nullable() {
+        eprintln!("warning: the pattern matches the empty string, so it matches at every position");
+    }
+    if with_source {
+        return exit_code(grep_sourced_files(&engine, &args[1..], &mut out));
+    }
+
+    let is_record_mode = paragraph || separator.is_some();
+    let grep_reader = |reader: &mut dyn std::io::Read, out: &mut std::io::StdoutLock| {
+        if let Some(group) = group {
+            grep::grep_group(&engine, group, std::io::BufReader::new(reader), out)
+        } else if let Some(template) = format {
+            grep::grep_format(&engine, template, std::io::BufReader::new(reader), out)
+        } else if is_record_mode {
+            grep::grep_records(&engine, reader, separator.as_ref(), out)
+        } else if only_matching {
+            grep::grep_only_matching(&engine, std::io::BufReader::new(reader), out)
+        } else if multiline {
+            grep::grep_multiline(&engine, reader, out)
+        } else {
+            grep::grep(&engine, reader, out)
+        }
+    };
+
+    let result = match (args.get(1), follow) {
+        (Some(path), true) => grep::follow_file(&engine, path, &mut out),
+        (Some(path), false) => {
+            std::fs::File::open(path).and_then(|mut file| grep_reader(&mut file, &mut out))
+        }
+        (None, true) => return usage_error("--follow needs a FILE"),
+        (None, false) => grep_reader(&mut std::io::stdin().lock(), &mut out),
+    };
+
+    exit_code(result)
+}
+
+// Exits with 2 like on other invalid calls, eg: on a flag without its value.
+fn usage_error(message: &str) -> i32 {
+    eprintln!("error: {}\n\nUsage:\n{}", message, USAGE);
+    2
+}
+
+fn grep_sourced_files(
+    engine: &Engine,
+    paths: &[String],
+    out: &mut std::io::StdoutLock,
+) -> std::io::Result<usize> {
+    if paths.is_empty() {
+        return grep::grep_sourced(engine, "<stdin>", std::io::stdin().lock(), out);
+    }
+
+    let mut count = 0;
+    for path in paths {
+        let file = std::fs::File::open(path)?;
+        count += grep::grep_sourced(engine, path, std::io::BufReader::new(file), out)?;
+    }
+    Ok(count)
+}
+
+fn exit_code(result: std::io::Result<usize>) -> i32 {
+    match result {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            2
+        }
+    }
+}
+
+// `replace PATTERN REPLACEMENT [FILE]`, writes the file (or stdin) with the matches replaced.
+fn run_replace(pattern: &str, replacement: &str, path: Option<&String>) -> i32 {
+    let engine = build_engine(pattern);
+    let mut out = std::io::stdout().lock();
+    let result = match path {
+        Some(path) => std::fs::File::open(path).and_then(|file| {
+            engine.replace_stream(std::io::BufReader::new(file), &mut out, replacement)
+        }),
+        None => engine.replace_stream(std::io::stdin().lock(), &mut out, replacement),
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            2
+        }
+    }
+}
+
+// `extract [--format TEMPLATE] PATTERN [FILE]`, writes the groups of the file (or stdin) as CSV
+// with a header row of the group names, see `extract::write_csv`, or as the template like
+// `grep --format`, see `extract::write_format`.
+fn run_extract(mut args: &[String]) -> i32 {
+    let mut format = None;
+    if let [flag, rest @ ..] = args {
+        if flag == "--format" {
+            let [template, rest @ ..] = rest else {
+                return usage_error("--format needs a TEMPLATE");
+            };
+            format = Some(template.as_str());
+            args = rest;
+        }
+    }
+    let (pattern, path) = match args {
+        [pattern] => (pattern, None),
+        [pattern, path] => (pattern, Some(path)),
+        [] => return usage_error("extract needs a PATTERN"),
+        _ => return usage_error("extract takes a PATTERN and at most one FILE"),
+    };
+
+    let engine = build_engine(pattern);
+    let mut out = std::io::stdout().lock();
+    let write = |reader: &mut dyn BufRead, out: &mut std::io::StdoutLock| match format {
+        Some(template) => extract::write_format(&engine, template, reader, out),
+        None => extract::write_csv(&engine, reader, out),
+    };
+    let result = match path {
+        Some(path) => std::fs::File::open(path)
+            .and_then(|file| write(&mut std::io::BufReader::new(file), &mut out)),
+        None => write(&mut std::io::stdin().lock(), &mut out),
+    };
+
+    exit_code(result)
+}
+
+// `classify RULES [FILE]`, prints the label of each line (`-` without a matching rule) + the line.
+fn run_classify(rules_path: &str, path: Option<&String>) -> i32 {
+    let set = match EngineSet::load(rules_path) {
+        Ok(set) => set,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 2;
+        }
+    };
+
+    let reader: Box<dyn BufRead> = match path {
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => Box::new(std::io::BufReader::new(file)),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                return 2;
+            }
+        },
+        None => Box::new(std::io::stdin().lock()),
+    };
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("{}", err);
+                return 2;
+            }
+        };
+        let label = set.classify(&line).map_or("-", |label| label.as_str());
+        println!("{}\t{}", label, line);
+    }
+
+    0
 }
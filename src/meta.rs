@@ -0,0 +1,11 @@
+// Stable API of the engines, which pick how to match (eg: the set simulation in safe mode), and
+// what they find.
+
+pub use crate::engine::{EndScanner, Engine, EngineBuilder, FindIter, Split, StreamMatcher};
+pub use crate::set::{EngineSet, SetMatch, SetScanner, SetStats, SharedEngineSet};
+pub use crate::types::{
+    BoundedMatch, CaptureParseError, Captures, Checkpoint, Complexity, DeadlineMatch, EngineStats,
+    FromCaptures, Match, MatchOutcome, ScanEvent, ValidationError,
+};
+#[cfg(feature = "derive")]
+pub use regexp_derive::FromCaptures;
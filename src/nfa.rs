@@ -0,0 +1,6 @@
+// Stable API of the automata patterns compile to, eg: for exporting or inspecting them.
+
+pub use crate::types::{
+    AutomatonSize, CharClass, CharClassInterner, ClassRange, CompileBudget, DotOptions, LeftT,
+    RankDir, State, Tag, Transition, TransitionAndEndState, START_STATE,
+};
@@ -0,0 +1,283 @@
+use crate::parser::*;
+use crate::types::*;
+
+pub struct Optimizer;
+
+impl Optimizer {
+    // Factors the common prefix out of adjacent alternation branches, eg: `abc|abd|abe` becomes
+    // `ab(c|d|e)`. Branch order is kept. Capturing groups are never factored, each captures on its
+    // own.
+    pub fn factor_prefixes(pattern: PatternSection) -> PatternSection {
+        Optimizer::rewrite_bottom_up(pattern, |section| match section {
+            PatternSection::Or(list, m, span) => Optimizer::factor_or(list, m, span),
+            other => other,
+        })
+    }
+
+    fn factor_or(list: Vec<PatternSection>, m: Quantifier, span: SourceSpan) -> PatternSection {
+        let branches = list
+            .into_iter()
+            .map(Optimizer::to_units)
+            .collect::<Vec<_>>();
+
+        let mut groups: Vec<Vec<Vec<PatternSection>>> = vec![];
+        for branch in branches {
+            match groups.last_mut() {
+                Some(group)
+                    if !branch.is_empty()
+                        && !branch[0].has_captures()
+                        && group[0].first() == branch.first() =>
+                {
+                    group.push(branch)
+                }
+                _ => groups.push(vec![branch]),
+            }
+        }
+
+        let mut new_list = groups
+            .into_iter()
+            .map(|group| {
+                if group.len() == 1 {
+                    return Optimizer::from_units(group.into_iter().next().unwrap());
+                }
+
+                let prefix_len = Optimizer::common_prefix_len(&group);
+                let mut prefix = vec![];
+                let mut suffixes = vec![];
+                for (i, mut branch) in group.into_iter().enumerate() {
+                    let suffix = branch.split_off(prefix_len);
+                    if i == 0 {
+                        prefix = branch;
+                    }
+                    suffixes.push(Optimizer::from_units(suffix));
+                }
+
+                // The factored alternation spans the branches it came from.
+                let suffixes_span = SourceSpan::of(&suffixes);
+                prefix.push(Optimizer::factor_or(
+                    suffixes,
+                    Quantifier::ONE,
+                    suffixes_span,
+                ));
+                Optimizer::from_units(prefix)
+            })
+            .collect::<Vec<_>>();
+
+        if new_list.len() == 1 && m == Quantifier::ONE {
+            new_list.pop().unwrap()
+        } else if new_list.len() == 1 && *new_list[0].get_mod() == Quantifier::ONE {
+            new_list.pop().unwrap().with_mod(m)
+        } else {
+            PatternSection::Or(new_list, m, span)
+        }
+    }
+
+    fn common_prefix_len(group: &[Vec<PatternSection>]) -> usize {
+        let first = &group[0];
+        let mut len = 0;
+
+        while len < first.len()
+            && !first[len].has_captures()
+            && group.iter().all(|branch| branch.get(len) == first.get(len))
+        {
+            len += 1;
+        }
+
+        len
+    }
+
+    // Flattens a branch into the sequence of sections it matches one after the other.
+    fn to_units(section: PatternSection) -> Vec<PatternSection> {
+        match section {
+            PatternSection::Literal(s, Quantifier::ONE, SourceSpan((start, _))) => s
+                .char_indices()
+                .map(|(i, c)| {
+                    let span = SourceSpan((start + i, start + i + c.len_utf8()));
+                    PatternSection::Char(c, Quantifier::ONE, span)
+                })
+                .collect(),
+            PatternSection::And(list, Quantifier::ONE, _) => {
+                list.into_iter().flat_map(Optimizer::to_units).collect()
+            }
+            other => vec![other],
+        }
+    }
+
+    fn from_units(mut units: Vec<PatternSection>) -> PatternSection {
+        if units.len() == 1 {
+            return units.pop().unwrap();
+        }
+
+        Parser::fold_literals(units)
+    }
+
+    // Rebuilds the tree from the leaves up without recursion, so deeply nested patterns are safe.
+    fn rewrite_bottom_up(
+        pattern: PatternSection,
+        mut f: impl FnMut(PatternSection) -> PatternSection,
+    ) -> PatternSection {
+        enum Step {
+            Enter(PatternSection),
+            // Is-or + mod + span + child count.
+            Exit(bool, Quantifier, SourceSpan, usize),
+            ExitGroup(Quantifier, GroupKind, SourceSpan),
+        }
+
+        let mut steps = vec![Step::Enter(pattern)];
+        let mut done: Vec<PatternSection> = vec![];
+
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Enter(PatternSection::And(list, m, span)) => {
+                    steps.push(Step::Exit(false, m, span, list.len()));
+                    steps.extend(list.into_iter().rev().map(Step::Enter));
+                }
+                Step::Enter(PatternSection::Or(list, m, span)) => {
+                    steps.push(Step::Exit(true, m, span, list.len()));
+                    steps.extend(list.into_iter().rev().map(Step::Enter));
+                }
+                Step::Enter(PatternSection::Group(section, m, kind, span)) => {
+                    steps.push(Step::ExitGroup(m, kind, span));
+                    steps.push(Step::Enter(*section));
+                }
+                Step::Enter(leaf) => done.push(f(leaf)),
+                Step::Exit(is_or, m, span, count) => {
+                    let list = done.split_off(done.len() - count);
+                    let section = if is_or {
+                        PatternSection::Or(list, m, span)
+                    } else {
+                        PatternSection::And(list, m, span)
+                    };
+                    done.push(f(section));
+                }
+                Step::ExitGroup(m, kind, span) => {
+                    let section = done.pop().expect("Missing group section");
+                    done.push(f(PatternSection::Group(Box::new(section), m, kind, span)));
+                }
+            }
+        }
+
+        done.pop().expect("Missing rewritten pattern")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::optimizer::*;
+
+    #[test]
+    fn test_factor_prefixes() {
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Literal("ab".into(), Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Or(
+                        vec![
+                            PatternSection::Char('c', Quantifier::ONE, SourceSpan::default()),
+                            PatternSection::Char('d', Quantifier::ONE, SourceSpan::default()),
+                            PatternSection::Char('e', Quantifier::ONE, SourceSpan::default()),
+                        ],
+                        Quantifier::ONE,
+                        SourceSpan::default()
+                    ),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Optimizer::factor_prefixes(Parser::parse("abc|abd|abe").unwrap()),
+        );
+
+        assert_eq!(
+            PatternSection::Group(
+                Box::new(PatternSection::And(
+                    vec![
+                        PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Or(
+                            vec![
+                                PatternSection::And(vec![], Quantifier::ONE, SourceSpan::default()),
+                                PatternSection::And(
+                                    vec![
+                                        PatternSection::Char(
+                                            'b',
+                                            Quantifier::ONE,
+                                            SourceSpan::default()
+                                        ),
+                                        PatternSection::Or(
+                                            vec![
+                                                PatternSection::Char(
+                                                    'c',
+                                                    Quantifier::ONE,
+                                                    SourceSpan::default()
+                                                ),
+                                                PatternSection::Char(
+                                                    'd',
+                                                    Quantifier::ONE,
+                                                    SourceSpan::default()
+                                                ),
+                                            ],
+                                            Quantifier::ONE,
+                                            SourceSpan::default()
+                                        ),
+                                    ],
+                                    Quantifier::ONE,
+                                    SourceSpan::default()
+                                ),
+                            ],
+                            Quantifier::ONE,
+                            SourceSpan::default()
+                        ),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                )),
+                Quantifier::ANY,
+                GroupKind::Capturing,
+                SourceSpan::default()
+            ),
+            Optimizer::factor_prefixes(Parser::parse("(a|abc|abd)*").unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_factor_prefixes_spans() {
+        let pattern = Optimizer::factor_prefixes(Parser::parse("xabc|xabd").unwrap());
+        assert_eq!((0, 9), pattern.span());
+
+        let PatternSection::And(items, _, _) = &pattern else {
+            panic!("Expected an AND: {:?}", pattern);
+        };
+        assert_eq!(
+            vec![(0, 3), (3, 9)],
+            items
+                .iter()
+                .map(|section| section.span())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_factor_prefixes_keeps_order() {
+        assert_eq!(
+            Parser::parse("ab|cd|ae").unwrap(),
+            Optimizer::factor_prefixes(Parser::parse("ab|cd|ae").unwrap()),
+        );
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Char('a', Quantifier::ANY, SourceSpan::default()),
+                    PatternSection::Or(
+                        vec![
+                            PatternSection::Char('b', Quantifier::ONE, SourceSpan::default()),
+                            PatternSection::Char('c', Quantifier::ONE, SourceSpan::default()),
+                        ],
+                        Quantifier::ONE,
+                        SourceSpan::default()
+                    ),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Optimizer::factor_prefixes(Parser::parse("a*b|a*c").unwrap()),
+        );
+    }
+}
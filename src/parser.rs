@@ -1,24 +1,314 @@
 use crate::types::*;
+use std::iter::Peekable;
+use std::str::CharIndices;
 
 pub struct Parser;
 
+// Everything the parser accepts, eg: for the help text. Kept in sync with the parser by the tests.
+pub const SYNTAX: &[SyntaxItem] = &[
+    SyntaxItem {
+        example: "a",
+        kind: TokenKind::Literal,
+        description: "a letter or digit matches itself",
+    },
+    SyntaxItem {
+        example: ".",
+        kind: TokenKind::MetaChar,
+        description: "any character",
+    },
+    SyntaxItem {
+        example: "[ab]",
+        kind: TokenKind::Class,
+        description: "one of the characters",
+    },
+    SyntaxItem {
+        example: "[^ab]",
+        kind: TokenKind::Class,
+        description: "any character except the listed ones",
+    },
+    SyntaxItem {
+        example: "\\.",
+        kind: TokenKind::Literal,
+        description: "a symbol matches itself when escaped, eg: \\* or \\\\",
+    },
+    SyntaxItem {
+        example: "\\d",
+        kind: TokenKind::Class,
+        description:
+            "an ASCII digit, \\w an ASCII word char and \\s an ASCII space, \\D \\W \\S negate them",
+    },
+    SyntaxItem {
+        example: "[a-z0]",
+        kind: TokenKind::Class,
+        description: "a character between a and z, or 0",
+    },
+    SyntaxItem {
+        example: "^a$",
+        kind: TokenKind::MetaChar,
+        description: "the start and the end of the input",
+    },
+    SyntaxItem {
+        example: "a|b",
+        kind: TokenKind::MetaChar,
+        description: "either side",
+    },
+    SyntaxItem {
+        example: "(ab)",
+        kind: TokenKind::GroupOpen,
+        description: "group, eg: for quantifiers and alternations",
+    },
+    SyntaxItem {
+        example: "(?:ab)",
+        kind: TokenKind::GroupOpen,
+        description: "non-capturing group",
+    },
+    SyntaxItem {
+        example: "(?P<name>ab)",
+        kind: TokenKind::GroupOpen,
+        description: "named group, also (?<name>ab)",
+    },
+    SyntaxItem {
+        example: "a?",
+        kind: TokenKind::Quantifier,
+        description: "optional",
+    },
+    SyntaxItem {
+        example: "a*",
+        kind: TokenKind::Quantifier,
+        description: "zero or more",
+    },
+    SyntaxItem {
+        example: "a+",
+        kind: TokenKind::Quantifier,
+        description: "one or more",
+    },
+    SyntaxItem {
+        example: "a{2}",
+        kind: TokenKind::Quantifier,
+        description: "exactly 2",
+    },
+    SyntaxItem {
+        example: "a{1,3}",
+        kind: TokenKind::Quantifier,
+        description: "between 1 and 3",
+    },
+    SyntaxItem {
+        example: "a{2,}",
+        kind: TokenKind::Quantifier,
+        description: "2 or more",
+    },
+    SyntaxItem {
+        example: "a+?",
+        kind: TokenKind::Quantifier,
+        description: "lazy, as few as possible in find and replace, the longest in find_longest",
+    },
+];
+
 impl Parser {
-    pub fn parse(raw: &str) -> PatternSection {
+    // The error is of the first invalid part of the pattern, with its span and a likely fix.
+    pub fn parse(raw: &str) -> Result<PatternSection, RegexError> {
+        let (pattern, diagnostics) = Parser::parse_lenient(raw);
+        if let Some(diagnostic) = diagnostics.first() {
+            pattern.drop_flat();
+            let suggestion = Parser::suggest(raw, diagnostic);
+            return Err(RegexError::from(diagnostic).with_suggestion(suggestion));
+        }
+
+        Ok(pattern)
+    }
+
+    // Never fails: invalid parts of the pattern become error sections and are reported as
+    // diagnostics, so a partially typed pattern can still be displayed.
+    pub fn parse_lenient(raw: &str) -> (PatternSection, Vec<Diagnostic>) {
+        let (pattern, diagnostics, _) = Parser::parse_with_tokens(raw);
+        (pattern, diagnostics)
+    }
+
+    // Classifies each part of the pattern the way the parser interprets it, eg: for highlighting.
+    pub fn tokenize(raw: &str) -> Vec<Token> {
+        let (pattern, _, tokens) = Parser::parse_with_tokens(raw);
+        pattern.drop_flat();
+        tokens
+    }
+
+    // Warnings of the pattern (parsed leniently, see `parse_lenient` for the errors), eg: parts
+    // that only slow down searches.
+    pub fn lint(raw: &str) -> Vec<Diagnostic> {
+        let (pattern, _) = Parser::parse_lenient(raw);
+        let warnings = pattern
+            .redundant_wildcards()
+            .into_iter()
+            .map(|span| Diagnostic::new(span, ErrorCode::RedundantWildcard))
+            .collect();
+        pattern.drop_flat();
+        warnings
+    }
+
+    // Likely fix of a diagnostic of the pattern, eg: "use \\+ to match a literal '+'".
+    pub fn suggest(raw: &str, diagnostic: &Diagnostic) -> Option<String> {
+        let (start, end) = diagnostic.span;
+        let first = raw[start..].chars().next()?;
+
+        let suggestion = match diagnostic.code {
+            ErrorCode::NothingToRepeat | ErrorCode::UnmatchedParen => format!(
+                "use {} to match a literal '{}'",
+                Parser::escape(&raw[start..end]),
+                &raw[start..end]
+            ),
+            ErrorCode::UnexpectedCharacter => {
+                format!("use \\{} to match a literal '{}'", first, first)
+            }
+            ErrorCode::UnterminatedClass => {
+                format!("add ']' to close the char group opened at offset {}", start)
+            }
+            ErrorCode::UnterminatedGroup => {
+                format!("add ')' to close the '(' opened at offset {}", start)
+            }
+            ErrorCode::UnterminatedRange => {
+                "add '}' to close the range, or use \\{ to match a literal '{'".into()
+            }
+            ErrorCode::InvalidRange => "the range needs min <= max and max >= 1, eg: {0,2}".into(),
+            ErrorCode::InvalidNumber => {
+                "a range takes numbers, eg: {2} or {1,3}, use \\{ to match a literal '{'".into()
+            }
+            ErrorCode::InvalidGroupName => {
+                "a group name takes letters, digits and '_', eg: (?P<year>\\d+)".into()
+            }
+            ErrorCode::DuplicateGroupName => {
+                format!("rename one of the '{}' groups", &raw[start..end])
+            }
+            ErrorCode::InvalidEscape => {
+                "escape symbols, eg: \\. for a dot, or use the classes \\d \\w \\s \\D \\W \\S"
+                    .into()
+            }
+            ErrorCode::InvalidClassRange => {
+                let last = raw[start..end].chars().last()?;
+                format!("put the lower char first: '{}-{}'", last, first)
+            }
+            ErrorCode::RedundantWildcard => format!(
+                "remove '{}', searches find matches anywhere already",
+                &raw[start..end]
+            ),
+            ErrorCode::CompileBudgetExceeded
+            | ErrorCode::NullablePattern
+            | ErrorCode::UnsupportedInSafeMode
+            | ErrorCode::PatternTooLarge
+            | ErrorCode::TooManyCaptureGroups => return None,
+        };
+
+        Some(suggestion)
+    }
+
+    // The raw text with a backslash before each symbol, so it matches literally, eg: `\{2\}`.
+    fn escape(raw: &str) -> String {
+        let mut out = String::with_capacity(raw.len());
+        for c in raw.chars() {
+            if !c.is_alphanumeric() {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    // Syntax reference lines of `SYNTAX`, eg: "  a{1,3}   between 1 and 3".
+    pub fn syntax_help() -> String {
+        let width = SYNTAX
+            .iter()
+            .map(|item| item.example.len())
+            .max()
+            .unwrap_or(0);
+
+        SYNTAX
+            .iter()
+            .map(|item| {
+                format!(
+                    "  {:width$}   {}\n",
+                    item.example,
+                    item.description,
+                    width = width
+                )
+            })
+            .collect()
+    }
+
+    // Nesting of the parenthesized groups, numbered in the order they are opened.
+    pub fn group_tree(raw: &str) -> Vec<CaptureGroup> {
+        let mut roots = vec![];
+        // None for non-capturing groups.
+        let mut open: Vec<Option<CaptureGroup>> = vec![];
+        let mut count = 0;
+
+        let close = |open: &mut Vec<Option<CaptureGroup>>, roots: &mut Vec<CaptureGroup>, end| {
+            let Some(mut group) = open.pop().expect("Missing open group") else {
+                return;
+            };
+            group.span.1 = end;
+            match open.iter_mut().rev().flatten().next() {
+                Some(parent) => parent.children.push(group),
+                None => roots.push(group),
+            }
+        };
+
+        for ((start, end), kind) in Parser::tokenize(raw) {
+            match kind {
+                TokenKind::GroupOpen if &raw[start..end] == "(?:" => open.push(None),
+                TokenKind::GroupOpen => {
+                    count += 1;
+                    open.push(Some(CaptureGroup {
+                        index: count,
+                        span: (start, end),
+                        children: vec![],
+                    }));
+                }
+                TokenKind::GroupClose => close(&mut open, &mut roots, end),
+                _ => {}
+            }
+        }
+
+        while !open.is_empty() {
+            close(&mut open, &mut roots, raw.len());
+        }
+
+        roots
+    }
+
+    fn parse_with_tokens(raw: &str) -> (PatternSection, Vec<Diagnostic>, Vec<Token>) {
+        let mut tokens = vec![];
         let mut stack: Vec<PatternSection> = vec![];
         let mut ops: Vec<Op> = vec![];
+        // Start + kind of the open groups.
+        let mut paren_starts: Vec<(usize, GroupKind)> = vec![];
+        let mut group_names = vec![];
+        let mut diagnostics = vec![];
 
         let mut need_and = false;
-        let mut idx = 0usize;
 
-        let mut raw_it = raw.chars();
-        while let Some(c) = raw_it.next() {
-            if let Some(pattern_mod) = Mod::from(&c) {
-                Parser::inject_mod(&mut stack, pattern_mod);
+        let mut raw_it = raw.char_indices().peekable();
+        while let Some((idx, c)) = raw_it.next() {
+            if let Some(pattern_mod) = Quantifier::from(&c) {
+                let (pattern_mod, end) = Parser::parse_lazy(&mut raw_it, pattern_mod, idx + 1);
+                tokens.push(((idx, end), TokenKind::Quantifier));
+                if !need_and {
+                    diagnostics.push(Diagnostic::new((idx, end), ErrorCode::NothingToRepeat));
+                    Parser::push_operand(
+                        &mut stack,
+                        &mut ops,
+                        &mut need_and,
+                        PatternSection::Error(Quantifier::ONE, SourceSpan((idx, idx))),
+                    );
+                }
+                Parser::inject_mod(&mut stack, pattern_mod, end);
             } else if c == '|' {
-                Parser::collapse_stacks(&mut stack, &mut ops, |op| match op {
-                    Some(Op::And) => false,
-                    _ => true,
-                });
+                tokens.push(((idx, idx + 1), TokenKind::MetaChar));
+                if !need_and {
+                    stack.push(PatternSection::And(
+                        vec![],
+                        Quantifier::ONE,
+                        SourceSpan((idx, idx)),
+                    ));
+                }
+                Parser::collapse_stacks(&mut stack, &mut ops, |op| !matches!(op, Some(Op::And)));
                 ops.push(Op::Or);
                 need_and = false;
             } else if c == '(' {
@@ -26,100 +316,360 @@ impl Parser {
                     ops.push(Op::And)
                 }
                 need_and = false;
-                ops.push(Op::Paren)
+                ops.push(Op::Paren);
+                let (kind, end) =
+                    Parser::parse_group_open(raw, idx, &mut group_names, &mut diagnostics);
+                while raw_it.next_if(|(i, _)| *i < end).is_some() {}
+                paren_starts.push((idx, kind));
+                tokens.push(((idx, end), TokenKind::GroupOpen));
             } else if c == ')' {
-                Parser::collapse_stacks(&mut stack, &mut ops, |op| match op {
-                    Some(Op::Paren) => true,
-                    _ => false,
-                });
-                assert_eq!(Some(Op::Paren), ops.pop());
-                if idx < raw.len() - 1 {
-                    need_and = true;
+                let Some((paren_start, kind)) = paren_starts.pop() else {
+                    diagnostics.push(Diagnostic::new((idx, idx + 1), ErrorCode::UnmatchedParen));
+                    tokens.push(((idx, idx + 1), TokenKind::Error));
+                    continue;
+                };
+                tokens.push(((idx, idx + 1), TokenKind::GroupClose));
+                if !need_and {
+                    stack.push(PatternSection::And(
+                        vec![],
+                        Quantifier::ONE,
+                        SourceSpan((idx, idx)),
+                    ));
                 }
+                Parser::collapse_stacks(&mut stack, &mut ops, |op| matches!(op, Some(Op::Paren)));
+                assert_eq!(Some(Op::Paren), ops.pop());
+                Parser::wrap_group(&mut stack, kind, (paren_start, idx + 1));
+                need_and = true;
             } else if c == '[' {
-                let mut next_c = raw_it.next().expect("Missing end of char group");
-                let is_negated = next_c == '^';
-                let mut char_group_chars = vec![];
-
-                if next_c == '^' {
-                    next_c = raw_it.next().expect("Missing end of char group");
+                let section = match Parser::parse_char_group(&mut raw_it, &mut diagnostics) {
+                    Some((ranges, is_negated)) => {
+                        let end = raw_it.peek().map(|(i, _)| *i).unwrap_or(raw.len());
+                        tokens.push(((idx, end), TokenKind::Class));
+                        PatternSection::CharGroup(
+                            ranges,
+                            Quantifier::ONE,
+                            is_negated,
+                            SourceSpan((idx, end)),
+                        )
+                    }
+                    None => {
+                        tokens.push(((idx, raw.len()), TokenKind::Error));
+                        diagnostics.push(Diagnostic::new(
+                            (idx, raw.len()),
+                            ErrorCode::UnterminatedClass,
+                        ));
+                        PatternSection::Error(Quantifier::ONE, SourceSpan((idx, raw.len())))
+                    }
+                };
+                Parser::push_operand(&mut stack, &mut ops, &mut need_and, section);
+            } else if c == '{' {
+                let (range_mod, end) = Parser::parse_range(&mut raw_it, raw.len());
+                match range_mod {
+                    Ok(range_mod) => {
+                        let (range_mod, end) = Parser::parse_lazy(&mut raw_it, range_mod, end);
+                        tokens.push(((idx, end), TokenKind::Quantifier));
+                        if !need_and {
+                            diagnostics
+                                .push(Diagnostic::new((idx, end), ErrorCode::NothingToRepeat));
+                            Parser::push_operand(
+                                &mut stack,
+                                &mut ops,
+                                &mut need_and,
+                                PatternSection::Error(Quantifier::ONE, SourceSpan((idx, idx))),
+                            );
+                        }
+                        Parser::inject_mod(&mut stack, range_mod, end);
+                    }
+                    Err(code) => {
+                        diagnostics.push(Diagnostic::new((idx, end), code));
+                        tokens.push(((idx, end), TokenKind::Error));
+                    }
                 }
-
-                loop {
-                    if next_c == ']' {
-                        break;
+            } else if c == '\\' {
+                let next = raw_it.next();
+                let section = match next.map(|(i, c)| (i, c, Parser::perl_class(c))) {
+                    Some((escaped_idx, escaped, Some((ranges, is_negated)))) => {
+                        let span = (idx, escaped_idx + escaped.len_utf8());
+                        tokens.push((span, TokenKind::Class));
+                        PatternSection::CharGroup(
+                            ranges,
+                            Quantifier::ONE,
+                            is_negated,
+                            SourceSpan(span),
+                        )
+                    }
+                    Some((escaped_idx, escaped, None)) if !escaped.is_alphanumeric() => {
+                        let span = (idx, escaped_idx + escaped.len_utf8());
+                        tokens.push((span, TokenKind::Literal));
+                        PatternSection::Char(escaped, Quantifier::ONE, SourceSpan(span))
+                    }
+                    _ => {
+                        let end = next.map_or(raw.len(), |(i, c)| i + c.len_utf8());
+                        diagnostics.push(Diagnostic::new((idx, end), ErrorCode::InvalidEscape));
+                        tokens.push(((idx, end), TokenKind::Error));
+                        PatternSection::Error(Quantifier::ONE, SourceSpan((idx, end)))
                     }
+                };
+                Parser::push_operand(&mut stack, &mut ops, &mut need_and, section);
+            } else if c == '^' || c == '$' {
+                let anchor = if c == '^' { Anchor::Start } else { Anchor::End };
+                tokens.push(((idx, idx + 1), TokenKind::MetaChar));
+                Parser::push_operand(
+                    &mut stack,
+                    &mut ops,
+                    &mut need_and,
+                    PatternSection::Anchor(anchor, Quantifier::ONE, SourceSpan((idx, idx + 1))),
+                );
+            } else if c == '.' {
+                tokens.push(((idx, idx + 1), TokenKind::MetaChar));
+                Parser::push_operand(
+                    &mut stack,
+                    &mut ops,
+                    &mut need_and,
+                    PatternSection::Any(Quantifier::ONE, SourceSpan((idx, idx + 1))),
+                );
+            } else if c.is_alphanumeric() || c == '-' {
+                tokens.push(((idx, idx + c.len_utf8()), TokenKind::Literal));
+                Parser::push_operand(
+                    &mut stack,
+                    &mut ops,
+                    &mut need_and,
+                    PatternSection::Char(c, Quantifier::ONE, SourceSpan((idx, idx + c.len_utf8()))),
+                );
+            } else {
+                diagnostics.push(Diagnostic::new(
+                    (idx, idx + c.len_utf8()),
+                    ErrorCode::UnexpectedCharacter,
+                ));
+                tokens.push(((idx, idx + c.len_utf8()), TokenKind::Error));
+                Parser::push_operand(
+                    &mut stack,
+                    &mut ops,
+                    &mut need_and,
+                    PatternSection::Error(Quantifier::ONE, SourceSpan((idx, idx + c.len_utf8()))),
+                );
+            }
+        }
+
+        loop {
+            if !need_and && !ops.is_empty() {
+                stack.push(PatternSection::And(
+                    vec![],
+                    Quantifier::ONE,
+                    SourceSpan((raw.len(), raw.len())),
+                ));
+                need_and = true;
+            }
 
-                    char_group_chars.push(next_c);
+            Parser::collapse_stacks(&mut stack, &mut ops, |op| {
+                matches!(op, None | Some(Op::Paren))
+            });
 
-                    next_c = raw_it.next().expect("Missing end of char group");
+            match paren_starts.pop() {
+                Some((paren_start, kind)) => {
+                    diagnostics.push(Diagnostic::new(
+                        (paren_start, raw.len()),
+                        ErrorCode::UnterminatedGroup,
+                    ));
+                    assert_eq!(Some(Op::Paren), ops.pop());
+                    Parser::wrap_group(&mut stack, kind, (paren_start, raw.len()));
                 }
+                None => break,
+            }
+        }
+        assert!(ops.is_empty());
+        assert!(stack.len() <= 1);
 
-                stack.push(PatternSection::CharGroup(
-                    char_group_chars,
-                    Mod::One,
-                    is_negated,
-                ));
+        (
+            stack.pop().unwrap_or(PatternSection::And(
+                vec![],
+                Quantifier::ONE,
+                SourceSpan::default(),
+            )),
+            diagnostics,
+            tokens,
+        )
+    }
 
-                if need_and {
-                    ops.push(Op::And);
+    // Kind and end of the group opening at `idx`: `(`, `(?:`, `(?P<name>` or `(?<name>`. Groups
+    // with an invalid name are reported and open as unnamed groups.
+    fn parse_group_open(
+        raw: &str,
+        idx: usize,
+        group_names: &mut Vec<String>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> (GroupKind, usize) {
+        let rest = &raw[idx + 1..];
+        if rest.starts_with("?:") {
+            return (GroupKind::NonCapturing, idx + 3);
+        }
+        let Some(prefix) = ["?P<", "?<"].into_iter().find(|p| rest.starts_with(p)) else {
+            return (GroupKind::Capturing, idx + 1);
+        };
+
+        let name_start = idx + 1 + prefix.len();
+        let Some(name_len) = raw[name_start..].find('>') else {
+            diagnostics.push(Diagnostic::new(
+                (idx, raw.len()),
+                ErrorCode::InvalidGroupName,
+            ));
+            return (GroupKind::Capturing, raw.len());
+        };
+        let name = &raw[name_start..name_start + name_len];
+        let end = name_start + name_len + 1;
+
+        let mut chars = name.chars();
+        let is_valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_');
+        if !is_valid {
+            diagnostics.push(Diagnostic::new((idx, end), ErrorCode::InvalidGroupName));
+            return (GroupKind::Capturing, end);
+        }
+        if group_names.iter().any(|other| other == name) {
+            diagnostics.push(Diagnostic::new(
+                (name_start, name_start + name_len),
+                ErrorCode::DuplicateGroupName,
+            ));
+        }
+        group_names.push(name.to_string());
+        (GroupKind::Named(name.to_string()), end)
+    }
+
+    fn wrap_group(stack: &mut Vec<PatternSection>, kind: GroupKind, span: Span) {
+        let section = stack.pop().expect("Empty stack error");
+        stack.push(PatternSection::Group(
+            Box::new(section),
+            Quantifier::ONE,
+            kind,
+            SourceSpan(span),
+        ));
+    }
+
+    fn push_operand(
+        stack: &mut Vec<PatternSection>,
+        ops: &mut Vec<Op>,
+        need_and: &mut bool,
+        section: PatternSection,
+    ) {
+        stack.push(section);
+        if *need_and {
+            ops.push(Op::And);
+        }
+        *need_and = true;
+    }
+
+    // Ranges of a group after the opening '[' + is-negated, in the order of the pattern, a single
+    // char being a range of itself. None if the group is not closed. A '-' first or last in the
+    // group is a literal '-', escaped symbols are literals too, eg: `[\]]`.
+    fn parse_char_group(
+        raw_it: &mut Peekable<CharIndices>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<(Vec<ClassRange>, bool)> {
+        let mut ranges = vec![];
+        let is_negated = raw_it.next_if(|(_, c)| *c == '^').is_some();
+
+        loop {
+            let (idx, c) = raw_it.next()?;
+            if c == ']' {
+                return Some((ranges, is_negated));
+            }
+            if c == '\\' {
+                let (escaped_idx, escaped) = raw_it.next()?;
+                match Parser::perl_class(escaped) {
+                    Some((class, false)) => ranges.extend(class),
+                    // A group can't hold the complement of some chars, eg: `[\Da]`.
+                    Some((_, true)) => diagnostics.push(Diagnostic::new(
+                        (idx, escaped_idx + escaped.len_utf8()),
+                        ErrorCode::InvalidEscape,
+                    )),
+                    None if escaped.is_alphanumeric() => diagnostics.push(Diagnostic::new(
+                        (idx, escaped_idx + escaped.len_utf8()),
+                        ErrorCode::InvalidEscape,
+                    )),
+                    None => ranges.push(ClassRange::new(escaped, escaped)),
                 }
-                need_and = true;
-            } else if c == '{' {
-                let mut min_str = String::new();
-                let mut min_is_max = false;
-                let min: usize;
-                let max: usize;
-
-                loop {
-                    let next_c = raw_it.next().expect("Missing char");
-                    if next_c == ',' {
-                        break;
-                    } else if next_c == '}' {
-                        min_is_max = true;
-                        break;
+                continue;
+            }
+
+            let mut ahead = raw_it.clone();
+            match (ahead.next(), ahead.next()) {
+                (Some((_, '-')), Some((end_idx, end))) if end != ']' => {
+                    raw_it.nth(1);
+                    if c <= end {
+                        ranges.push(ClassRange::new(c, end));
+                    } else {
+                        diagnostics.push(Diagnostic::new(
+                            (idx, end_idx + end.len_utf8()),
+                            ErrorCode::InvalidClassRange,
+                        ));
                     }
-                    min_str.push(next_c);
                 }
+                _ => ranges.push(ClassRange::new(c, c)),
+            }
+        }
+    }
 
-                min = usize::from_str_radix(&min_str, 10).expect("Invalid number");
-                if !min_is_max {
-                    let mut max_str = String::new();
-                    loop {
-                        let next_c = raw_it.next().expect("Missing char");
-                        if next_c == '}' {
-                            break;
-                        }
-                        max_str.push(next_c);
-                    }
+    // Ranges + is-negated of the class of `\d`, `\w` or `\s` by the letter after the backslash, the
+    // upper case letter negating it. ASCII only, eg: `\d` is `[0-9]`, so there are no Unicode
+    // tables to turn off with an ASCII mode.
+    fn perl_class(c: char) -> Option<(Vec<ClassRange>, bool)> {
+        let ranges = match c.to_ascii_lowercase() {
+            'd' => vec![ClassRange::new('0', '9')],
+            'w' => vec![
+                ClassRange::new('a', 'z'),
+                ClassRange::new('A', 'Z'),
+                ClassRange::new('0', '9'),
+                ClassRange::new('_', '_'),
+            ],
+            's' => [' ', '\t', '\n', '\r', '\u{b}', '\u{c}']
+                .into_iter()
+                .map(|c| ClassRange::new(c, c))
+                .collect(),
+            _ => return None,
+        };
+        Some((ranges, c.is_ascii_uppercase()))
+    }
 
-                    max = usize::from_str_radix(&max_str, 10).expect("Invalid number");
-                } else {
-                    max = min;
-                }
+    // A `?` right after a quantifier makes it lazy, eg: `a+?`. Returns the quantifier + its end.
+    fn parse_lazy(
+        raw_it: &mut Peekable<CharIndices>,
+        quantifier: Quantifier,
+        end: usize,
+    ) -> (Quantifier, usize) {
+        match raw_it.next_if(|(_, c)| *c == '?') {
+            Some((idx, _)) => (quantifier.lazy(), idx + 1),
+            None => (quantifier, end),
+        }
+    }
 
-                Parser::inject_mod(&mut stack, Mod::Range(min, max));
-            } else if c.is_ascii_alphanumeric() || c == '.' {
-                stack.push(PatternSection::Char(c, Mod::One));
-                if need_and {
-                    ops.push(Op::And);
-                }
-                need_and = true;
-            } else {
-                panic!("Unexpected character error");
+    // Range modifier after the opening '{' + end of the range in the pattern, eg: `{2}`, `{1,3}`
+    // or `{2,}`.
+    fn parse_range(
+        raw_it: &mut Peekable<CharIndices>,
+        raw_len: usize,
+    ) -> (Result<Quantifier, ErrorCode>, usize) {
+        let mut body = String::new();
+
+        let end = loop {
+            match raw_it.next() {
+                Some((idx, '}')) => break idx + 1,
+                Some((_, c)) => body.push(c),
+                None => return (Err(ErrorCode::UnterminatedRange), raw_len),
             }
+        };
 
-            idx += 1;
+        let (min_str, max_str) = body.split_once(',').unwrap_or((&body, &body));
+        if let (Ok(min), "") = (min_str.parse::<usize>(), max_str) {
+            return (Ok(Quantifier::range(min, None)), end);
         }
 
-        Parser::collapse_stacks(&mut stack, &mut ops, |op| match op {
-            None => true,
-            Some(_) => false,
-        });
-        assert!(ops.is_empty());
-        assert!(stack.len() <= 1);
+        let range_mod = match (min_str.parse::<usize>(), max_str.parse::<usize>()) {
+            (Ok(min), Ok(max)) if min <= max && max >= 1 => Ok(Quantifier::range(min, Some(max))),
+            (Ok(_), Ok(_)) => Err(ErrorCode::InvalidRange),
+            _ => Err(ErrorCode::InvalidNumber),
+        };
 
-        stack.pop().unwrap_or(PatternSection::And(vec![], Mod::One))
+        (range_mod, end)
     }
 
     fn collapse_stacks(
@@ -140,14 +690,57 @@ impl Parser {
             let tail = stack.drain(stack.len() - count - 1..).collect::<Vec<_>>();
 
             let collapsed = match op {
-                Op::And => PatternSection::And(tail, Mod::One),
-                Op::Or => PatternSection::Or(tail, Mod::One),
+                Op::And => Parser::fold_literals(tail),
+                Op::Or => {
+                    let span = SourceSpan::of(&tail);
+                    PatternSection::Or(tail, Quantifier::ONE, span)
+                }
                 _ => unreachable!("Unexpected OP during collapse"),
             };
             stack.push(collapsed);
         }
     }
 
+    // Joins runs of plain chars into literal strings. Returns the literal alone when it covers the
+    // whole list.
+    pub fn fold_literals(list: Vec<PatternSection>) -> PatternSection {
+        let span = SourceSpan::of(&list);
+        let mut folded = vec![];
+
+        for section in list {
+            let (prefix, prefix_span) = match &section {
+                PatternSection::Char(c, Quantifier::ONE, span) => (c.to_string(), *span),
+                PatternSection::Literal(s, Quantifier::ONE, span) => (s.clone(), *span),
+                _ => {
+                    folded.push(section);
+                    continue;
+                }
+            };
+
+            match folded.last_mut() {
+                Some(PatternSection::Literal(s, Quantifier::ONE, span)) => {
+                    s.push_str(&prefix);
+                    *span = span.to(prefix_span);
+                }
+                Some(PatternSection::Char(c, Quantifier::ONE, span)) => {
+                    let literal = PatternSection::Literal(
+                        format!("{}{}", c, prefix),
+                        Quantifier::ONE,
+                        span.to(prefix_span),
+                    );
+                    *folded.last_mut().unwrap() = literal;
+                }
+                _ => folded.push(section),
+            }
+        }
+
+        if folded.len() == 1 && matches!(folded[0], PatternSection::Literal(_, _, _)) {
+            folded.pop().unwrap()
+        } else {
+            PatternSection::And(folded, Quantifier::ONE, span)
+        }
+    }
+
     fn pop_same(ops: &mut Vec<Op>) -> (Option<Op>, usize) {
         let top_op = ops.last();
         if top_op.is_none() {
@@ -167,16 +760,19 @@ impl Parser {
         (Some(top_op), count)
     }
 
-    fn inject_mod(stack: &mut Vec<PatternSection>, m: Mod) {
-        let new_pattern = match stack.pop().expect("Empty stack error") {
-            PatternSection::And(v, _) => PatternSection::And(v, m),
-            PatternSection::Or(v, _) => PatternSection::Or(v, m),
-            PatternSection::Char(v, _) => PatternSection::Char(v, m),
-            PatternSection::CharGroup(v, _, is_negated) => {
-                PatternSection::CharGroup(v, m, is_negated)
+    // Modifies the last atom (char, class or group), eg: only `b` in `a|b?`. An atom with a
+    // modifier already is wrapped, so `(a+)?` keeps the `+`. The span ends with the quantifier.
+    fn inject_mod(stack: &mut Vec<PatternSection>, m: Quantifier, end: usize) {
+        let section = stack.pop().expect("Empty stack error");
+        let span = SourceSpan((section.span().0, end));
+        let new_pattern = match *section.get_mod() {
+            Quantifier::ONE => {
+                let mut section = section.with_mod(m);
+                *section.span_mut() = span;
+                section
             }
+            _ => PatternSection::And(vec![section], m, span),
         };
-
         stack.push(new_pattern);
     }
 }
@@ -185,9 +781,17 @@ impl Parser {
 mod test {
     use crate::parser::*;
 
+    // Ranges of single chars, like the parser gives for a group without ranges.
+    fn chars(chars: &[char]) -> Vec<ClassRange> {
+        chars.iter().map(|&c| ClassRange::new(c, c)).collect()
+    }
+
     #[test]
     fn test_empty() {
-        assert_eq!(PatternSection::And(vec![], Mod::One), Parser::parse(""));
+        assert_eq!(
+            PatternSection::And(vec![], Quantifier::ONE, SourceSpan::default()),
+            Parser::parse("").unwrap()
+        );
     }
 
     #[test]
@@ -195,14 +799,15 @@ mod test {
         assert_eq!(
             PatternSection::And(
                 vec![
-                    PatternSection::Char('a', Mod::One),
-                    PatternSection::Char('b', Mod::OneOrMore),
-                    PatternSection::Char('c', Mod::ZeroOrOne),
-                    PatternSection::Char('d', Mod::Any),
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Char('b', Quantifier::ONE_OR_MORE, SourceSpan::default()),
+                    PatternSection::Char('c', Quantifier::ZERO_OR_ONE, SourceSpan::default()),
+                    PatternSection::Char('d', Quantifier::ANY, SourceSpan::default()),
                 ],
-                Mod::One
+                Quantifier::ONE,
+                SourceSpan::default()
             ),
-            Parser::parse("ab+c?d*")
+            Parser::parse("ab+c?d*").unwrap()
         );
     }
 
@@ -211,12 +816,143 @@ mod test {
         assert_eq!(
             PatternSection::Or(
                 vec![
-                    PatternSection::Char('a', Mod::One),
-                    PatternSection::Char('b', Mod::Any),
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Char('b', Quantifier::ANY, SourceSpan::default()),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("a|b*").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stacked_mods() {
+        assert_eq!(
+            PatternSection::Group(
+                Box::new(PatternSection::Char(
+                    'a',
+                    Quantifier::ONE_OR_MORE,
+                    SourceSpan::default()
+                )),
+                Quantifier::ZERO_OR_ONE,
+                GroupKind::Capturing,
+                SourceSpan::default()
+            ),
+            Parser::parse("(a+)?").unwrap()
+        );
+        assert_eq!(
+            PatternSection::Or(
+                vec![
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::And(
+                        vec![PatternSection::Char(
+                            'b',
+                            Quantifier::range(2, Some(2)),
+                            SourceSpan::default()
+                        )],
+                        Quantifier::ANY,
+                        SourceSpan::default()
+                    ),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("a|b{2}*").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Group(
+                        Box::new(PatternSection::Char(
+                            'a',
+                            Quantifier::ONE,
+                            SourceSpan::default()
+                        )),
+                        Quantifier::ONE,
+                        GroupKind::Capturing,
+                        SourceSpan::default()
+                    ),
+                    PatternSection::Group(
+                        Box::new(PatternSection::Literal(
+                            "bc".into(),
+                            Quantifier::ONE,
+                            SourceSpan::default()
+                        )),
+                        Quantifier::ONE_OR_MORE,
+                        GroupKind::NonCapturing,
+                        SourceSpan::default()
+                    ),
                 ],
-                Mod::One
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("(a)(?:bc)+").unwrap()
+        );
+        assert_ne!(Parser::parse("ab").unwrap(), Parser::parse("(ab)").unwrap());
+        assert_eq!(
+            vec![((0, 3), TokenKind::GroupOpen), ((3, 4), TokenKind::Literal)],
+            Parser::tokenize("(?:a")
+        );
+    }
+
+    #[test]
+    fn test_named_groups() {
+        assert_eq!(
+            PatternSection::Group(
+                Box::new(PatternSection::Char(
+                    'a',
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                )),
+                Quantifier::ONE,
+                GroupKind::Named("first".into()),
+                SourceSpan::default()
             ),
-            Parser::parse("a|b*")
+            Parser::parse("(?P<first>a)").unwrap()
+        );
+        assert_eq!(
+            Parser::parse("(?P<x_1>a)").unwrap(),
+            Parser::parse("(?<x_1>a)").unwrap()
+        );
+        assert_eq!(
+            vec![((0, 5), TokenKind::GroupOpen), ((5, 6), TokenKind::Literal)],
+            Parser::tokenize("(?<x>a")
+        );
+        assert_eq!(
+            vec![1, 2],
+            Parser::group_tree("(?P<a>x)(?:y)(z)")
+                .iter()
+                .map(|group| group.index)
+                .collect::<Vec<_>>()
+        );
+
+        let codes = |raw| {
+            Parser::parse_lenient(raw)
+                .1
+                .iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.span))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(ErrorCode::InvalidGroupName, (0, 4))], codes("(?<>a)"));
+        assert_eq!(
+            vec![(ErrorCode::InvalidGroupName, (0, 7))],
+            codes("(?P<1a>a)")
+        );
+        assert_eq!(
+            vec![
+                (ErrorCode::InvalidGroupName, (0, 6)),
+                (ErrorCode::UnterminatedGroup, (0, 6))
+            ],
+            codes("(?P<ab")
+        );
+        assert_eq!(
+            vec![(ErrorCode::DuplicateGroupName, (10, 11))],
+            codes("(?<a>x)(?<a>y)")
         );
     }
 
@@ -225,46 +961,590 @@ mod test {
         assert_eq!(
             PatternSection::And(
                 vec![
-                    PatternSection::Char('a', Mod::One),
-                    PatternSection::CharGroup(vec!['b', 'c'], Mod::One, false),
-                    PatternSection::Char('d', Mod::One),
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::CharGroup(
+                        chars(&['b', 'c']),
+                        Quantifier::ONE,
+                        false,
+                        SourceSpan::default()
+                    ),
+                    PatternSection::Char('d', Quantifier::ONE, SourceSpan::default()),
                 ],
-                Mod::One
+                Quantifier::ONE,
+                SourceSpan::default()
             ),
-            Parser::parse("a[bc]d"),
+            Parser::parse("a[bc]d").unwrap(),
         );
         assert_eq!(
             PatternSection::Or(
                 vec![
-                    PatternSection::Char('a', Mod::One),
-                    PatternSection::CharGroup(vec!['b', 'c'], Mod::One, true),
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::CharGroup(
+                        chars(&['b', 'c']),
+                        Quantifier::ONE,
+                        true,
+                        SourceSpan::default()
+                    ),
                 ],
-                Mod::One
+                Quantifier::ONE,
+                SourceSpan::default()
             ),
-            Parser::parse("a|[^bc]"),
+            Parser::parse("a|[^bc]").unwrap(),
         );
         assert_eq!(
             PatternSection::And(
                 vec![
-                    PatternSection::CharGroup(vec!['b', 'c'], Mod::Any, true),
-                    PatternSection::Char('a', Mod::One),
+                    PatternSection::CharGroup(
+                        chars(&['b', 'c']),
+                        Quantifier::ANY,
+                        true,
+                        SourceSpan::default()
+                    ),
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
                 ],
-                Mod::One
+                Quantifier::ONE,
+                SourceSpan::default()
             ),
-            Parser::parse("[^bc]*a"),
+            Parser::parse("[^bc]*a").unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_char_group_ranges() {
+        let ranges = |raw| match Parser::parse(raw).unwrap() {
+            PatternSection::CharGroup(ranges, _, _, _) => ranges,
+            pattern => panic!("Expected a char group: {:?}", pattern),
+        };
+        let range = ClassRange::new;
+        assert_eq!(vec![range('a', 'c'), range('x', 'x')], ranges("[a-cx]"));
+        assert_eq!(
+            vec![range('-', '-'), range('a', 'b'), range('-', '-')],
+            ranges("[-a-b-]")
+        );
+        assert_eq!(vec![range('õ', 'ö'), range('0', '1')], ranges("[^õ-ö0-1]"));
+        // Ranges are kept whole, whatever their size.
+        assert_eq!(vec![range(' ', char::MAX)], ranges("[ -\u{10FFFF}]"));
+
+        let codes = |raw| {
+            Parser::parse_lenient(raw)
+                .1
+                .into_iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.span))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![(ErrorCode::InvalidClassRange, (2, 5))],
+            codes("a[z-a]")
+        );
+        assert_eq!(
+            Some("put the lower char first: 'a-z'".into()),
+            Parser::parse("a[z-a]")
+                .unwrap_err()
+                .suggestion()
+                .map(String::from)
         );
     }
 
     #[test]
     fn test_mod_range() {
         assert_eq!(
-            PatternSection::Char('a', Mod::Range(3, 3)),
-            Parser::parse("a{3}"),
+            PatternSection::Char('a', Quantifier::range(3, Some(3)), SourceSpan::default()),
+            Parser::parse("a{3}").unwrap(),
+        );
+
+        assert_eq!(
+            PatternSection::Char('a', Quantifier::range(3, Some(6)), SourceSpan::default()),
+            Parser::parse("a{3,6}").unwrap(),
+        );
+
+        assert_eq!(
+            PatternSection::Char('a', Quantifier::range(2, None), SourceSpan::default()),
+            Parser::parse("a{2,}").unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_lazy_mods() {
+        assert_eq!(
+            PatternSection::Char('a', Quantifier::ONE_OR_MORE.lazy(), SourceSpan::default()),
+            Parser::parse("a+?").unwrap(),
+        );
+        assert_eq!(
+            PatternSection::Char('a', Quantifier::ZERO_OR_ONE.lazy(), SourceSpan::default()),
+            Parser::parse("a??").unwrap(),
+        );
+        assert_eq!(
+            PatternSection::Char(
+                'a',
+                Quantifier::range(1, Some(3)).lazy(),
+                SourceSpan::default()
+            ),
+            Parser::parse("a{1,3}?").unwrap(),
+        );
+        assert_eq!("{1,3}?", Quantifier::range(1, Some(3)).lazy().to_pattern());
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            PatternSection::Literal("a.*(\\".into(), Quantifier::ONE, SourceSpan::default()),
+            Parser::parse("a\\.\\*\\(\\\\").unwrap()
+        );
+        // An escaped dot is a plain char, the wildcard has its own section.
+        assert_eq!(
+            PatternSection::Char('.', Quantifier::ONE_OR_MORE, SourceSpan::default()),
+            Parser::parse("\\.+").unwrap()
+        );
+        assert_eq!(
+            PatternSection::Any(Quantifier::ONE_OR_MORE, SourceSpan::default()),
+            Parser::parse(".+").unwrap()
+        );
+        assert_eq!(
+            PatternSection::CharGroup(
+                chars(&[']', '\\', 'a']),
+                Quantifier::ANY,
+                false,
+                SourceSpan::default()
+            ),
+            Parser::parse("[\\]\\\\a]*").unwrap()
+        );
+
+        let codes = |raw| {
+            Parser::parse_lenient(raw)
+                .1
+                .into_iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.span))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![(ErrorCode::InvalidEscape, (1, 3))], codes("a\\qb"));
+        assert_eq!(vec![(ErrorCode::InvalidEscape, (1, 2))], codes("a\\"));
+        assert_eq!(vec![(ErrorCode::InvalidEscape, (1, 3))], codes("[\\q]"));
+        assert_eq!(
+            vec![
+                ((0, 2), TokenKind::Literal),
+                ((2, 3), TokenKind::Quantifier)
+            ],
+            Parser::tokenize("\\.+")
+        );
+    }
+
+    #[test]
+    fn test_perl_classes() {
+        let group = |raw| match Parser::parse(raw).unwrap() {
+            PatternSection::CharGroup(ranges, _, is_negated, _) => (
+                ranges
+                    .iter()
+                    .map(|range| range.end as usize - range.start as usize + 1)
+                    .sum::<usize>(),
+                is_negated,
+            ),
+            pattern => panic!("Expected a char group: {:?}", pattern),
+        };
+        assert_eq!((10, false), group("\\d"));
+        assert_eq!((63, true), group("\\W"));
+        assert_eq!((6, false), group("\\s"));
+        assert_eq!((12, false), group("[\\d_x]"));
+        // ASCII only.
+        for raw in ["\\d", "\\w", "\\s"] {
+            match Parser::parse(raw).unwrap() {
+                PatternSection::CharGroup(ranges, ..) => {
+                    assert!(ranges.iter().all(|range| range.end.is_ascii()))
+                }
+                pattern => panic!("Expected a char group: {:?}", pattern),
+            }
+        }
+
+        assert_eq!(
+            vec![Diagnostic::new((1, 3), ErrorCode::InvalidEscape)],
+            Parser::parse_lenient("[\\Sa]").1
+        );
+        assert_eq!(
+            vec![((0, 2), TokenKind::Class), ((2, 3), TokenKind::Literal)],
+            Parser::tokenize("\\d-")
+        );
+    }
+
+    #[test]
+    fn test_anchors() {
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Anchor(Anchor::Start, Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Literal("ab".into(), Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Anchor(Anchor::End, Quantifier::ONE, SourceSpan::default()),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("^ab$").unwrap()
+        );
+        // Only the first `^` of a char group negates it.
+        assert_eq!(
+            PatternSection::CharGroup(
+                chars(&['$', '^']),
+                Quantifier::ONE,
+                true,
+                SourceSpan::default()
+            ),
+            Parser::parse("[^$^]").unwrap()
+        );
+        assert_eq!(
+            PatternSection::Literal("^$".into(), Quantifier::ONE, SourceSpan::default()),
+            Parser::parse("\\^\\$").unwrap()
+        );
+        assert_eq!(
+            vec![
+                ((0, 1), TokenKind::MetaChar),
+                ((1, 2), TokenKind::Quantifier)
+            ],
+            Parser::tokenize("$?")
+        );
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(
+            PatternSection::Literal("abc".into(), Quantifier::ONE, SourceSpan::default()),
+            Parser::parse("abc").unwrap()
+        );
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Group(
+                        Box::new(PatternSection::Literal(
+                            "bc".into(),
+                            Quantifier::ONE,
+                            SourceSpan::default()
+                        )),
+                        Quantifier::ONE,
+                        GroupKind::Capturing,
+                        SourceSpan::default()
+                    ),
+                    PatternSection::Char('d', Quantifier::ONE, SourceSpan::default()),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("a(bc)d").unwrap()
+        );
+        assert_eq!(
+            PatternSection::And(
+                vec![
+                    PatternSection::Literal("ab".into(), Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Any(Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Char('c', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::Char('d', Quantifier::ONE_OR_MORE, SourceSpan::default()),
+                    PatternSection::Literal("ef".into(), Quantifier::ONE, SourceSpan::default()),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("ab.cd+ef").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_empty_branches() {
+        assert_eq!(
+            PatternSection::Or(
+                vec![
+                    PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    PatternSection::And(vec![], Quantifier::ONE, SourceSpan::default()),
+                ],
+                Quantifier::ONE,
+                SourceSpan::default()
+            ),
+            Parser::parse("a|").unwrap(),
+        );
+        assert_eq!(
+            PatternSection::Group(
+                Box::new(PatternSection::Or(
+                    vec![
+                        PatternSection::And(vec![], Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                )),
+                Quantifier::ONE,
+                GroupKind::Capturing,
+                SourceSpan::default()
+            ),
+            Parser::parse("(|a)").unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let pattern = Parser::parse("ab|(c[de]+)?x").unwrap();
+        assert_eq!((0, 13), pattern.span());
+
+        let PatternSection::Or(branches, _, _) = &pattern else {
+            panic!("Expected an OR: {:?}", pattern);
+        };
+        assert_eq!(
+            vec![(0, 2), (3, 13)],
+            branches
+                .iter()
+                .map(|section| section.span())
+                .collect::<Vec<_>>()
+        );
+
+        let PatternSection::And(items, _, _) = &branches[1] else {
+            panic!("Expected an AND: {:?}", branches[1]);
+        };
+        assert_eq!(
+            vec![(3, 12), (12, 13)],
+            items
+                .iter()
+                .map(|section| section.span())
+                .collect::<Vec<_>>()
+        );
+
+        let PatternSection::Group(group, _, _, _) = &items[0] else {
+            panic!("Expected a group: {:?}", items[0]);
+        };
+        assert_eq!((4, 10), group.span());
+
+        let (pattern, _) = Parser::parse_lenient("*a|");
+        assert_eq!((0, 3), pattern.span());
+        let PatternSection::Or(branches, _, _) = &pattern else {
+            panic!("Expected an OR: {:?}", pattern);
+        };
+        assert_eq!(
+            vec![(0, 2), (3, 3)],
+            branches
+                .iter()
+                .map(|section| section.span())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        assert_eq!(
+            (Parser::parse("a|b*").unwrap(), vec![]),
+            Parser::parse_lenient("a|b*")
+        );
+
+        assert_eq!(
+            (
+                PatternSection::And(
+                    vec![
+                        PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Error(Quantifier::ONE, SourceSpan::default()),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                ),
+                vec![Diagnostic::new((1, 4), ErrorCode::UnterminatedClass)],
+            ),
+            Parser::parse_lenient("a[bc"),
+        );
+
+        assert_eq!(
+            (
+                PatternSection::Or(
+                    vec![
+                        PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Error(Quantifier::ONE_OR_MORE, SourceSpan::default()),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                ),
+                vec![Diagnostic::new((2, 3), ErrorCode::NothingToRepeat)],
+            ),
+            Parser::parse_lenient("a|+"),
+        );
+
+        assert_eq!(
+            (
+                PatternSection::And(
+                    vec![
+                        PatternSection::Error(Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Literal(
+                            "bc".into(),
+                            Quantifier::ONE,
+                            SourceSpan::default()
+                        ),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                ),
+                vec![
+                    Diagnostic::new((0, 1), ErrorCode::UnexpectedCharacter),
+                    Diagnostic::new((2, 3), ErrorCode::UnmatchedParen),
+                    Diagnostic::new((4, 10), ErrorCode::InvalidNumber),
+                ],
+            ),
+            Parser::parse_lenient("%b)c{1,x2}"),
+        );
+
+        assert_eq!(
+            (
+                PatternSection::And(
+                    vec![
+                        PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                        PatternSection::Group(
+                            Box::new(PatternSection::Char(
+                                'b',
+                                Quantifier::ONE_OR_MORE,
+                                SourceSpan::default()
+                            )),
+                            Quantifier::ONE,
+                            GroupKind::Capturing,
+                            SourceSpan::default()
+                        ),
+                    ],
+                    Quantifier::ONE,
+                    SourceSpan::default()
+                ),
+                vec![
+                    Diagnostic::new((4, 7), ErrorCode::InvalidRange),
+                    Diagnostic::new((1, 7), ErrorCode::UnterminatedGroup),
+                ],
+            ),
+            Parser::parse_lenient("a(b+{0}"),
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = Parser::parse("ab{2,x}").unwrap_err();
+        assert_eq!(ErrorCode::InvalidNumber, err.code());
+        assert_eq!(Some((2, 7)), err.span());
+        assert!(err.suggestion().is_some());
+
+        // The first error is reported.
+        let err = Parser::parse("a[b|c)").unwrap_err();
+        assert_eq!(ErrorCode::UnterminatedClass, err.code());
+    }
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(
+            vec![Diagnostic::new((0, 3), ErrorCode::RedundantWildcard)],
+            Parser::lint("^.*ab")
+        );
+        assert_eq!(Vec::<Diagnostic>::new(), Parser::lint("a.*b"));
+        assert_eq!(
+            Some("remove '.*?', searches find matches anywhere already".into()),
+            Parser::suggest("ab.*?", &Parser::lint("ab.*?")[0])
+        );
+    }
+
+    #[test]
+    fn test_suggest() {
+        let suggestions = |raw: &str| {
+            Parser::parse_lenient(raw)
+                .1
+                .iter()
+                .map(|diagnostic| Parser::suggest(raw, diagnostic).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["use \\+ to match a literal '+'"], suggestions("+a"));
+        assert_eq!(vec!["use \\% to match a literal '%'"], suggestions("a%b"));
+        assert_eq!(
+            vec!["use \\{2\\} to match a literal '{2}'"],
+            suggestions("{2}a")
+        );
+        assert!(Parser::parse("\\+a\\%b\\{2\\}").is_ok());
+        assert_eq!(
+            vec!["a group name takes letters, digits and '_', eg: (?P<year>\\d+)"],
+            suggestions("(?P<a-b>c)")
+        );
+        assert!(Parser::parse("(?P<year>\\d+)").is_ok());
+        assert_eq!(
+            vec!["add ')' to close the '(' opened at offset 3"],
+            suggestions("abc(d")
+        );
+        assert_eq!(
+            vec!["add ']' to close the char group opened at offset 1"],
+            suggestions("a[bc")
+        );
+        assert_eq!(
+            vec!["the range needs min <= max and max >= 1, eg: {0,2}"],
+            suggestions("a{3,1}")
+        );
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec![
+                ((0, 1), TokenKind::Literal),
+                ((1, 2), TokenKind::Quantifier),
+                ((2, 3), TokenKind::GroupOpen),
+                ((3, 7), TokenKind::Class),
+                ((7, 8), TokenKind::MetaChar),
+                ((8, 9), TokenKind::MetaChar),
+                ((9, 10), TokenKind::GroupClose),
+                ((10, 15), TokenKind::Quantifier),
+            ],
+            Parser::tokenize("a*([^b]|.){1,2}"),
         );
+        assert_eq!(
+            vec![
+                ((0, 1), TokenKind::Literal),
+                ((1, 2), TokenKind::Error),
+                ((2, 3), TokenKind::Error),
+                ((3, 6), TokenKind::Error),
+            ],
+            Parser::tokenize("a)%[bc"),
+        );
+    }
+
+    #[test]
+    fn test_syntax() {
+        for item in SYNTAX {
+            let (_, diagnostics) = Parser::parse_lenient(item.example);
+            assert_eq!(Vec::<Diagnostic>::new(), diagnostics, "{}", item.example);
+            assert!(
+                Parser::tokenize(item.example)
+                    .iter()
+                    .any(|(_, kind)| *kind == item.kind),
+                "{}",
+                item.example
+            );
+        }
 
+        assert!(Parser::syntax_help().contains("\n  a{1,3}         between 1 and 3\n"));
+    }
+
+    #[test]
+    fn test_group_tree() {
+        assert_eq!(Vec::<CaptureGroup>::new(), Parser::group_tree("ab|c"));
         assert_eq!(
-            PatternSection::Char('a', Mod::Range(3, 6)),
-            Parser::parse("a{3,6}"),
+            vec![
+                CaptureGroup {
+                    index: 1,
+                    span: (0, 10),
+                    children: vec![
+                        CaptureGroup {
+                            index: 2,
+                            span: (1, 4),
+                            children: vec![],
+                        },
+                        CaptureGroup {
+                            index: 3,
+                            span: (4, 9),
+                            children: vec![CaptureGroup {
+                                index: 4,
+                                span: (5, 8),
+                                children: vec![],
+                            }],
+                        },
+                    ],
+                },
+                CaptureGroup {
+                    index: 5,
+                    span: (10, 12),
+                    children: vec![],
+                },
+            ],
+            Parser::group_tree("((a)((b)))(c"),
         );
     }
 
@@ -275,53 +1555,63 @@ mod test {
                 vec![
                     PatternSection::And(
                         vec![
-                            PatternSection::Char('a', Mod::One),
-                            PatternSection::Char('b', Mod::ZeroOrOne),
+                            PatternSection::Char('a', Quantifier::ONE, SourceSpan::default()),
+                            PatternSection::Char(
+                                'b',
+                                Quantifier::ZERO_OR_ONE,
+                                SourceSpan::default()
+                            ),
                         ],
-                        Mod::One
+                        Quantifier::ONE,
+                        SourceSpan::default()
                     ),
-                    PatternSection::Or(
-                        vec![
-                            PatternSection::And(
-                                vec![
-                                    PatternSection::Char('c', Mod::One),
-                                    PatternSection::Char('d', Mod::One),
-                                ],
-                                Mod::One
-                            ),
-                            PatternSection::Or(
-                                vec![
-                                    PatternSection::And(
+                    PatternSection::Group(
+                        Box::new(PatternSection::Or(
+                            vec![
+                                PatternSection::Literal(
+                                    "cd".into(),
+                                    Quantifier::ONE,
+                                    SourceSpan::default()
+                                ),
+                                PatternSection::Group(
+                                    Box::new(PatternSection::Or(
                                         vec![
-                                            PatternSection::Char('1', Mod::One),
-                                            PatternSection::Char('f', Mod::One),
+                                            PatternSection::Literal(
+                                                "1f".into(),
+                                                Quantifier::ONE,
+                                                SourceSpan::default()
+                                            ),
+                                            PatternSection::Literal(
+                                                "gh".into(),
+                                                Quantifier::ONE,
+                                                SourceSpan::default()
+                                            ),
+                                            PatternSection::Literal(
+                                                "ij".into(),
+                                                Quantifier::ONE,
+                                                SourceSpan::default()
+                                            ),
                                         ],
-                                        Mod::One,
-                                    ),
-                                    PatternSection::And(
-                                        vec![
-                                            PatternSection::Char('g', Mod::One),
-                                            PatternSection::Char('h', Mod::One),
-                                        ],
-                                        Mod::One,
-                                    ),
-                                    PatternSection::And(
-                                        vec![
-                                            PatternSection::Char('i', Mod::One),
-                                            PatternSection::Char('j', Mod::One),
-                                        ],
-                                        Mod::One,
-                                    ),
-                                ],
-                                Mod::ZeroOrOne,
-                            ),
-                        ],
-                        Mod::Any,
+                                        Quantifier::ONE,
+                                        SourceSpan::default(),
+                                    )),
+                                    Quantifier::ZERO_OR_ONE,
+                                    GroupKind::Capturing,
+                                    SourceSpan::default(),
+                                ),
+                            ],
+                            Quantifier::ONE,
+                            SourceSpan::default(),
+                        )),
+                        Quantifier::ANY,
+                        GroupKind::Capturing,
+                        SourceSpan::default(),
                     ),
                 ],
-                Mod::One,
+                Quantifier::ONE,
+                SourceSpan::default(),
             ),
-            Parser::parse("ab?|(cd|(1f|gh|ij)?)*"),
+            Parser::parse("ab?|(cd|(1f|gh|ij)?)*").unwrap(),
         );
     }
 }
@@ -0,0 +1,496 @@
+use crate::types::*;
+use std::time::Instant;
+
+// An instruction to run next with the capture slots recorded so far.
+type Thread = (usize, Vec<Option<usize>>);
+
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    // Sorted ranges + is-negated.
+    Class(Vec<ClassRange>, bool),
+    Assert(Anchor),
+    // Records the position in a capture slot: `2 * group` where it opens, the next one where it
+    // closes.
+    Save(usize),
+    // Both ways, the first one preferred.
+    Split(usize, usize),
+    Jump(usize),
+    // Invalid part of a leniently parsed pattern.
+    Fail,
+    Match,
+}
+
+// A pending step of the program compilation.
+enum Task<'a> {
+    // Section with its quantifier.
+    Section(&'a PatternSection),
+    // Section ignoring its quantifier.
+    Body(&'a PatternSection),
+    // Copies of a quantified section: section, copies made.
+    Repeat(&'a PatternSection, usize),
+    // Copies of a bounded quantifier after the required ones, all skipping to the end: section,
+    // copies made.
+    Optional(&'a PatternSection, usize),
+    // Copy of the body, then a split back to its start: section.
+    Loop(&'a PatternSection),
+    // The split back: body start, greedy.
+    LoopEnd(usize, bool),
+    // The save of a capture group end: slot.
+    Save(usize),
+    // Points the skips of the innermost optional section or alternation here.
+    SkipEnd,
+    // Branches of an OR, each trying the next one when it fails.
+    OrSplit,
+    OrNext,
+}
+
+// The pattern as a program of prioritized alternatives, run on all its threads at once in the
+// order of their priority (a Pike VM). The match it finds is the one a backtracking engine would:
+// the earlier branch wins and greedy quantifiers repeat as much as they can, eg: `a|ab` matches
+// "a" of "ab". The automaton has no order between its moves, so it can only find the longest.
+#[derive(Debug)]
+pub struct Program {
+    insts: Vec<Inst>,
+    // Capture slots of the groups, the whole match included.
+    slot_count: usize,
+}
+
+impl Program {
+    // Built from the pattern before prefix factoring, which keeps the branch order but not the
+    // priorities, eg: `b*[^c].|b*` would become `b*(?:[^c].|)`. Uses an explicit task stack and
+    // the budget like the automaton, so deeply nested or huge patterns fail the same way.
+    pub fn new(pattern: &PatternSection, budget: &CompileBudget) -> Result<Program, RegexError> {
+        let mut insts = vec![];
+        let capture_indices = pattern.capture_indices();
+        let mut tasks = vec![Task::Section(pattern)];
+        // Splits and jumps waiting for the end of what they skip, by optional section or
+        // alternation.
+        let mut skips: Vec<Vec<usize>> = vec![];
+        let mut or_splits: Vec<usize> = vec![];
+
+        let started_at = Instant::now();
+        let mut steps = 0usize;
+
+        while let Some(task) = tasks.pop() {
+            steps += 1;
+            budget.check(steps, started_at)?;
+
+            match task {
+                Task::Section(section) => tasks.push(Task::Repeat(section, 0)),
+                Task::Repeat(section, i) => {
+                    let quantifier = section.get_mod();
+                    match quantifier.max {
+                        // `a*` is `(?:a+)?`, so an iteration matching the empty string can still
+                        // end the loop first, eg: `(?:b?|a)*` matches "" of "a", like backtracking.
+                        None if quantifier.min == 0 => {
+                            skips.push(vec![]);
+                            tasks.push(Task::SkipEnd);
+                            tasks.push(Task::Loop(section));
+                            Program::push_skip(&mut insts, &mut skips, quantifier.greedy);
+                        }
+                        None if i + 1 < quantifier.min => {
+                            tasks.push(Task::Repeat(section, i + 1));
+                            tasks.push(Task::Body(section));
+                        }
+                        None => tasks.push(Task::Loop(section)),
+                        Some(_) if i < quantifier.min => {
+                            tasks.push(Task::Repeat(section, i + 1));
+                            tasks.push(Task::Body(section));
+                        }
+                        Some(max) if i < max => {
+                            skips.push(vec![]);
+                            tasks.push(Task::Optional(section, i));
+                        }
+                        Some(_) => {}
+                    }
+                }
+                Task::Optional(section, i) => {
+                    let quantifier = section.get_mod();
+                    if quantifier.max.is_some_and(|max| i < max) {
+                        tasks.push(Task::Optional(section, i + 1));
+                        tasks.push(Task::Body(section));
+                        Program::push_skip(&mut insts, &mut skips, quantifier.greedy);
+                    } else {
+                        tasks.push(Task::SkipEnd);
+                    }
+                }
+                Task::Body(section) => match section {
+                    PatternSection::And(list, _, _) => {
+                        tasks.extend(list.iter().rev().map(Task::Section));
+                    }
+                    PatternSection::Or(list, _, _) => match list.split_last() {
+                        Some((last, rest)) => {
+                            skips.push(vec![]);
+                            tasks.push(Task::SkipEnd);
+                            tasks.push(Task::Section(last));
+                            for branch in rest.iter().rev() {
+                                tasks.push(Task::OrNext);
+                                tasks.push(Task::Section(branch));
+                                tasks.push(Task::OrSplit);
+                            }
+                        }
+                        None => insts.push(Inst::Fail),
+                    },
+                    PatternSection::Group(inner, _, GroupKind::NonCapturing, _) => {
+                        tasks.push(Task::Section(inner))
+                    }
+                    PatternSection::Group(inner, _, _, _) => {
+                        let group = capture_indices[&std::ptr::from_ref(section)];
+                        insts.push(Inst::Save(2 * group));
+                        tasks.push(Task::Save(2 * group + 1));
+                        tasks.push(Task::Section(inner));
+                    }
+                    PatternSection::Char(c, _, _) => insts.push(Inst::Char(*c)),
+                    PatternSection::Any(_, _) => insts.push(Inst::Any),
+                    PatternSection::Literal(literal, _, _) => {
+                        insts.extend(literal.chars().map(Inst::Char));
+                    }
+                    PatternSection::CharGroup(ranges, _, is_negated, _) => {
+                        let ranges = ClassRange::normalize(ranges.clone());
+                        insts.push(Inst::Class(ranges, *is_negated));
+                    }
+                    PatternSection::Anchor(anchor, _, _) => insts.push(Inst::Assert(*anchor)),
+                    PatternSection::Error(_, _) => insts.push(Inst::Fail),
+                },
+                Task::Loop(section) => {
+                    tasks.push(Task::LoopEnd(insts.len(), section.get_mod().greedy));
+                    tasks.push(Task::Body(section));
+                }
+                Task::LoopEnd(body, greedy) => {
+                    insts.push(Program::split(body, insts.len() + 1, greedy));
+                }
+                Task::Save(slot) => insts.push(Inst::Save(slot)),
+                Task::SkipEnd => {
+                    let end = insts.len();
+                    for skip in skips.pop().expect("Missing skips") {
+                        insts[skip] = match insts[skip] {
+                            Inst::Split(usize::MAX, next) => Inst::Split(end, next),
+                            Inst::Split(next, _) => Inst::Split(next, end),
+                            _ => Inst::Jump(end),
+                        };
+                    }
+                }
+                Task::OrSplit => {
+                    or_splits.push(insts.len());
+                    insts.push(Inst::Split(insts.len() + 1, 0));
+                }
+                Task::OrNext => {
+                    skips.last_mut().expect("Missing skips").push(insts.len());
+                    insts.push(Inst::Jump(0));
+                    let split = or_splits.pop().expect("Missing branch split");
+                    insts[split] = Inst::Split(split + 1, insts.len());
+                }
+            }
+        }
+
+        insts.push(Inst::Match);
+        Ok(Program {
+            insts,
+            slot_count: 2 * (capture_indices.len() + 1),
+        })
+    }
+
+    // A split into what follows or to the end of the optional section, pointed there by
+    // `SkipEnd`. `usize::MAX` stands for the end until then.
+    fn push_skip(insts: &mut Vec<Inst>, skips: &mut [Vec<usize>], greedy: bool) {
+        skips.last_mut().expect("Missing skips").push(insts.len());
+        insts.push(Program::split(insts.len() + 1, usize::MAX, greedy));
+    }
+
+    fn split(body: usize, exit: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, exit)
+        } else {
+            Inst::Split(exit, body)
+        }
+    }
+
+    // End of the match starting at byte `start` a backtracking engine would find, see `Program`.
+    pub fn first_match_end(
+        &self,
+        haystack: &str,
+        start: usize,
+        clusters: &Clusters,
+    ) -> Option<usize> {
+        self.run_with(haystack, start, 0, clusters)
+            .map(|(end, _)| end)
+    }
+
+    // Capture slots of the match of `first_match_end`, by the branches it took, eg: `ab|(a)b`
+    // takes the first branch, where group 1 has no part. The whole match is at slots 0 and 1.
+    pub fn first_match_captures(
+        &self,
+        haystack: &str,
+        start: usize,
+        clusters: &Clusters,
+    ) -> Option<Vec<Option<usize>>> {
+        let (end, mut slots) = self.run_with(haystack, start, self.slot_count, clusters)?;
+        slots[0] = Some(start);
+        slots[1] = Some(end);
+        Some(slots)
+    }
+
+    // The threads read the haystack in lockstep, but in grapheme mode `.` reads a whole cluster
+    // while the other instructions read a char, so they are tried one at a time instead.
+    fn run_with(
+        &self,
+        haystack: &str,
+        start: usize,
+        slot_count: usize,
+        clusters: &Clusters,
+    ) -> Option<(usize, Vec<Option<usize>>)> {
+        if clusters.is_grapheme_mode() {
+            self.backtrack(haystack, start, slot_count, clusters)
+        } else {
+            self.run(haystack, start, slot_count)
+        }
+    }
+
+    // The end of the first match and the first `slot_count` capture slots it recorded.
+    fn run(
+        &self,
+        haystack: &str,
+        start: usize,
+        slot_count: usize,
+    ) -> Option<(usize, Vec<Option<usize>>)> {
+        let mut threads = vec![];
+        let mut next = vec![];
+        let mut seen = vec![usize::MAX; self.insts.len()];
+        let mut stack = vec![];
+        let slots = vec![None; slot_count];
+        self.add_thread(
+            haystack,
+            start,
+            (0, slots),
+            &mut threads,
+            &mut seen,
+            &mut stack,
+        );
+
+        let mut found = None;
+        let mut chars = haystack[start..].char_indices();
+        loop {
+            let c = chars.next();
+            let pos = c.map_or(haystack.len(), |(i, _)| start + i);
+            let next_pos = c.map_or(haystack.len(), |(i, c)| start + i + c.len_utf8());
+
+            for (pc, slots) in threads.drain(..) {
+                let is_read = match (&self.insts[pc], c) {
+                    (Inst::Match, _) => {
+                        // The threads after this one have a lower priority.
+                        found = Some((pos, slots));
+                        break;
+                    }
+                    (_, None) => false,
+                    (Inst::Char(expected), Some((_, c))) => *expected == c,
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class(ranges, is_negated), Some((_, c))) => {
+                        ClassRange::any_contains(ranges, c) != *is_negated
+                    }
+                    _ => false,
+                };
+                if is_read {
+                    let thread = (pc + 1, slots);
+                    self.add_thread(haystack, next_pos, thread, &mut next, &mut seen, &mut stack);
+                }
+            }
+
+            if c.is_none() || next.is_empty() {
+                return found;
+            }
+            std::mem::swap(&mut threads, &mut next);
+        }
+    }
+
+    // Like `run`, but tries the threads one at a time in the order of their priority, going back
+    // to the next one when one fails (backtracking). A thread failing at an offset fails there
+    // whatever its capture slots, so each instruction is tried once per offset.
+    fn backtrack(
+        &self,
+        haystack: &str,
+        start: usize,
+        slot_count: usize,
+        clusters: &Clusters,
+    ) -> Option<(usize, Vec<Option<usize>>)> {
+        enum Step {
+            Try(usize, usize),
+            // Puts back the slot recorded before a failed thread: slot, recorded offset.
+            Restore(usize, Option<usize>),
+        }
+
+        let mut slots = vec![None; slot_count];
+        let mut tried = HashSet::default();
+        let mut tasks = vec![Step::Try(0, start)];
+        while let Some(task) = tasks.pop() {
+            let (pc, pos) = match task {
+                Step::Try(pc, pos) => (pc, pos),
+                Step::Restore(slot, saved) => {
+                    slots[slot] = saved;
+                    continue;
+                }
+            };
+            if !tried.insert((pc, pos)) {
+                continue;
+            }
+
+            let c = haystack[pos..].chars().next();
+            let char_end = c.map_or(pos, |c| pos + c.len_utf8());
+            match (&self.insts[pc], c) {
+                (Inst::Match, _) => return Some((pos, slots)),
+                (Inst::Jump(to), _) => tasks.push(Step::Try(*to, pos)),
+                (Inst::Split(first, second), _) => {
+                    tasks.push(Step::Try(*second, pos));
+                    tasks.push(Step::Try(*first, pos));
+                }
+                (Inst::Assert(anchor), _) if anchor.holds(pos == 0, pos == haystack.len()) => {
+                    tasks.push(Step::Try(pc + 1, pos));
+                }
+                (Inst::Save(slot), _) => {
+                    if let Some(saved) = slots.get_mut(*slot) {
+                        tasks.push(Step::Restore(*slot, *saved));
+                        *saved = Some(pos);
+                    }
+                    tasks.push(Step::Try(pc + 1, pos));
+                }
+                (Inst::Char(expected), Some(c)) if *expected == c => {
+                    tasks.push(Step::Try(pc + 1, char_end));
+                }
+                (Inst::Any, Some(c)) => {
+                    tasks.push(Step::Try(pc + 1, clusters.wildcard_end(pos, c)));
+                }
+                (Inst::Class(ranges, is_negated), Some(c))
+                    if ClassRange::any_contains(ranges, c) != *is_negated =>
+                {
+                    tasks.push(Step::Try(pc + 1, char_end));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // Adds the threads the instruction leads to without reading, in the order of their priority,
+    // with the capture slots recorded on the way. `seen` has the position each instruction was
+    // last added at.
+    fn add_thread(
+        &self,
+        haystack: &str,
+        pos: usize,
+        thread: Thread,
+        threads: &mut Vec<Thread>,
+        seen: &mut [usize],
+        stack: &mut Vec<Thread>,
+    ) {
+        stack.push(thread);
+        while let Some((pc, mut slots)) = stack.pop() {
+            if seen[pc] == pos {
+                continue;
+            }
+            seen[pc] = pos;
+
+            match self.insts[pc] {
+                Inst::Jump(to) => stack.push((to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((second, slots.clone()));
+                    stack.push((first, slots));
+                }
+                Inst::Assert(anchor) => {
+                    if anchor.holds(pos == 0, pos == haystack.len()) {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::Save(slot) => {
+                    if let Some(saved) = slots.get_mut(slot) {
+                        *saved = Some(pos);
+                    }
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Fail => {}
+                _ => threads.push((pc, slots)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::*;
+    use crate::pike::*;
+
+    fn first_match_end(pattern: &str, haystack: &str) -> Option<usize> {
+        let program =
+            Program::new(&Parser::parse(pattern).unwrap(), &CompileBudget::default()).unwrap();
+        let end = program.first_match_end(haystack, 0, &Clusters::default());
+        // Backtracking finds the same match where the clusters are chars.
+        #[cfg(feature = "grapheme")]
+        assert_eq!(
+            end,
+            program.first_match_end(haystack, 0, &Clusters::graphemes(haystack))
+        );
+        end
+    }
+
+    #[test]
+    fn test_first_match_end() {
+        assert_eq!(Some(1), first_match_end("a|ab", "abab"));
+        assert_eq!(Some(2), first_match_end("ab|a", "abab"));
+        assert_eq!(Some(3), first_match_end("a*", "aaab"));
+        assert_eq!(Some(0), first_match_end("a*?", "aaab"));
+        assert_eq!(Some(2), first_match_end("a+?b", "ab"));
+        assert_eq!(Some(2), first_match_end("a{1,3}", "aa"));
+        assert_eq!(Some(1), first_match_end("a{1,3}?", "aaa"));
+        assert_eq!(Some(4), first_match_end("(a|ab)(c|bcd)", "abcd"));
+        assert_eq!(Some(2), first_match_end("(a|ab)(c|b)", "abc"));
+        assert_eq!(Some(1), first_match_end("(?:a|ab)?", "ab"));
+        assert_eq!(Some(3), first_match_end("(?:ab*)?", "abb"));
+        assert_eq!(Some(2), first_match_end("[^x]b$", "ab"));
+        assert_eq!(None, first_match_end("[^x]b$", "abc"));
+        assert_eq!(Some(0), first_match_end("(a*)*", "b"));
+        assert_eq!(None, first_match_end("x", "abc"));
+    }
+
+    #[test]
+    fn test_first_match_captures() {
+        let captures = |pattern: &str, haystack: &str| {
+            let program =
+                Program::new(&Parser::parse(pattern).unwrap(), &CompileBudget::default()).unwrap();
+            let slots = program.first_match_captures(haystack, 0, &Clusters::default());
+            #[cfg(feature = "grapheme")]
+            assert_eq!(
+                slots,
+                program.first_match_captures(haystack, 0, &Clusters::graphemes(haystack))
+            );
+            slots
+        };
+        assert_eq!(
+            Some(vec![Some(0), Some(2), None, None]),
+            captures("ab|(a)b", "ab")
+        );
+        assert_eq!(
+            Some(vec![Some(0), Some(2), Some(0), Some(1), Some(1), Some(2)]),
+            captures("(a|ab)(c|b)", "abc")
+        );
+        // The last iteration of a loop.
+        assert_eq!(
+            Some(vec![Some(0), Some(3), Some(2), Some(3)]),
+            captures("(?:(a)|b)*", "aba")
+        );
+        assert_eq!(None, captures("(a)x", "ab"));
+    }
+
+    #[cfg(feature = "grapheme")]
+    #[test]
+    fn test_first_match_end_graphemes() {
+        let first_match_end = |pattern: &str, haystack: &str| {
+            Program::new(&Parser::parse(pattern).unwrap(), &CompileBudget::default())
+                .unwrap()
+                .first_match_end(haystack, 0, &Clusters::graphemes(haystack))
+        };
+        // "e\u{301}" (e + combining accent) is a single cluster, which `.` reads at once.
+        assert_eq!(Some(5), first_match_end("a.b", "ae\u{301}b"));
+        assert_eq!(None, first_match_end("a.\\\u{301}b", "ae\u{301}b"));
+        assert_eq!(Some(4), first_match_end("ae\\\u{301}", "ae\u{301}b"));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::engine::*;
+use crate::parser::*;
+use crate::types::*;
+use serde_json::{json, Value};
+
+// Handles a `{"pattern": ..., "input": ...}` JSON request of a browser playground and returns
+// the JSON response with the AST, the automaton (DOT), the matcher trace, the result and how far
+// a full match got.
+pub fn handle(request: &str) -> String {
+    let request = match serde_json::from_str::<Value>(request) {
+        Ok(request) => request,
+        Err(err) => return json!({ "error": format!("Invalid JSON: {}", err) }).to_string(),
+    };
+
+    let (pattern, input) = match (request["pattern"].as_str(), request["input"].as_str()) {
+        (Some(pattern), Some(input)) => (pattern, input),
+        _ => return json!({ "error": "Missing pattern or input" }).to_string(),
+    };
+
+    let (ast, diagnostics) = Parser::parse_lenient(pattern);
+    if !diagnostics.is_empty() {
+        return json!({
+            "error": "Invalid pattern",
+            "ast": ast_to_json(&ast),
+            "diagnostics": diagnostics
+                .iter()
+                .map(|diagnostic| json!({
+                    "span": [diagnostic.span.0, diagnostic.span.1],
+                    "code": diagnostic.code.as_str(),
+                    "message": diagnostic.message,
+                    "suggestion": Parser::suggest(pattern, diagnostic),
+                }))
+                .collect::<Vec<_>>(),
+            "supported_syntax": Engine::supported_syntax()
+                .iter()
+                .map(|feature| format!("{:?}", feature))
+                .collect::<Vec<_>>(),
+        })
+        .to_string();
+    }
+
+    let ast_json = ast_to_json(&ast);
+    let engine = match Engine::from_ast(ast) {
+        Ok(engine) => engine,
+        Err(err) => return json!({ "error": err.to_string(), "ast": ast_json }).to_string(),
+    };
+    let (result, trace) = engine.trace(input);
+    let outcome = engine.match_outcome(input);
+
+    json!({
+        "ast": ast_json,
+        "automaton": engine.to_dot(),
+        "trace": trace
+            .iter()
+            .map(|(state, i)| json!([state, i]))
+            .collect::<Vec<_>>(),
+        "result": result,
+        "full_match": {
+            "matched": outcome.matched,
+            "furthest_pos": outcome.furthest_pos,
+            "ended_in_state_count": outcome.ended_in_state_count,
+        },
+    })
+    .to_string()
+}
+
+fn ast_to_json(section: &PatternSection) -> Value {
+    let items_to_json =
+        |list: &Vec<PatternSection>| list.iter().map(ast_to_json).collect::<Vec<_>>();
+
+    let mut out = match section {
+        PatternSection::And(list, _, _) => json!({ "kind": "and", "items": items_to_json(list) }),
+        PatternSection::Or(list, _, _) => json!({ "kind": "or", "items": items_to_json(list) }),
+        PatternSection::Group(section, _, kind, _) => json!({
+            "kind": "group",
+            "group": match kind {
+                GroupKind::Capturing => json!("capturing"),
+                GroupKind::NonCapturing => json!("non_capturing"),
+                GroupKind::Named(name) => json!({ "name": name }),
+            },
+            "item": ast_to_json(section),
+        }),
+        PatternSection::Char(c, _, _) => json!({ "kind": "char", "char": c.to_string() }),
+        PatternSection::Any(_, _) => json!({ "kind": "any" }),
+        PatternSection::Literal(s, _, _) => json!({ "kind": "literal", "value": s }),
+        PatternSection::CharGroup(ranges, _, is_negated, _) => json!({
+            "kind": "char_group",
+            "ranges": ranges
+                .iter()
+                .map(|range| json!([range.start.to_string(), range.end.to_string()]))
+                .collect::<Vec<_>>(),
+            "negated": is_negated,
+        }),
+        PatternSection::Anchor(anchor, _, _) => json!({
+            "kind": "anchor",
+            "anchor": match anchor {
+                Anchor::Start => "start",
+                Anchor::End => "end",
+            },
+        }),
+        PatternSection::Error(_, _) => json!({ "kind": "error" }),
+    };
+
+    let (start, end) = section.span();
+    out["span"] = json!([start, end]);
+
+    let quantifier = *section.get_mod();
+    out["mod"] = match quantifier.to_pattern().as_str() {
+        "" => json!(null),
+        pattern @ ("?" | "+" | "*") => json!(pattern),
+        _ => json!({ "min": quantifier.min, "max": quantifier.max, "greedy": quantifier.greedy }),
+    };
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::playground::*;
+
+    #[test]
+    fn test_handle() {
+        let response =
+            serde_json::from_str::<Value>(&handle(r#"{"pattern": "ab*", "input": "xab"}"#))
+                .unwrap();
+
+        assert_eq!(json!(true), response["result"]);
+        assert_eq!(
+            json!({
+                "kind": "and",
+                "mod": null,
+                "span": [0, 3],
+                "items": [
+                    { "kind": "char", "char": "a", "mod": null, "span": [0, 1] },
+                    { "kind": "char", "char": "b", "mod": "*", "span": [1, 3] },
+                ],
+            }),
+            response["ast"]
+        );
+        assert!(response["automaton"]
+            .as_str()
+            .unwrap()
+            .starts_with("digraph {"));
+        assert_eq!(json!([5, 0]), response["trace"][0]);
+        assert_eq!(json!(false), response["full_match"]["matched"]);
+        assert_eq!(json!(0), response["full_match"]["furthest_pos"]);
+    }
+
+    #[test]
+    fn test_handle_errors() {
+        let response = serde_json::from_str::<Value>(&handle("{")).unwrap();
+        assert!(response["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid JSON"));
+
+        let response = serde_json::from_str::<Value>(&handle(r#"{"pattern": "a"}"#)).unwrap();
+        assert_eq!(json!("Missing pattern or input"), response["error"]);
+
+        let response =
+            serde_json::from_str::<Value>(&handle(r#"{"pattern": "a[b", "input": "ab"}"#)).unwrap();
+        assert_eq!(json!("Invalid pattern"), response["error"]);
+        assert_eq!(
+            json!([{
+                "span": [1, 3],
+                "code": "E0001",
+                "message": "Missing end of char group",
+                "suggestion": "add ']' to close the char group opened at offset 1",
+            }]),
+            response["diagnostics"]
+        );
+        assert_eq!(json!("Literal"), response["supported_syntax"][0]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::engine::*;
+use crate::types::*;
+
+// Ready made engines for common formats, built from the AST directly. Use them for full matches,
+// eg: `recipes::iso_date().is_full_match("2024-02-29")`.
+
+// A `YYYY-MM-DD` date. The day is not checked against the month, eg: "2023-02-31" matches.
+pub fn iso_date() -> Engine {
+    let month = or(vec![
+        seq(vec![char('0'), class("123456789")]),
+        seq(vec![char('1'), class("012")]),
+    ]);
+    let day = or(vec![
+        seq(vec![char('0'), class("123456789")]),
+        seq(vec![class("12"), digit()]),
+        seq(vec![char('3'), class("01")]),
+    ]);
+
+    Engine::from_ast(seq(vec![
+        digit().with_mod(Quantifier::range(4, Some(4))),
+        char('-'),
+        month,
+        char('-'),
+        day,
+    ]))
+    .expect("Invalid recipe")
+}
+
+// A dotted IPv4 address with decimal parts from 0 to 255, without leading zeros.
+pub fn ipv4() -> Engine {
+    let part = || {
+        or(vec![
+            seq(vec![literal("25"), class("012345")]),
+            seq(vec![char('2'), class("01234"), digit()]),
+            seq(vec![char('1'), digit(), digit()]),
+            seq(vec![class("123456789"), digit()]),
+            digit(),
+        ])
+    };
+
+    Engine::from_ast(seq(vec![
+        part(),
+        literal("."),
+        part(),
+        literal("."),
+        part(),
+        literal("."),
+        part(),
+    ]))
+    .expect("Invalid recipe")
+}
+
+// A UUID in the hyphenated form, eg: "123e4567-e89b-12d3-a456-426614174000". Any case of the hex
+// digits.
+pub fn uuid() -> Engine {
+    let hex = |n| class("0123456789abcdefABCDEF").with_mod(Quantifier::range(n, Some(n)));
+
+    Engine::from_ast(seq(vec![
+        hex(8),
+        char('-'),
+        hex(4),
+        char('-'),
+        hex(4),
+        char('-'),
+        hex(4),
+        char('-'),
+        hex(12),
+    ]))
+    .expect("Invalid recipe")
+}
+
+fn seq(list: Vec<PatternSection>) -> PatternSection {
+    PatternSection::And(list, Quantifier::ONE, SourceSpan::default())
+}
+
+fn or(list: Vec<PatternSection>) -> PatternSection {
+    PatternSection::Or(list, Quantifier::ONE, SourceSpan::default())
+}
+
+fn char(c: char) -> PatternSection {
+    PatternSection::Char(c, Quantifier::ONE, SourceSpan::default())
+}
+
+fn literal(s: &str) -> PatternSection {
+    PatternSection::Literal(s.into(), Quantifier::ONE, SourceSpan::default())
+}
+
+fn class(chars: &str) -> PatternSection {
+    PatternSection::CharGroup(
+        chars.chars().map(|c| ClassRange::new(c, c)).collect(),
+        Quantifier::ONE,
+        false,
+        SourceSpan::default(),
+    )
+}
+
+fn digit() -> PatternSection {
+    PatternSection::CharGroup(
+        vec![ClassRange::new('0', '9')],
+        Quantifier::ONE,
+        false,
+        SourceSpan::default(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::recipes::*;
+
+    #[test]
+    fn test_iso_date() {
+        let engine = iso_date();
+        for date in ["2024-02-29", "1999-12-31", "0000-01-01", "2023-10-10"] {
+            assert!(engine.is_full_match(date), "{}", date);
+        }
+        for date in [
+            "2024-2-29",
+            "2024-00-10",
+            "2024-13-10",
+            "2024-01-00",
+            "2024-01-32",
+            "24-01-01",
+            "2024/01/01",
+            "2024-01-011",
+        ] {
+            assert!(!engine.is_full_match(date), "{}", date);
+        }
+        assert!(engine.is_match("due 2024-01-15."));
+    }
+
+    #[test]
+    fn test_ipv4() {
+        let engine = ipv4();
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "192.168.1.255",
+            "255.255.255.255",
+            "10.99.100.249",
+        ] {
+            assert!(engine.is_full_match(ip), "{}", ip);
+        }
+        for ip in [
+            "256.0.0.1",
+            "1.2.3",
+            "1.2.3.4.5",
+            "01.2.3.4",
+            "1..3.4",
+            "1x2x3x4",
+            "300.1.1.1",
+            "",
+        ] {
+            assert!(!engine.is_full_match(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_uuid() {
+        let engine = uuid();
+        assert!(engine.is_full_match("123e4567-e89b-12d3-a456-426614174000"));
+        assert!(engine.is_full_match("123E4567-E89B-12D3-A456-426614174000"));
+        assert!(!engine.is_full_match("123e4567e89b12d3a456426614174000"));
+        assert!(!engine.is_full_match("123e4567-e89b-12d3-a456-42661417400g"));
+        assert!(!engine.is_full_match("123e4567-e89b-12d3-a456-4266141740001"));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::types::*;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Engines beyond this many on a thread evict the buffers of another engine, whichever comes first
+// in the map, so short lived engines can't grow the pool forever.
+const MAX_POOLED_ENGINES: usize = 16;
+
+static NEXT_ENGINE_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static POOL: RefCell<HashMap<usize, Scratch>> = RefCell::new(HashMap::default());
+}
+
+// Unique identity of an engine, the key of its buffers in the pool.
+pub fn next_engine_id() -> usize {
+    NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Reusable buffers of the matcher.
+#[derive(Debug, Default)]
+pub struct Scratch {
+    pub stack: Vec<(State, usize)>,
+    pub visited: HashSet<(State, usize)>,
+    pub chars: Vec<char>,
+    // Char index of the end of the grapheme cluster of each char, in grapheme mode.
+    pub grapheme_ends: Vec<usize>,
+}
+
+impl Scratch {
+    // Takes the buffers of the engine out of the pool of the current thread. The buffers are not
+    // borrowed while matching, so a match within a match (eg: in a trace callback) is fine.
+    pub fn take(engine_id: usize) -> Scratch {
+        POOL.with(|pool| pool.borrow_mut().remove(&engine_id))
+            .unwrap_or_default()
+    }
+
+    // Returns the buffers to the pool of the current thread, emptied but keeping their capacity.
+    pub fn give_back(mut self, engine_id: usize) {
+        self.stack.clear();
+        self.visited.clear();
+        self.chars.clear();
+        self.grapheme_ends.clear();
+
+        POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() >= MAX_POOLED_ENGINES {
+                // Not the oldest, the map keeps no order.
+                if let Some(&evicted) = pool.keys().next() {
+                    pool.remove(&evicted);
+                }
+            }
+            pool.insert(engine_id, self);
+        });
+    }
+
+    #[cfg(test)]
+    pub fn is_pooled(engine_id: usize) -> bool {
+        POOL.with(|pool| pool.borrow().contains_key(&engine_id))
+    }
+}
@@ -0,0 +1,390 @@
+use crate::engine::*;
+use crate::types::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Patterns with a label each, eg: for routing or tagging log lines.
+#[derive(Debug)]
+pub struct EngineSet<L> {
+    rules: Vec<(Engine, L)>,
+    // Hits per rule (by index) and the count of classified strings, when recording stats.
+    hits: Option<Vec<AtomicUsize>>,
+    classified: AtomicUsize,
+}
+
+// Hit counts of a set, eg. to find dead or overly hot rules.
+#[derive(Debug, PartialEq)]
+pub struct SetStats<'a, L> {
+    pub classified: usize,
+    pub hits: Vec<(&'a L, usize)>,
+}
+
+impl<L> EngineSet<L> {
+    pub fn new() -> EngineSet<L> {
+        EngineSet {
+            rules: vec![],
+            hits: None,
+            classified: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn add(&mut self, pattern: &str, label: L) -> Result<(), RegexError> {
+        let engine = EngineBuilder::new(pattern).build()?;
+        self.rules.push((engine, label));
+        if let Some(hits) = &mut self.hits {
+            hits.push(AtomicUsize::new(0));
+        }
+        Ok(())
+    }
+
+    // Counts the hits of each rule from now on, see `stats`.
+    pub fn record_stats(&mut self) {
+        self.hits = Some(self.rules.iter().map(|_| AtomicUsize::new(0)).collect());
+        self.classified.store(0, Ordering::Relaxed);
+    }
+
+    // Label of the first pattern matching anywhere within the string.
+    pub fn classify(&self, s: &str) -> Option<&L> {
+        let idx = self.rules.iter().position(|(engine, _)| engine.is_match(s));
+
+        if let Some(hits) = &self.hits {
+            self.classified.fetch_add(1, Ordering::Relaxed);
+            if let Some(idx) = idx {
+                hits[idx].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        idx.map(|idx| &self.rules[idx].1)
+    }
+
+    // None unless `record_stats` was called.
+    pub fn stats(&self) -> Option<SetStats<'_, L>> {
+        let hits = self.hits.as_ref()?;
+        Some(SetStats {
+            classified: self.classified.load(Ordering::Relaxed),
+            hits: self
+                .rules
+                .iter()
+                .zip(hits)
+                .map(|((_, label), hits)| (label, hits.load(Ordering::Relaxed)))
+                .collect(),
+        })
+    }
+
+    // Scans a stream for all the patterns at once, see `SetScanner`.
+    pub fn scanner(&self) -> SetScanner<'_, L> {
+        SetScanner {
+            set: self,
+            scanners: self
+                .rules
+                .iter()
+                .map(|(engine, _)| engine.end_scanner())
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl<L> Default for EngineSet<L> {
+    fn default() -> EngineSet<L> {
+        EngineSet::new()
+    }
+}
+
+impl EngineSet<String> {
+    // Builds the set from `pattern => label` lines, eg:
+    //
+    //     # Access log routing
+    //     GET.*404 => not_found
+    //     POST => write
+    //
+    // Earlier rules win. Lines starting with `#` are comments.
+    pub fn from_rules(rules: &str) -> Result<EngineSet<String>, String> {
+        let mut lines = vec![];
+        let mut patterns = vec![];
+        let mut labels = vec![];
+
+        for (line_idx, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (pattern, label) = line
+                .split_once("=>")
+                .ok_or_else(|| format!("Invalid line {}: {}", line_idx + 1, line))?;
+            lines.push(line_idx + 1);
+            patterns.push(pattern.trim());
+            labels.push(label.trim().to_string());
+        }
+
+        // Compiled together, so the rules share their common classes.
+        let engines = Engine::compile_many(&patterns)
+            .map_err(|(i, err)| format!("Invalid pattern on line {}: {}", lines[i], err))?;
+
+        let mut set = EngineSet::new();
+        set.rules = engines.into_iter().zip(labels).collect();
+        Ok(set)
+    }
+
+    pub fn load(path: &str) -> Result<EngineSet<String>, String> {
+        let rules = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        EngineSet::from_rules(&rules)
+    }
+}
+
+// A match end found by a `SetScanner`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SetMatch {
+    // Index of the rule, in the order of the set.
+    pub rule: usize,
+    // Byte offset of the end of the match in the stream.
+    pub end: usize,
+}
+
+// Streaming scan for every rule of a set, reporting each match end in the chunk where it ends,
+// while scanning on, eg: for intrusion detection over network streams. Matches may span chunks.
+pub struct SetScanner<'a, L> {
+    set: &'a EngineSet<L>,
+    scanners: Vec<EndScanner<'a>>,
+}
+
+impl<'a, L> SetScanner<'a, L> {
+    // Feeds the next chunk and returns the match ends within it, ordered by offset then rule.
+    // Each rule reports an offset once, eg: `a|aa` on "aa" gives the ends 1 and 2.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SetMatch> {
+        let mut found = vec![];
+        for (rule, scanner) in self.scanners.iter_mut().enumerate() {
+            scanner.feed(chunk, |end| found.push(SetMatch { rule, end }));
+        }
+
+        found.sort_by_key(|found| (found.end, found.rule));
+        found
+    }
+
+    // Ends the input and returns the matches ending with it through `$`, see `EndScanner::finish`.
+    pub fn finish(&mut self) -> Vec<SetMatch> {
+        let mut found = vec![];
+        for (rule, scanner) in self.scanners.iter_mut().enumerate() {
+            scanner.finish(|end| found.push(SetMatch { rule, end }));
+        }
+
+        found.sort_by_key(|found| (found.end, found.rule));
+        found
+    }
+
+    pub fn label(&self, found: &SetMatch) -> &'a L {
+        &self.set.rules[found.rule].1
+    }
+}
+
+// A rules file set that can be reloaded while other threads classify with it, eg. in a long
+// running filter service. Readers keep the set they got until they ask for the current one again.
+#[derive(Debug)]
+pub struct SharedEngineSet {
+    current: RwLock<Arc<EngineSet<String>>>,
+    // Rules text of the current set, to skip recompiling an unchanged file.
+    rules: Mutex<String>,
+    // Whether every set built records its stats, see `EngineSet::record_stats`.
+    record_stats: bool,
+}
+
+impl SharedEngineSet {
+    pub fn load(path: &str) -> Result<SharedEngineSet, String> {
+        SharedEngineSet::load_with(path, false)
+    }
+
+    // Like `load`, with each set recording its stats from when it's swapped in. The counts of a
+    // set start over on a reload, as its rules may be different.
+    pub fn load_recording_stats(path: &str) -> Result<SharedEngineSet, String> {
+        SharedEngineSet::load_with(path, true)
+    }
+
+    fn load_with(path: &str, record_stats: bool) -> Result<SharedEngineSet, String> {
+        let rules = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let set = SharedEngineSet::build(&rules, record_stats)?;
+        Ok(SharedEngineSet {
+            current: RwLock::new(Arc::new(set)),
+            rules: Mutex::new(rules),
+            record_stats,
+        })
+    }
+
+    fn build(rules: &str, record_stats: bool) -> Result<EngineSet<String>, String> {
+        let mut set = EngineSet::from_rules(rules)?;
+        if record_stats {
+            set.record_stats();
+        }
+        Ok(set)
+    }
+
+    pub fn current(&self) -> Arc<EngineSet<String>> {
+        self.current.read().unwrap().clone()
+    }
+
+    // Recompiles the rules file and swaps it in. Returns whether the rules changed. On an error
+    // the current set stays in use.
+    pub fn reload_from(&self, path: &str) -> Result<bool, String> {
+        let rules = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+
+        let mut current_rules = self.rules.lock().unwrap();
+        if *current_rules == rules {
+            return Ok(false);
+        }
+
+        let set = SharedEngineSet::build(&rules, self.record_stats)?;
+        *self.current.write().unwrap() = Arc::new(set);
+        *current_rules = rules;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::set::*;
+
+    #[test]
+    fn test_classify() {
+        let set = EngineSet::from_rules(
+            "# Routing\n\
+             GET.*404 => not_found\n\
+             \n\
+             GET => read\n\
+             POST|PUT => write\n",
+        )
+        .unwrap();
+
+        assert_eq!(3, set.len());
+        assert_eq!(Some(&"not_found".into()), set.classify("x GET a 404"));
+        assert_eq!(Some(&"read".into()), set.classify("x GET a 200"));
+        assert_eq!(Some(&"write".into()), set.classify("PUT"));
+        assert_eq!(None, set.classify("DELETE"));
+    }
+
+    #[test]
+    fn test_scanner() {
+        let set = EngineSet::from_rules("ab+c => abc\nb+ => b\nx[^x]*x => x").unwrap();
+        let mut scanner = set.scanner();
+        let ends = |found: Vec<SetMatch>| {
+            found
+                .iter()
+                .map(|found| (set.rules[found.rule].1.as_str(), found.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![("b", 3)], ends(scanner.feed(b"xab")));
+        // Matches spanning chunks end in the later chunk, also with split chars.
+        assert_eq!(vec![("b", 4), ("abc", 5)], ends(scanner.feed(b"bc\xc3")));
+        assert_eq!(vec![("x", 8), ("b", 9)], ends(scanner.feed(b"\xa9xb")));
+        assert_eq!(Vec::<(&str, usize)>::new(), ends(scanner.feed(b"")));
+        assert_eq!("abc", scanner.label(&SetMatch { rule: 0, end: 5 }));
+    }
+
+    #[test]
+    fn test_scanner_anchors() {
+        let set = EngineSet::from_rules("^xa => start\nb$ => end").unwrap();
+        let mut scanner = set.scanner();
+
+        assert_eq!(vec![SetMatch { rule: 0, end: 2 }], scanner.feed(b"xabxa"));
+        assert_eq!(Vec::<SetMatch>::new(), scanner.feed(b"b"));
+        // `$` only holds once the input ended.
+        assert_eq!(vec![SetMatch { rule: 1, end: 6 }], scanner.finish());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut set = EngineSet::from_rules("GET => read\nPOST => write\nPUT => put").unwrap();
+        assert_eq!(None, set.stats());
+
+        set.record_stats();
+        set.classify("GET");
+        set.classify("x GET");
+        set.classify("POST");
+        set.classify("DELETE");
+
+        let stats = set.stats().unwrap();
+        assert_eq!(4, stats.classified);
+        assert_eq!(
+            vec![
+                (&"read".to_string(), 2),
+                (&"write".to_string(), 1),
+                (&"put".to_string(), 0)
+            ],
+            stats.hits
+        );
+    }
+
+    #[test]
+    fn test_from_rules_errors() {
+        assert_eq!(
+            "Invalid line 2: abc",
+            EngineSet::from_rules("a => x\nabc").unwrap_err()
+        );
+        assert_eq!(
+            "Invalid pattern on line 1: Missing end of char group at (1, 3)",
+            EngineSet::from_rules("a[b => x").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_shared_reload() {
+        let path = std::env::temp_dir().join(format!("regexp-rules-{}.txt", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        std::fs::write(&path, "GET => read\n").unwrap();
+
+        let shared = SharedEngineSet::load(path_str).unwrap();
+        let before = shared.current();
+        assert_eq!(Some(&"read".into()), before.classify("GET"));
+        assert_eq!(Ok(false), shared.reload_from(path_str));
+
+        std::fs::write(&path, "GET => get\nPOST => write\n").unwrap();
+        assert_eq!(Ok(true), shared.reload_from(path_str));
+        assert_eq!(Some(&"get".into()), shared.current().classify("GET"));
+        // The set taken before stays usable.
+        assert_eq!(Some(&"read".into()), before.classify("GET"));
+
+        // A broken file keeps the previous rules.
+        std::fs::write(&path, "a[b => broken\n").unwrap();
+        assert!(shared.reload_from(path_str).is_err());
+        assert_eq!(Some(&"write".into()), shared.current().classify("POST"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shared_reload_stats() {
+        let path = std::env::temp_dir().join(format!("regexp-stats-{}.txt", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        std::fs::write(&path, "GET => read\n").unwrap();
+
+        let shared = SharedEngineSet::load_recording_stats(path_str).unwrap();
+        shared.current().classify("GET");
+        assert_eq!(1, shared.current().stats().unwrap().classified);
+        assert_eq!(
+            None,
+            SharedEngineSet::load(path_str).unwrap().current().stats()
+        );
+
+        std::fs::write(&path, "GET => get\nPOST => write\n").unwrap();
+        assert_eq!(Ok(true), shared.reload_from(path_str));
+        let current = shared.current();
+        current.classify("POST");
+        current.classify("PUT");
+        assert_eq!(
+            Some(SetStats {
+                classified: 2,
+                hits: vec![(&"get".into(), 0), (&"write".into(), 1)],
+            }),
+            current.stats()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
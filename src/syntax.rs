@@ -0,0 +1,9 @@
+// Stable API of the patterns: the parser, the pattern tree and what it tells about the pattern, eg:
+// for highlighting or linting patterns.
+
+pub use crate::optimizer::Optimizer;
+pub use crate::parser::{Parser, SYNTAX};
+pub use crate::types::{
+    Anchor, CaptureGroup, Diagnostic, GroupKind, PatternFeatures, PatternSection, Quantifier,
+    SourceSpan, SyntaxFeature, SyntaxItem, Token, TokenKind,
+};
@@ -0,0 +1,205 @@
+// Random pattern generation for property tests, fuzzing and benchmarks.
+
+#[derive(Debug)]
+pub struct GeneratorOptions {
+    pub alphabet: Vec<char>,
+    // Nesting depth of groups.
+    pub max_depth: usize,
+    // Items of a sequence and branches of an alternation.
+    pub max_items: usize,
+    pub alternations: bool,
+    pub char_groups: bool,
+    pub wildcards: bool,
+    pub mods: bool,
+    pub ranges: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        GeneratorOptions {
+            alphabet: vec!['a', 'b', 'c'],
+            max_depth: 3,
+            max_items: 4,
+            alternations: true,
+            char_groups: true,
+            wildcards: true,
+            mods: true,
+            ranges: true,
+        }
+    }
+}
+
+pub struct PatternGenerator {
+    options: GeneratorOptions,
+    // Xorshift state, the same seed always generates the same patterns.
+    rng: u64,
+}
+
+impl PatternGenerator {
+    pub fn new(seed: u64, options: GeneratorOptions) -> PatternGenerator {
+        assert!(!options.alphabet.is_empty());
+        assert!(options.max_items >= 1);
+
+        PatternGenerator {
+            options,
+            rng: seed.max(1),
+        }
+    }
+
+    pub fn pattern(&mut self) -> String {
+        let mut out = String::new();
+        self.push_alternation(&mut out, self.options.max_depth);
+        out
+    }
+
+    // Random string over the alphabet with at most `max_len` chars.
+    pub fn input(&mut self, max_len: usize) -> String {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| self.alphabet_char()).collect()
+    }
+
+    fn push_alternation(&mut self, out: &mut String, depth: usize) {
+        let branches = if self.options.alternations && self.chance(4) {
+            1 + self.below(self.options.max_items)
+        } else {
+            1
+        };
+
+        for i in 0..branches {
+            if i > 0 {
+                out.push('|');
+            }
+            self.push_sequence(out, depth);
+        }
+    }
+
+    fn push_sequence(&mut self, out: &mut String, depth: usize) {
+        for _ in 0..1 + self.below(self.options.max_items) {
+            self.push_atom(out, depth);
+            self.push_mod(out);
+        }
+    }
+
+    fn push_atom(&mut self, out: &mut String, depth: usize) {
+        match self.below(6) {
+            0 if depth > 0 => {
+                out.push('(');
+                self.push_alternation(out, depth - 1);
+                out.push(')');
+            }
+            1 if self.options.char_groups => {
+                out.push('[');
+                if self.chance(3) {
+                    out.push('^');
+                }
+                for _ in 0..1 + self.below(3) {
+                    let c = self.alphabet_char();
+                    out.push(c);
+                }
+                out.push(']');
+            }
+            2 if self.options.wildcards => out.push('.'),
+            _ => {
+                let c = self.alphabet_char();
+                out.push(c);
+            }
+        }
+    }
+
+    fn push_mod(&mut self, out: &mut String) {
+        if !self.options.mods || !self.chance(3) {
+            return;
+        }
+
+        match self.below(4) {
+            0 => out.push('?'),
+            1 => out.push('+'),
+            2 => out.push('*'),
+            _ if self.options.ranges => {
+                let max = 1 + self.below(3);
+                let min = self.below(max + 1);
+                out.push_str(&format!("{{{},{}}}", min, max));
+            }
+            _ => out.push('?'),
+        }
+    }
+
+    fn alphabet_char(&mut self) -> char {
+        let i = self.below(self.options.alphabet.len());
+        self.options.alphabet[i]
+    }
+
+    // True with a 1 in n chance.
+    fn chance(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::engine::*;
+    use crate::parser::*;
+    use crate::testutil::*;
+    use crate::types::*;
+
+    #[test]
+    fn test_pattern_is_deterministic() {
+        let mut a = PatternGenerator::new(42, GeneratorOptions::default());
+        let mut b = PatternGenerator::new(42, GeneratorOptions::default());
+
+        for _ in 0..10 {
+            assert_eq!(a.pattern(), b.pattern());
+        }
+    }
+
+    #[test]
+    fn test_pattern_options() {
+        let mut generator = PatternGenerator::new(
+            7,
+            GeneratorOptions {
+                alphabet: vec!['x'],
+                alternations: false,
+                char_groups: false,
+                wildcards: false,
+                mods: false,
+                ..GeneratorOptions::default()
+            },
+        );
+
+        for _ in 0..100 {
+            assert!(generator.pattern().chars().all(|c| "x()".contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_generated_patterns() {
+        let mut generator = PatternGenerator::new(1, GeneratorOptions::default());
+
+        for _ in 0..200 {
+            let pattern = generator.pattern();
+            assert_eq!(Vec::<Diagnostic>::new(), Parser::parse_lenient(&pattern).1);
+
+            let engine = Engine::new(&pattern).unwrap();
+            for _ in 0..10 {
+                let input = generator.input(8);
+                assert_eq!(
+                    engine.is_match(&input),
+                    engine.stream_matcher().feed(input.as_bytes()).is_some(),
+                    "{} on {}",
+                    pattern,
+                    input
+                );
+                if engine.is_full_match(&input) {
+                    assert!(engine.is_match(&input), "{} on {}", pattern, input);
+                }
+            }
+        }
+    }
+}
@@ -1,257 +1,2353 @@
-use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Hash tables of the automata and the matchers. The `fxhash` feature swaps SipHash for the faster
+// FxHash, hashing the small state keys dominates compile profiles.
+#[cfg(feature = "fxhash")]
+pub type HashMap<K, V> = rustc_hash::FxHashMap<K, V>;
+#[cfg(feature = "fxhash")]
+pub type HashSet<T> = rustc_hash::FxHashSet<T>;
+#[cfg(not(feature = "fxhash"))]
+pub type HashMap<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "fxhash"))]
+pub type HashSet<T> = std::collections::HashSet<T>;
 
 pub type State = usize;
+// Start state of compiled patterns. Engines keep their own start state, as automata built in
+// other ways (eg: from a table) may start anywhere.
+pub const START_STATE: State = 0;
 pub type LeftT = (State, Option<char>);
 pub type TransitionAndEndState = (Transition, State);
+//              Start  End
+pub type Span = (usize, usize);
+pub type Token = (Span, TokenKind);
+// Sorted ranges of the chars a class move reads, shared by the engines compiled with the same
+// `CharClassInterner`. Negated char groups read the ranges of the chars they don't list.
+pub type CharClass = Arc<[ClassRange]>;
 
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub struct Transition {
     pub base: HashMap<LeftT, Vec<State>>,
-    //                   From           NotChars   To
-    pub negated: HashMap<State, HashMap<Vec<char>, Vec<State>>>,
+    //                   From           Ranges     To
+    pub classes: HashMap<State, HashMap<CharClass, Vec<State>>>,
+    // Moves reading any char, eg: for `.`, while a `'.'` in `base` is a literal dot.
+    pub wildcard: HashMap<State, Vec<State>>,
+    // Capture group boundaries recorded when entering the states, see `Engine::captures`.
+    pub tags: HashMap<State, Tag>,
+    // Moves reading no char, taken only where the anchor holds.
+    pub anchors: HashMap<(State, Anchor), Vec<State>>,
+}
+
+// Positions of the input a pattern can require, eg: `^ab$` only matches "ab".
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub enum Anchor {
+    Start, // `^`, the start of the input
+    End,   // `$`, the end of the input
+}
+
+impl Anchor {
+    pub fn holds(self, is_start: bool, is_end: bool) -> bool {
+        match self {
+            Anchor::Start => is_start,
+            Anchor::End => is_end,
+        }
+    }
+
+    pub fn to_pattern(self) -> &'static str {
+        match self {
+            Anchor::Start => "^",
+            Anchor::End => "$",
+        }
+    }
+}
+
+// Boundary of a capture group, by its 1-based index.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Tag {
+    Open(usize),
+    Close(usize),
 }
 
-impl Transition {
-    pub fn new() -> Transition {
-        Transition {
-            base: HashMap::new(),
-            negated: HashMap::new(),
-        }
+impl Tag {
+    // Index of the offset in the captured spans, 0 and 1 being the whole match.
+    pub fn slot(self) -> usize {
+        match self {
+            Tag::Open(group) => group * 2,
+            Tag::Close(group) => group * 2 + 1,
+        }
+    }
+}
+
+impl Transition {
+    pub fn new() -> Transition {
+        Transition {
+            base: HashMap::default(),
+            classes: HashMap::default(),
+            wildcard: HashMap::default(),
+            tags: HashMap::default(),
+            anchors: HashMap::default(),
+        }
+    }
+
+    pub fn merge(&mut self, other: Transition) {
+        for (k, mut v) in other.base {
+            self.base.entry(k).or_insert(vec![]).append(&mut v);
+        }
+
+        for (k, v) in other.classes {
+            let submap = self.classes.entry(k).or_default();
+            for (subk, mut subv) in v {
+                submap.entry(subk).or_insert(vec![]).append(&mut subv);
+            }
+        }
+
+        for (k, mut v) in other.wildcard {
+            self.wildcard.entry(k).or_default().append(&mut v);
+        }
+
+        self.tags.extend(other.tags);
+
+        for (k, mut v) in other.anchors {
+            self.anchors.entry(k).or_default().append(&mut v);
+        }
+    }
+
+    // Moves as (from, label, to), sorted, with `eps` for epsilon moves, `^` and `$` for anchors,
+    // `.` for the wildcard, `\.`, `\^` and `\$` for these chars and char groups for the classes,
+    // eg: `[a-z]` or `[^ab]`, see `ClassRange::to_pattern`, like the lines of `Engine::from_table`.
+    pub fn edges(&self) -> Vec<(State, String, State)> {
+        let mut edges = vec![];
+
+        for ((from, c), to_states) in &self.base {
+            let (rank, label) = match c {
+                None => (0, "eps".to_string()),
+                Some(c @ ('.' | '^' | '$')) => (1, format!("\\{}", c)),
+                Some(c) => (1, c.escape_debug().to_string()),
+            };
+            edges.extend(to_states.iter().map(|to| (*from, rank, label.clone(), *to)));
+        }
+
+        for ((from, anchor), to_states) in &self.anchors {
+            let label = anchor.to_pattern().to_string();
+            edges.extend(to_states.iter().map(|to| (*from, 0, label.clone(), *to)));
+        }
+
+        for (from, to_states) in &self.wildcard {
+            edges.extend(to_states.iter().map(|to| (*from, 1, ".".to_string(), *to)));
+        }
+
+        for (from, submap) in &self.classes {
+            for (class, to_states) in submap {
+                let label = ClassRange::to_pattern(class);
+                edges.extend(to_states.iter().map(|to| (*from, 2, label.clone(), *to)));
+            }
+        }
+
+        edges.sort();
+        edges.dedup();
+        edges
+            .into_iter()
+            .map(|(from, _, label, to)| (from, label, to))
+            .collect()
+    }
+
+    // Drops the moves of states not reachable from the roots or not leading to the finish state,
+    // eg: the dead ends left by compiling alternations.
+    pub fn prune(&mut self, roots: &[State], finish_state: State) {
+        let useful = self.useful_states(roots, finish_state);
+        let is_useful = |state: &State| useful.contains(state);
+
+        self.base.retain(|(from, _), to_states| {
+            to_states.retain(is_useful);
+            is_useful(from) && !to_states.is_empty()
+        });
+        self.classes.retain(|from, submap| {
+            submap.retain(|_, to_states| {
+                to_states.retain(is_useful);
+                !to_states.is_empty()
+            });
+            is_useful(from) && !submap.is_empty()
+        });
+        self.wildcard.retain(|from, to_states| {
+            to_states.retain(is_useful);
+            is_useful(from) && !to_states.is_empty()
+        });
+        self.anchors.retain(|(from, _), to_states| {
+            to_states.retain(is_useful);
+            is_useful(from) && !to_states.is_empty()
+        });
+        self.tags.retain(|state, _| is_useful(state));
+    }
+
+    // States on a path from the roots to the finish state.
+    pub fn useful_states(&self, roots: &[State], finish_state: State) -> HashSet<State> {
+        let mut forward: HashMap<State, Vec<State>> = HashMap::default();
+        let mut backward: HashMap<State, Vec<State>> = HashMap::default();
+        for (from, _, to) in self.edges() {
+            forward.entry(from).or_default().push(to);
+            backward.entry(to).or_default().push(from);
+        }
+
+        let reachable = |graph: &HashMap<State, Vec<State>>, roots: &[State]| {
+            let mut seen = roots.iter().copied().collect::<HashSet<_>>();
+            let mut todo = roots.to_vec();
+            while let Some(state) = todo.pop() {
+                for next in graph.get(&state).into_iter().flatten() {
+                    if seen.insert(*next) {
+                        todo.push(*next);
+                    }
+                }
+            }
+            seen
+        };
+        let from_roots = reachable(&forward, roots);
+        let to_finish = reachable(&backward, &[finish_state]);
+        from_roots.intersection(&to_finish).copied().collect()
+    }
+
+    // Merges states with the same moves (a self-loop counts the same on both), eg: the states
+    // after `a` and `c` in `ab|cb`, which both move on `b` to the end. The `keep` states are
+    // never merged into others and tagged states are never merged. Returns the number of
+    // merged states.
+    pub fn merge_equivalent(&mut self, keep: &[State]) -> usize {
+        let mut merged = 0;
+
+        loop {
+            let mut moves: HashMap<State, Vec<(String, Option<State>)>> = HashMap::default();
+            for (from, label, to) in self.edges() {
+                if self.tags.contains_key(&from) {
+                    continue;
+                }
+                let to = if to == from { None } else { Some(to) };
+                moves.entry(from).or_default().push((label, to));
+            }
+
+            let mut states = moves.keys().copied().collect::<Vec<_>>();
+            states.sort();
+
+            let mut representative: HashMap<&Vec<(String, Option<State>)>, State> =
+                HashMap::default();
+            let mut renames = HashMap::default();
+            for state in states {
+                let rep = *representative.entry(&moves[&state]).or_insert(state);
+                if rep != state && !keep.contains(&state) {
+                    renames.insert(state, rep);
+                }
+            }
+
+            if renames.is_empty() {
+                return merged;
+            }
+            merged += renames.len();
+            self.rename_states(&renames);
+        }
+    }
+
+    // Moves the moves of the renamed states to their new states (dropping the ones from the
+    // renamed states, which have the same moves as their new states).
+    fn rename_states(&mut self, renames: &HashMap<State, State>) {
+        let rename = |to_states: &mut Vec<State>| {
+            for to in to_states.iter_mut() {
+                *to = *renames.get(to).unwrap_or(to);
+            }
+            to_states.sort();
+            to_states.dedup();
+        };
+
+        self.base.retain(|(from, _), _| !renames.contains_key(from));
+        self.base.values_mut().for_each(rename);
+        self.classes.retain(|from, _| !renames.contains_key(from));
+        for submap in self.classes.values_mut() {
+            submap.values_mut().for_each(rename);
+        }
+        self.wildcard.retain(|from, _| !renames.contains_key(from));
+        self.wildcard.values_mut().for_each(rename);
+        self.anchors
+            .retain(|(from, _), _| !renames.contains_key(from));
+        self.anchors.values_mut().for_each(rename);
+    }
+
+    // Whether any move starts from the state.
+    fn has_moves_from(&self, state: State) -> bool {
+        self.base.keys().any(|(from, _)| *from == state)
+            || self.classes.contains_key(&state)
+            || self.wildcard.contains_key(&state)
+            || self.anchors.keys().any(|(from, _)| *from == state)
+    }
+
+    // Points the moves into the renamed states to their new states. Moves of the renamed states
+    // to the same state are kept apart, eg: the 2 branches of `(a|a)*`, see
+    // `Engine::complexity_estimate`.
+    fn redirect(&mut self, renames: &HashMap<State, State>) {
+        if renames.is_empty() {
+            return;
+        }
+
+        let redirect = |to_states: &mut Vec<State>| {
+            for to in to_states.iter_mut() {
+                *to = *renames.get(to).unwrap_or(to);
+            }
+        };
+        self.base.values_mut().for_each(redirect);
+        self.classes
+            .values_mut()
+            .for_each(|submap| submap.values_mut().for_each(redirect));
+        self.wildcard.values_mut().for_each(redirect);
+        self.anchors.values_mut().for_each(redirect);
+    }
+
+    pub fn size(&self) -> AutomatonSize {
+        let mut states = HashSet::default();
+        let mut edges = 0;
+        for ((from, _), to_states) in &self.base {
+            states.insert(*from);
+            states.extend(to_states);
+            edges += to_states.len();
+        }
+        for (from, submap) in &self.classes {
+            states.insert(*from);
+            for to_states in submap.values() {
+                states.extend(to_states);
+                edges += to_states.len();
+            }
+        }
+        for (from, to_states) in &self.wildcard {
+            states.insert(*from);
+            states.extend(to_states);
+            edges += to_states.len();
+        }
+        for ((from, _), to_states) in &self.anchors {
+            states.insert(*from);
+            states.extend(to_states);
+            edges += to_states.len();
+        }
+
+        AutomatonSize {
+            states: states.len(),
+            edges,
+        }
+    }
+
+    // Highest state with a move from or to it, None without moves.
+    pub fn max_state(&self) -> Option<State> {
+        let base = self
+            .base
+            .iter()
+            .flat_map(|((from, _), to)| to.iter().chain([from]));
+        let classes = self
+            .classes
+            .iter()
+            .flat_map(|(from, submap)| submap.values().flatten().chain([from]));
+        let wildcard = self
+            .wildcard
+            .iter()
+            .flat_map(|(from, to)| to.iter().chain([from]));
+        let anchors = self
+            .anchors
+            .iter()
+            .flat_map(|((from, _), to)| to.iter().chain([from]));
+        base.chain(classes)
+            .chain(wildcard)
+            .chain(anchors)
+            .max()
+            .copied()
+    }
+
+    pub fn insert_base(&mut self, k: LeftT, v: State) {
+        self.base.entry(k).or_insert(vec![]).push(v);
+    }
+
+    pub fn insert_wildcard(&mut self, state: State, to: State) {
+        self.wildcard.entry(state).or_default().push(to);
+    }
+
+    pub fn insert_anchor(&mut self, state: State, anchor: Anchor, to: State) {
+        self.anchors.entry((state, anchor)).or_default().push(to);
+    }
+
+    pub fn insert_class(&mut self, state: State, class: CharClass, to: State) {
+        let submap = self.classes.entry(state).or_default();
+        submap.entry(class).or_insert(vec![]).push(to);
+    }
+
+    // States reachable through epsilon moves only, including the state itself.
+    pub fn epsilon_closure(&self, state: State) -> Vec<State> {
+        self.epsilon_closure_at(state, false, false)
+    }
+
+    // Like `epsilon_closure`, also following the anchors holding at the position.
+    pub fn epsilon_closure_at(&self, state: State, is_start: bool, is_end: bool) -> Vec<State> {
+        let mut closure = vec![state];
+        let mut todo = vec![state];
+
+        while let Some(state) = todo.pop() {
+            for new_state in self.epsilon_moves(state, is_start, is_end) {
+                if !closure.contains(&new_state) {
+                    closure.push(new_state);
+                    todo.push(new_state);
+                }
+            }
+        }
+
+        closure
+    }
+
+    // Targets of the moves reading no char from the state: the epsilon moves and the moves of the
+    // anchors holding at the position.
+    pub fn epsilon_moves(
+        &self,
+        state: State,
+        is_start: bool,
+        is_end: bool,
+    ) -> impl Iterator<Item = State> + '_ {
+        let anchors = [Anchor::Start, Anchor::End]
+            .into_iter()
+            .filter(move |anchor| anchor.holds(is_start, is_end))
+            .flat_map(move |anchor| self.anchors.get(&(state, anchor)).into_iter().flatten());
+        self.base
+            .get(&(state, None))
+            .into_iter()
+            .flatten()
+            .chain(anchors)
+            .copied()
+    }
+
+    pub fn states_from(&self, state: State, c: Option<&char>, i: usize) -> Vec<(State, usize)> {
+        self.states_from_with(state, c, i, i + 1, i + 1)
+    }
+
+    // Like `states_from`, but reading the char moves on to `char_end` and the wildcard to
+    // `wildcard_end`, eg: to byte offsets or to the end of a grapheme cluster.
+    pub fn states_from_with(
+        &self,
+        state: State,
+        c: Option<&char>,
+        i: usize,
+        char_end: usize,
+        wildcard_end: usize,
+    ) -> Vec<(State, usize)> {
+        let mut out = vec![];
+
+        if let Some(c) = c {
+            if let Some(new_states) = self.base.get(&(state, Some(*c))) {
+                for new_state in new_states {
+                    out.push((*new_state, char_end));
+                }
+            }
+
+            if let Some(submap) = self.classes.get(&state) {
+                for (class, new_states) in submap {
+                    if ClassRange::any_contains(class, *c) {
+                        for new_state in new_states {
+                            out.push((*new_state, char_end));
+                        }
+                    }
+                }
+            }
+
+            if let Some(new_states) = self.wildcard.get(&state) {
+                for new_state in new_states {
+                    out.push((*new_state, wildcard_end));
+                }
+            }
+        }
+
+        if let Some(new_states) = self.base.get(&(state, None)) {
+            for new_state in new_states {
+                out.push((*new_state, i));
+            }
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BoundedMatch {
+    Match,
+    NoMatch,
+    // Only the first `processed_len` bytes were searched and they didn't match.
+    Truncated { processed_len: usize },
+}
+
+// Chars from `start` to `end`, both included.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ClassRange {
+    pub start: char,
+    pub end: char,
+}
+
+impl ClassRange {
+    pub const ALL: ClassRange = ClassRange {
+        start: '\0',
+        end: char::MAX,
+    };
+
+    pub fn new(start: char, end: char) -> ClassRange {
+        ClassRange { start, end }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.start <= c && c <= self.end
+    }
+
+    // Whether a char is in one of the sorted ranges, eg: of a `CharClass`.
+    pub fn any_contains(ranges: &[ClassRange], c: char) -> bool {
+        let i = ranges.partition_point(|range| range.end < c);
+        ranges.get(i).is_some_and(|range| range.contains(c))
+    }
+
+    // Whether a char is in all the classes, eg: `[a-f]` and `[^b-z]` share `a`.
+    pub fn overlap<'a>(classes: impl IntoIterator<Item = &'a [ClassRange]>) -> bool {
+        let mut shared = vec![ClassRange::ALL];
+        for class in classes {
+            // The chars outside either of them are outside their intersection.
+            let mut outside = ClassRange::complement(&shared);
+            outside.extend(ClassRange::complement(class));
+            shared = ClassRange::complement(&outside);
+        }
+        !shared.is_empty()
+    }
+
+    // Sorted ranges of all the chars not in the ranges.
+    pub fn complement(ranges: &[ClassRange]) -> Vec<ClassRange> {
+        let mut out = vec![];
+        let mut next = Some('\0');
+        for range in ClassRange::normalize(ranges.to_vec()) {
+            if let Some(start) = next.filter(|&start| start < range.start) {
+                out.push(ClassRange::new(start, char_before(range.start)));
+            }
+            next = char_after(range.end);
+        }
+        if let Some(start) = next {
+            out.push(ClassRange::new(start, char::MAX));
+        }
+        out
+    }
+
+    // Sorts the ranges and joins the overlapping and adjacent ones.
+    pub fn normalize(mut ranges: Vec<ClassRange>) -> Vec<ClassRange> {
+        ranges.sort_by_key(|range| range.start);
+
+        let mut out: Vec<ClassRange> = vec![];
+        for range in ranges {
+            match out.last_mut() {
+                Some(last) if char_after(last.end).is_none_or(|after| range.start <= after) => {
+                    last.end = last.end.max(range.end)
+                }
+                _ => out.push(range),
+            }
+        }
+        out
+    }
+
+    // The sorted ranges as a char group, eg: `[0-9a-f]`, written as the negated group of the other
+    // chars when they hold the last char, eg: `[^ab]`. `]`, `-`, `^`, `\\` and the whitespace are
+    // escaped, so the group is one word of a `Engine::from_table` line.
+    pub fn to_pattern(ranges: &[ClassRange]) -> String {
+        let is_negated = ranges.last().is_some_and(|range| range.end == char::MAX);
+        let (prefix, ranges) = if is_negated {
+            ("[^", ClassRange::complement(ranges))
+        } else {
+            ("[", ranges.to_vec())
+        };
+
+        let mut out = String::from(prefix);
+        for range in ranges {
+            push_class_char(&mut out, range.start);
+            // Ranges of 2 chars are written as the 2 chars, eg: `[^bc]`.
+            if char_after(range.start) == Some(range.end) {
+                push_class_char(&mut out, range.end);
+            } else if range.end != range.start {
+                out.push('-');
+                push_class_char(&mut out, range.end);
+            }
+        }
+        out.push(']');
+        out
+    }
+
+    // The sorted ranges of a char group written by `to_pattern`, None if it isn't one.
+    pub fn from_pattern(group: &str) -> Option<Vec<ClassRange>> {
+        let inner = group.strip_prefix('[')?.strip_suffix(']')?;
+        let (is_negated, inner) = match inner.strip_prefix('^') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+
+        // The chars + whether they were escaped.
+        let mut chars = vec![];
+        let mut it = inner.chars();
+        while let Some(c) = it.next() {
+            if c != '\\' {
+                chars.push((c, false));
+                continue;
+            }
+            let c = match it.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    let (hex, rest) = it.as_str().strip_prefix('{')?.split_once('}')?;
+                    let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+                    it = rest.chars();
+                    c
+                }
+                c => c,
+            };
+            chars.push((c, true));
+        }
+
+        let mut ranges = vec![];
+        let mut rest = chars.as_slice();
+        loop {
+            let (range, after) = match rest {
+                [] => break,
+                [(start, _), ('-', false), (end, _), after @ ..] => {
+                    (ClassRange::new(*start, *end), after)
+                }
+                [(c, _), after @ ..] => (ClassRange::new(*c, *c), after),
+            };
+            if range.start > range.end {
+                return None;
+            }
+            ranges.push(range);
+            rest = after;
+        }
+        Some(if is_negated {
+            ClassRange::complement(&ranges)
+        } else {
+            ClassRange::normalize(ranges)
+        })
+    }
+}
+
+// Partition of the chars into classes the automaton can't tell apart, eg: `[ab]c|[^x]` has 4
+// classes: `a` and `b`, `c`, `x` and the rest. A table of moves needs a column per class instead of
+// per char.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CharClasses {
+    // First char of each range with the class of the range, sorted.
+    ranges: Vec<(char, usize)>,
+    count: usize,
+}
+
+impl CharClasses {
+    pub fn new(transitions: &Transition) -> CharClasses {
+        type Moves = Vec<(State, Vec<State>)>;
+
+        // Moves reading each char, sorted.
+        let mut char_moves: HashMap<char, Moves> = HashMap::default();
+        for ((from, c), to) in &transitions.base {
+            if let Some(c) = c {
+                let mut to = to.clone();
+                to.sort();
+                char_moves.entry(*c).or_default().push((*from, to));
+            }
+        }
+        char_moves.values_mut().for_each(|moves| moves.sort());
+        let char_classes = transitions
+            .classes
+            .values()
+            .flat_map(|submap| submap.keys())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut starts = vec!['\0'];
+        for &c in char_moves.keys() {
+            starts.push(c);
+            starts.extend(char_after(c));
+        }
+        for range in char_classes.iter().flat_map(|class| class.iter()) {
+            starts.push(range.start);
+            starts.extend(char_after(range.end));
+        }
+        starts.sort();
+        starts.dedup();
+
+        // Ranges with the same moves share a class: the same char moves and the same char
+        // classes reading them. The wildcard reads all chars, so it never splits a class.
+        let mut classes: HashMap<(Option<&Moves>, Vec<bool>), usize> = HashMap::default();
+        let ranges = starts
+            .into_iter()
+            .map(|start| {
+                let signature = (
+                    char_moves.get(&start),
+                    char_classes
+                        .iter()
+                        .map(|class| ClassRange::any_contains(class, start))
+                        .collect(),
+                );
+                let next_class = classes.len();
+                (start, *classes.entry(signature).or_insert(next_class))
+            })
+            .collect();
+
+        CharClasses {
+            ranges,
+            count: classes.len(),
+        }
+    }
+
+    pub fn class_of(&self, c: char) -> usize {
+        let i = self.ranges.partition_point(|(start, _)| *start <= c);
+        self.ranges[i - 1].1
+    }
+
+    // First char of each range of chars with the class of the range, sorted.
+    pub fn ranges(&self) -> &[(char, usize)] {
+        &self.ranges
+    }
+
+    // Number of classes, at least 1.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // A char of each class, by class, eg: to find the moves of a whole class at once.
+    pub fn representatives(&self) -> Vec<char> {
+        let mut chars = vec![None; self.count];
+        for (start, class) in &self.ranges {
+            chars[*class].get_or_insert(*start);
+        }
+        chars
+            .into_iter()
+            .map(|c| c.expect("Class without chars"))
+            .collect()
+    }
+}
+
+// Writes a char of a `ClassRange::to_pattern` group.
+fn push_class_char(out: &mut String, c: char) {
+    match c {
+        ']' | '-' | '^' | '\\' => {
+            out.push('\\');
+            out.push(c);
+        }
+        ' ' => out.push_str("\\u{20}"),
+        c => out.extend(c.escape_debug()),
+    }
+}
+
+// Neighbouring chars, skipping the surrogate gap. Only called with chars that have them.
+fn char_before(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        c => char::from_u32(c as u32 - 1).expect("No char before"),
+    }
+}
+
+fn char_after(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        c => char::from_u32(c as u32 + 1),
+    }
+}
+
+// A match of `Engine::find`, with byte offsets into the haystack.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Match<'h> {
+    pub start: usize,
+    pub end: usize,
+    pub slice: &'h str,
+}
+
+impl Match<'_> {
+    pub fn span(&self) -> Span {
+        (self.start, self.end)
+    }
+
+    // Byte offsets on char boundaries, so `&haystack[m.range()]` can't panic.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+// Spans of the capture groups of a match, see `Engine::captures`. Group 0 is the whole match.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Captures<'h> {
+    pub(crate) haystack: &'h str,
+    // Group names by index, see `PatternSection::capture_names`.
+    pub(crate) names: Arc<[Option<String>]>,
+    // Start and end offsets of each group, in the order of `Tag::slot`.
+    pub(crate) slots: Vec<Option<usize>>,
+}
+
+impl<'h> Captures<'h> {
+    // The match of the group, None when the group took no part in the match, eg: `(a)|b` on "b".
+    pub fn get(&self, group: usize) -> Option<Match<'h>> {
+        let (start, end) = self.span(group)?;
+        Some(Match {
+            start,
+            end,
+            slice: &self.haystack[start..end],
+        })
+    }
+
+    // The match of the group named `(?P<name>...)` or `(?<name>...)`.
+    pub fn name(&self, name: &str) -> Option<Match<'h>> {
+        let group = self
+            .names
+            .iter()
+            .position(|group_name| group_name.as_deref() == Some(name))?;
+        self.get(group)
+    }
+
+    pub fn span(&self, group: usize) -> Option<Span> {
+        match self.slots.get(group * 2..group * 2 + 2)? {
+            [Some(start), Some(end)] => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    // Number of groups, the whole match included.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    // Always false, as the whole match is a group.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h>>> + '_ {
+        (0..self.len()).map(|group| self.get(group))
+    }
+
+    // Appends the replacement with its group references filled in: `$1` or `${1}` by index,
+    // `$name` or `${name}` by name and `$$` for a `$`. Groups not taking part in the match and
+    // unknown groups are empty. A `$` without a reference after it is kept.
+    pub fn expand(&self, replacement: &str, out: &mut String) {
+        let mut rest = replacement;
+        while let Some(dollar) = rest.find('$') {
+            out.push_str(&rest[..dollar]);
+            rest = &rest[dollar + 1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (reference, after) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.split_once('}') {
+                    Some((reference, after)) => (reference, after),
+                    None => ("", rest),
+                },
+                None => {
+                    let len = rest
+                        .find(|c: char| !c.is_alphanumeric() && c != '_')
+                        .unwrap_or(rest.len());
+                    rest.split_at(len)
+                }
+            };
+            if reference.is_empty() {
+                out.push('$');
+                continue;
+            }
+
+            out.push_str(self.reference(reference).map_or("", |found| found.slice));
+            rest = after;
+        }
+        out.push_str(rest);
+    }
+
+    // The group parsed, eg: `caps.parse::<u32>("year")`. The group is by index when it's a
+    // number and by name otherwise, like the references of `expand`.
+    pub fn parse<T: std::str::FromStr>(&self, group: &str) -> Result<T, CaptureParseError<T::Err>> {
+        let found = self
+            .reference(group)
+            .ok_or_else(|| CaptureParseError::Missing(group.to_string()))?;
+        found.slice.parse().map_err(CaptureParseError::Invalid)
+    }
+
+    // The match of the group by index or by name.
+    pub(crate) fn reference(&self, reference: &str) -> Option<Match<'h>> {
+        match reference.parse::<usize>() {
+            Ok(group) => self.get(group),
+            Err(_) => self.name(reference),
+        }
+    }
+}
+
+// Error of `Captures::parse`.
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum CaptureParseError<E> {
+    // The group took no part in the match or is not in the pattern.
+    Missing(String),
+    // The error of `FromStr`.
+    Invalid(E),
+}
+
+impl<E> CaptureParseError<E> {
+    // Converts the `FromStr` error, keeping the other errors, eg: to name the group in it.
+    pub fn map_invalid<F>(self, f: impl FnOnce(E) -> F) -> CaptureParseError<F> {
+        match self {
+            CaptureParseError::Missing(group) => CaptureParseError::Missing(group),
+            CaptureParseError::Invalid(err) => CaptureParseError::Invalid(f(err)),
+        }
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for CaptureParseError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CaptureParseError::Missing(group) => write!(f, "No match for group {}", group),
+            CaptureParseError::Invalid(err) => write!(f, "Invalid group: {}", err),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for CaptureParseError<E> {}
+
+// Types filled in from the groups of a match, eg: a struct of the fields of a log line. With the
+// `derive` feature, `#[derive(FromCaptures)]` parses each field from the group of the same name,
+// `Option` fields being None when the group took no part in the match. The `Invalid` errors name
+// the group, eg: "status: invalid digit found in string".
+pub trait FromCaptures: Sized {
+    fn from_captures(caps: &Captures) -> Result<Self, CaptureParseError<String>>;
+}
+
+// Layout version of `Checkpoint::to_bytes`, bumped on any change so old bytes are rejected.
+const CHECKPOINT_VERSION: u32 = 1;
+
+// Suspended state of a `StreamMatcher`, see `StreamMatcher::checkpoint` and `Engine::resume`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Checkpoint {
+    // Of the automaton the states belong to, see `Engine::fingerprint`.
+    pub(crate) fingerprint: u64,
+    // Active states with the byte offset where their match attempt started, sorted.
+    pub(crate) active: Vec<(State, usize)>,
+    pub(crate) offset: usize,
+    pub(crate) pending: Vec<u8>,
+    pub(crate) found: Option<Span>,
+}
+
+impl Checkpoint {
+    // Bytes consumed before the checkpoint.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Self-contained little endian encoding, eg: to resume the scan on another worker.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = CHECKPOINT_VERSION.to_le_bytes().to_vec();
+        out.extend_from_slice(&self.fingerprint.to_le_bytes());
+        let mut push = |n: usize| out.extend_from_slice(&(n as u64).to_le_bytes());
+        push(self.offset);
+        match self.found {
+            Some((start, end)) => {
+                push(1);
+                push(start);
+                push(end);
+            }
+            None => push(0),
+        }
+        push(self.active.len());
+        for &(state, start) in &self.active {
+            push(state);
+            push(start);
+        }
+        push(self.pending.len());
+        out.extend_from_slice(&self.pending);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Checkpoint, String> {
+        let mut rest = bytes;
+        let version = u32::from_le_bytes(take(&mut rest, 4)?.try_into().unwrap());
+        if version != CHECKPOINT_VERSION {
+            return Err(format!("Unsupported checkpoint version {}", version));
+        }
+
+        let fingerprint = take_u64(&mut rest)?;
+        let offset = take_usize(&mut rest)?;
+        let found = match take_u64(&mut rest)? {
+            0 => None,
+            _ => Some((take_usize(&mut rest)?, take_usize(&mut rest)?)),
+        };
+        let active = (0..take_usize(&mut rest)?)
+            .map(|_| Ok((take_usize(&mut rest)?, take_usize(&mut rest)?)))
+            .collect::<Result<_, String>>()?;
+        let pending_len = take_usize(&mut rest)?;
+        let pending = take(&mut rest, pending_len)?.to_vec();
+        if !rest.is_empty() {
+            return Err("Trailing bytes after the checkpoint".into());
+        }
+
+        Ok(Checkpoint {
+            fingerprint,
+            active,
+            offset,
+            pending,
+            found,
+        })
+    }
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if rest.len() < len {
+        return Err("Truncated checkpoint".into());
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(head)
+}
+
+fn take_u64(rest: &mut &[u8]) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(take(rest, 8)?.try_into().unwrap()))
+}
+
+fn take_usize(rest: &mut &[u8]) -> Result<usize, String> {
+    usize::try_from(take_u64(rest)?).map_err(|_| "Checkpoint value out of range".into())
+}
+
+// Result of `Engine::find_with_deadline`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DeadlineMatch {
+    Match(Span),
+    NoMatch,
+    // The deadline passed after scanning the first `scanned_len` bytes.
+    TimedOut { scanned_len: usize },
+}
+
+// Events of `Engine::scan`, in the order they are found. More kinds of events may be added.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum ScanEvent {
+    // Byte offset where a match starts, before its end is read.
+    MatchStart(usize),
+    // Index and span of a capture group taking part in the match, from its tagged states.
+    GroupCapture(usize, Span),
+    MatchEnd(Span),
+}
+
+// Byte offsets of the ends of the grapheme clusters of a haystack in grapheme mode, where the
+// wildcard reads a whole cluster and matches start at cluster boundaries. None otherwise, where
+// the wildcard reads a char.
+#[derive(Debug, Default)]
+pub struct Clusters {
+    ends: Option<Vec<usize>>,
+}
+
+impl Clusters {
+    #[cfg(feature = "grapheme")]
+    pub fn graphemes(haystack: &str) -> Clusters {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let ends = haystack
+            .grapheme_indices(true)
+            .map(|(i, grapheme)| i + grapheme.len())
+            .collect();
+        Clusters { ends: Some(ends) }
+    }
+
+    pub fn is_grapheme_mode(&self) -> bool {
+        self.ends.is_some()
+    }
+
+    // Where the wildcard reading the char at byte `pos` stops: the end of its cluster.
+    pub fn wildcard_end(&self, pos: usize, c: char) -> usize {
+        let char_end = pos + c.len_utf8();
+        match &self.ends {
+            Some(ends) => ends
+                .get(ends.partition_point(|&end| end <= pos))
+                .map_or(char_end, |&end| end.max(char_end)),
+            None => char_end,
+        }
+    }
+
+    // Cluster boundaries from byte `from` on in grapheme mode, where matches can start.
+    pub fn starts_from(&self, from: usize) -> Option<impl Iterator<Item = usize> + '_> {
+        let ends = self.ends.as_ref()?;
+        let rest = &ends[ends.partition_point(|&end| end < from)..];
+        Some(
+            (from == 0)
+                .then_some(0)
+                .into_iter()
+                .chain(rest.iter().copied()),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MatchOutcome {
+    pub matched: bool,
+    // Byte offset of the furthest char any state got to.
+    pub furthest_pos: usize,
+    // Number of states active where the matching stopped.
+    pub ended_in_state_count: usize,
+}
+
+// Where a string stopped matching the pattern.
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub span: Span,
+    // None when the string ended too early.
+    pub found: Option<char>,
+}
+
+impl Default for Transition {
+    fn default() -> Transition {
+        Transition::new()
+    }
+}
+
+impl std::fmt::Display for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (from, label, to) in self.edges() {
+            writeln!(f, "{} {} {}", from, label, to)?;
+        }
+        Ok(())
+    }
+}
+
+// The derived output of the nested maps is unreadable, eg: in `dbg!` and failed assertions.
+impl std::fmt::Debug for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Transition {{")?;
+        for (from, label, to) in self.edges() {
+            writeln!(f, "    {} {} {}", from, label, to)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.found {
+            Some(c) => write!(f, "Unexpected '{}' at {}", c, self.span.0),
+            None => write!(f, "Unexpected end of input at {}", self.span.0),
+        }
+    }
+}
+
+// Kinds of pattern errors with stable codes, eg: for editors mapping errors to docs and fixes.
+// New kinds get new codes, codes are never reused.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    UnterminatedClass,
+    UnterminatedRange,
+    InvalidRange,
+    InvalidNumber,
+    NothingToRepeat,
+    UnmatchedParen,
+    UnterminatedGroup,
+    UnexpectedCharacter,
+    CompileBudgetExceeded,
+    NullablePattern,
+    UnsupportedInSafeMode,
+    PatternTooLarge,
+    InvalidGroupName,
+    DuplicateGroupName,
+    TooManyCaptureGroups,
+    InvalidClassRange,
+    InvalidEscape,
+    RedundantWildcard,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedClass => "E0001",
+            ErrorCode::UnterminatedRange => "E0002",
+            ErrorCode::InvalidRange => "E0003",
+            ErrorCode::InvalidNumber => "E0004",
+            ErrorCode::NothingToRepeat => "E0005",
+            ErrorCode::UnmatchedParen => "E0006",
+            ErrorCode::UnterminatedGroup => "E0007",
+            ErrorCode::UnexpectedCharacter => "E0008",
+            ErrorCode::CompileBudgetExceeded => "E0009",
+            ErrorCode::NullablePattern => "E0010",
+            ErrorCode::UnsupportedInSafeMode => "E0011",
+            ErrorCode::PatternTooLarge => "E0012",
+            ErrorCode::InvalidGroupName => "E0013",
+            ErrorCode::DuplicateGroupName => "E0014",
+            ErrorCode::TooManyCaptureGroups => "E0015",
+            ErrorCode::InvalidClassRange => "E0016",
+            ErrorCode::InvalidEscape => "E0017",
+            ErrorCode::RedundantWildcard => "E0018",
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::UnterminatedClass => "Missing end of char group",
+            ErrorCode::UnterminatedRange => "Missing end of range",
+            ErrorCode::InvalidRange => "Invalid range",
+            ErrorCode::InvalidNumber => "Invalid number",
+            ErrorCode::NothingToRepeat => "Nothing to repeat",
+            ErrorCode::UnmatchedParen => "Unmatched ')'",
+            ErrorCode::UnterminatedGroup => "Missing end of group",
+            ErrorCode::UnexpectedCharacter => "Unexpected character",
+            ErrorCode::CompileBudgetExceeded => "Compilation exceeded the budget",
+            ErrorCode::NullablePattern => "Pattern matches the empty string",
+            ErrorCode::UnsupportedInSafeMode => "Not supported in safe mode",
+            ErrorCode::PatternTooLarge => "Pattern has too many states",
+            ErrorCode::InvalidGroupName => "Invalid group name",
+            ErrorCode::DuplicateGroupName => "Duplicate group name",
+            ErrorCode::TooManyCaptureGroups => "Too many capture groups",
+            ErrorCode::InvalidClassRange => "Invalid char range",
+            ErrorCode::InvalidEscape => "Invalid escape",
+            ErrorCode::RedundantWildcard => "Redundant wildcard in a search",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, code: ErrorCode) -> Diagnostic {
+        Diagnostic {
+            span,
+            code,
+            message: code.message().into(),
+        }
+    }
+}
+
+// Error of building an engine from a pattern.
+#[derive(Debug, PartialEq)]
+pub struct RegexError {
+    code: ErrorCode,
+    // None for errors of the whole pattern, eg: the compile budget.
+    span: Option<Span>,
+    message: String,
+    // Likely fix, eg: "add ')' to close the '(' opened at offset 3".
+    suggestion: Option<String>,
+}
+
+impl RegexError {
+    pub fn new(code: ErrorCode, span: Option<Span>, message: String) -> RegexError {
+        RegexError {
+            code,
+            span,
+            message,
+            suggestion: None,
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Option<String>) -> RegexError {
+        self.suggestion = suggestion;
+        self
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn suggestion(&self) -> Option<&str> {
+        self.suggestion.as_deref()
+    }
+}
+
+impl From<&Diagnostic> for RegexError {
+    fn from(diagnostic: &Diagnostic) -> RegexError {
+        RegexError::new(
+            diagnostic.code,
+            Some(diagnostic.span),
+            diagnostic.message.clone(),
+        )
+    }
+}
+
+impl std::fmt::Display for RegexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {:?}", self.message, span),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaptureGroup {
+    pub index: usize, // 1-based, in the order of the opening parens
+    pub span: Span,   // including the parens
+    pub children: Vec<CaptureGroup>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum RankDir {
+    TopBottom,
+    LeftRight,
+    BottomTop,
+    RightLeft,
+}
+
+impl RankDir {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RankDir::TopBottom => "TB",
+            RankDir::LeftRight => "LR",
+            RankDir::BottomTop => "BT",
+            RankDir::RightLeft => "RL",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DotOptions {
+    pub rankdir: Option<RankDir>,
+    pub show_state_ids: bool,
+    // Folds epsilon moves into the char edges after them.
+    pub collapse_epsilon: bool,
+    // Trace from `Engine::trace` to highlight on the graph.
+    pub highlight_path: Option<Vec<(State, usize)>>,
+}
+
+impl Default for DotOptions {
+    fn default() -> DotOptions {
+        DotOptions {
+            rankdir: None,
+            show_state_ids: true,
+            collapse_epsilon: false,
+            highlight_path: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Literal,
+    MetaChar,
+    Class,
+    Quantifier,
+    GroupOpen,
+    GroupClose,
+    Error,
+}
+
+// Worst case matching time of a pattern under a backtracking matcher, by the length of the string.
+// This engine's own matcher stays polynomial, see `Engine::complexity_estimate`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum Complexity {
+    Linear,
+    Polynomial,
+    Exponential,
+}
+
+// Syntax constructs of regex engines in general, whether supported by this one or not.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyntaxFeature {
+    Literal,
+    Wildcard,
+    CharClass,
+    NegatedCharClass,
+    Alternation,
+    Group,
+    Quantifiers,
+    CountedRepetition,
+    // `.` matching a grapheme cluster, with the `grapheme` cargo feature.
+    GraphemeWildcard,
+    ClassRanges,
+    Escapes,
+    PerlClasses,
+    Anchors,
+    Captures,
+    NamedCaptures,
+    Backreferences,
+    Lookaround,
+    CaseInsensitive,
+}
+
+impl SyntaxFeature {
+    pub const ALL: &'static [SyntaxFeature] = &[
+        SyntaxFeature::Literal,
+        SyntaxFeature::Wildcard,
+        SyntaxFeature::CharClass,
+        SyntaxFeature::NegatedCharClass,
+        SyntaxFeature::Alternation,
+        SyntaxFeature::Group,
+        SyntaxFeature::Quantifiers,
+        SyntaxFeature::CountedRepetition,
+        SyntaxFeature::GraphemeWildcard,
+        SyntaxFeature::ClassRanges,
+        SyntaxFeature::Escapes,
+        SyntaxFeature::PerlClasses,
+        SyntaxFeature::Anchors,
+        SyntaxFeature::Captures,
+        SyntaxFeature::NamedCaptures,
+        SyntaxFeature::Backreferences,
+        SyntaxFeature::Lookaround,
+        SyntaxFeature::CaseInsensitive,
+    ];
+
+    pub fn example(&self) -> &'static str {
+        match self {
+            SyntaxFeature::Literal => "abc",
+            SyntaxFeature::Wildcard => ".",
+            SyntaxFeature::CharClass => "[abc]",
+            SyntaxFeature::NegatedCharClass => "[^abc]",
+            SyntaxFeature::Alternation => "a|b",
+            SyntaxFeature::Group => "(ab)",
+            SyntaxFeature::Quantifiers => "a?b*c+",
+            SyntaxFeature::CountedRepetition => "a{1,3}",
+            SyntaxFeature::GraphemeWildcard => ".",
+            SyntaxFeature::ClassRanges => "[a-z]",
+            SyntaxFeature::Escapes => "\\.",
+            SyntaxFeature::PerlClasses => "\\d\\w\\s",
+            SyntaxFeature::Anchors => "^a$",
+            SyntaxFeature::Captures => "(a)",
+            SyntaxFeature::NamedCaptures => "(?P<name>a)",
+            SyntaxFeature::Backreferences => "(a)\\1",
+            SyntaxFeature::Lookaround => "(?=a)",
+            SyntaxFeature::CaseInsensitive => "(?i)a",
+        }
+    }
+
+    // Whether this build supports the construct, taking the cargo features into account.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            SyntaxFeature::Literal
+            | SyntaxFeature::Wildcard
+            | SyntaxFeature::CharClass
+            | SyntaxFeature::NegatedCharClass
+            | SyntaxFeature::Alternation
+            | SyntaxFeature::Group
+            | SyntaxFeature::Quantifiers
+            | SyntaxFeature::CountedRepetition
+            | SyntaxFeature::ClassRanges
+            | SyntaxFeature::Escapes
+            | SyntaxFeature::PerlClasses
+            | SyntaxFeature::Anchors
+            | SyntaxFeature::Captures
+            | SyntaxFeature::NamedCaptures => true,
+            SyntaxFeature::GraphemeWildcard => cfg!(feature = "grapheme"),
+            SyntaxFeature::Backreferences
+            | SyntaxFeature::Lookaround
+            | SyntaxFeature::CaseInsensitive => false,
+        }
+    }
+}
+
+// A construct the parser accepts, with an example of it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SyntaxItem {
+    pub example: &'static str,
+    // Token of the construct in the example.
+    pub kind: TokenKind,
+    pub description: &'static str,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    And,
+    Or,
+    Paren,
+}
+
+// How many times a section repeats, eg: `{2,}` is min 2 and no max. Lazy quantifiers (`a+?`) repeat
+// as few times as they can in the leftmost-first searches: `find`, `captures`, `find_iter` and the
+// replace and split APIs built on it. They match the same strings, so `is_match` and the
+// leftmost-longest `find_longest` and `captures_longest` don't tell them apart from greedy ones.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Quantifier {
+    pub min: usize,
+    pub max: Option<usize>, // None when unbounded
+    pub greedy: bool,
+}
+
+impl Quantifier {
+    pub const ONE: Quantifier = Quantifier::range(1, Some(1));
+    pub const ZERO_OR_ONE: Quantifier = Quantifier::range(0, Some(1));
+    pub const ONE_OR_MORE: Quantifier = Quantifier::range(1, None);
+    pub const ANY: Quantifier = Quantifier::range(0, None);
+
+    pub const fn range(min: usize, max: Option<usize>) -> Quantifier {
+        Quantifier {
+            min,
+            max,
+            greedy: true,
+        }
+    }
+
+    pub fn from(c: &char) -> Option<Quantifier> {
+        match c {
+            '?' => Some(Quantifier::ZERO_OR_ONE),
+            '+' => Some(Quantifier::ONE_OR_MORE),
+            '*' => Some(Quantifier::ANY),
+            _ => None,
+        }
+    }
+
+    pub fn lazy(self) -> Quantifier {
+        Quantifier {
+            greedy: false,
+            ..self
+        }
+    }
+
+    // The quantifier in pattern syntax, empty for `ONE`.
+    pub fn to_pattern(self) -> String {
+        let pattern = match (self.min, self.max) {
+            (1, Some(1)) => return String::new(),
+            (0, Some(1)) => "?".into(),
+            (1, None) => "+".into(),
+            (0, None) => "*".into(),
+            (min, None) => format!("{{{},}}", min),
+            (min, Some(max)) if min == max => format!("{{{}}}", min),
+            (min, Some(max)) => format!("{{{},{}}}", min, max),
+        };
+
+        if self.greedy {
+            pattern
+        } else {
+            pattern + "?"
+        }
+    }
+}
+
+// A pending step of the pattern compilation.
+enum CompileTask<'a> {
+    // Section with its quantifier: start.
+    Section(&'a PatternSection, State),
+    // Section ignoring its quantifier: start.
+    WithoutMod(&'a PatternSection, State),
+    // Items of an AND: list, index, end.
+    And(&'a Vec<PatternSection>, usize, State),
+    // Branches of an OR: list, index, start, branch ends.
+    Or(&'a Vec<PatternSection>, usize, State, Vec<State>),
+    // Copies of a quantified section: section, copies made, states skipping to the end.
+    Repeat(&'a PatternSection, usize, Vec<State>),
+    // Closes the loop of an unbounded quantifier: section, loop start.
+    Loop(&'a PatternSection, State),
+    // Tags the end of a capture group: group index.
+    CloseGroup(usize),
+}
+
+// The state after the given one, failing instead of overflowing on huge patterns.
+pub fn next_state(state: State) -> Result<State, RegexError> {
+    state.checked_add(1).ok_or_else(|| {
+        RegexError::new(
+            ErrorCode::PatternTooLarge,
+            None,
+            ErrorCode::PatternTooLarge.message().into(),
+        )
+    })
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct AutomatonSize {
+    pub states: usize,
+    pub edges: usize,
+}
+
+// Pattern and automaton sizes after each compilation pass, to see what the passes bought. Passes
+// turned off leave the size unchanged. The AST sizes are 0 for engines not built from a pattern.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct EngineStats {
+    pub ast_nodes: usize,
+    pub factored_ast_nodes: usize,
+    pub compiled: AutomatonSize,
+    pub pruned: AutomatonSize,
+    pub merged: AutomatonSize,
+    // Classes of chars with the same moves in the final automaton, see `CharClasses`.
+    pub char_classes: usize,
+}
+
+impl std::fmt::Display for EngineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "AST nodes:       {}", self.ast_nodes)?;
+        writeln!(f, "  factored:      {}", self.factored_ast_nodes)?;
+        for (name, size) in [
+            ("Compiled:       ", self.compiled),
+            ("  pruned:       ", self.pruned),
+            ("  merged:       ", self.merged),
+        ] {
+            writeln!(f, "{} {} states, {} edges", name, size.states, size.edges)?;
+        }
+        writeln!(f, "Char classes:    {}", self.char_classes)?;
+        Ok(())
+    }
+}
+
+// Limits of the pattern compilation, None is unlimited.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompileBudget {
+    pub max_steps: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+impl CompileBudget {
+    // Fails once a compilation started at `started_at` takes its step beyond the budget.
+    pub(crate) fn check(&self, steps: usize, started_at: Instant) -> Result<(), RegexError> {
+        if self.max_steps.is_some_and(|max_steps| steps > max_steps) {
+            return Err(RegexError::new(
+                ErrorCode::CompileBudgetExceeded,
+                None,
+                format!("Compilation exceeded the budget of {} steps", steps - 1),
+            ));
+        }
+        // Checking the clock on every step would be too costly.
+        if steps.is_multiple_of(1024)
+            && self
+                .max_duration
+                .is_some_and(|max_duration| started_at.elapsed() > max_duration)
+        {
+            return Err(RegexError::new(
+                ErrorCode::CompileBudgetExceeded,
+                None,
+                format!(
+                    "Compilation exceeded the budget of {:?}",
+                    self.max_duration.unwrap()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Keeps one copy of each char class, eg: of `[^"]` in thousands of patterns compiled together
+// with `Engine::compile_many`.
+#[derive(Debug, Default)]
+pub struct CharClassInterner {
+    classes: HashSet<CharClass>,
+}
+
+impl CharClassInterner {
+    pub fn intern(&mut self, ranges: &[ClassRange]) -> CharClass {
+        if let Some(class) = self.classes.get(ranges) {
+            return class.clone();
+        }
+
+        let class = CharClass::from(ranges);
+        self.classes.insert(class.clone());
+        class
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PatternFeatures {
+    pub has_captures: bool,
+    pub has_backrefs: bool,
+    pub has_lookaround: bool,
+    pub is_literal: bool,
+    pub is_anchored: bool,
+    pub max_len: Option<usize>, // None when unbounded
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum GroupKind {
+    Capturing,    // `(a)`
+    NonCapturing, // `(?:a)`
+    Named(String),
+}
+
+// Where a section is in the pattern. Spans don't take part in comparing sections, eg: the two
+// `a`s of `ab|ac` are the same prefix.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceSpan(pub Span);
+
+impl PartialEq for SourceSpan {
+    fn eq(&self, _other: &SourceSpan) -> bool {
+        true
     }
+}
 
-    pub fn merge(&mut self, other: Transition) {
-        for (k, mut v) in other.base {
-            self.base.entry(k).or_insert(vec![]).append(&mut v);
-        }
-
-        for (k, v) in other.negated {
-            let submap = self.negated.entry(k).or_insert(HashMap::new());
-            for (subk, mut subv) in v {
-                submap.entry(subk).or_insert(vec![]).append(&mut subv);
-            }
-        }
+impl SourceSpan {
+    // Span covering both, eg: of an AND from its first and last items.
+    pub fn to(self, other: SourceSpan) -> SourceSpan {
+        SourceSpan((self.0 .0.min(other.0 .0), self.0 .1.max(other.0 .1)))
     }
 
-    pub fn insert_base(&mut self, k: LeftT, v: State) {
-        self.base.entry(k).or_insert(vec![]).push(v);
+    // Span covering all the sections, empty at 0 for none.
+    pub fn of(list: &[PatternSection]) -> SourceSpan {
+        list.iter()
+            .map(|section| SourceSpan(section.span()))
+            .reduce(SourceSpan::to)
+            .unwrap_or_default()
     }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatternSection {
+    And(Vec<PatternSection>, Quantifier, SourceSpan),
+    Or(Vec<PatternSection>, Quantifier, SourceSpan),
+    Group(Box<PatternSection>, Quantifier, GroupKind, SourceSpan), // parenthesized section
+    Char(char, Quantifier, SourceSpan),
+    Any(Quantifier, SourceSpan), // `.`, any char
+    Literal(String, Quantifier, SourceSpan),
+    CharGroup(Vec<ClassRange>, Quantifier, bool, SourceSpan), // ranges + mod + is-negated + span
+    Anchor(Anchor, Quantifier, SourceSpan),
+    Error(Quantifier, SourceSpan), // invalid part of a leniently parsed pattern, never matches
+}
 
-    pub fn insert_negated(&mut self, state: State, not_chars: Vec<char>, to: State) {
-        let submap = self.negated.entry(state).or_insert(HashMap::new());
-        submap.entry(not_chars).or_insert(vec![]).push(to);
+impl PatternSection {
+    // Compiles the section into transitions using an explicit task stack instead of recursion, so
+    // deeply nested patterns can't overflow the call stack.
+    pub fn to_transition(&self, start: State, next: State) -> TransitionAndEndState {
+        self.to_transition_with_budget(
+            start,
+            next,
+            &CompileBudget::default(),
+            &mut CharClassInterner::default(),
+        )
+        .expect("Unlimited compilation failed")
     }
 
-    pub fn states_from(&self, state: State, c: Option<&char>, i: usize) -> Vec<(State, usize)> {
-        let mut out = vec![];
+    pub fn to_transition_with_budget(
+        &self,
+        start: State,
+        next: State,
+        budget: &CompileBudget,
+        interner: &mut CharClassInterner,
+    ) -> Result<TransitionAndEndState, RegexError> {
+        let mut out = Transition::new();
+        let capture_indices = self.capture_indices();
+        let mut tasks = vec![CompileTask::Section(self, start)];
+        // End states of the finished tasks, consumed by the tasks waiting for them.
+        let mut ends: Vec<State> = vec![];
+        // States are numbered in order of creation, from `next`.
+        let mut free = next;
+        let mut new_state = || {
+            let state = free;
+            free = next_state(free)?;
+            Ok::<_, RegexError>(state)
+        };
 
-        if let Some(c) = c {
-            if let Some(new_states) = self.base.get(&(state, Some(*c))) {
-                for new_state in new_states {
-                    out.push((*new_state, i + 1));
+        let started_at = Instant::now();
+        let mut steps = 0usize;
+
+        while let Some(task) = tasks.pop() {
+            steps += 1;
+            budget.check(steps, started_at)?;
+
+            match task {
+                CompileTask::Section(section, start) => {
+                    ends.push(start);
+                    tasks.push(CompileTask::Repeat(section, 0, vec![]));
                 }
-            }
+                CompileTask::WithoutMod(section, start) => match section {
+                    PatternSection::And(list, _, _) => tasks.push(CompileTask::And(list, 0, start)),
+                    PatternSection::Or(list, _, _) => {
+                        tasks.push(CompileTask::Or(list, 0, start, vec![]))
+                    }
+                    PatternSection::Group(section, _, GroupKind::NonCapturing, _) => {
+                        tasks.push(CompileTask::Section(section, start))
+                    }
+                    PatternSection::Group(inner, _, _, _) => {
+                        let group = capture_indices[&std::ptr::from_ref(section)];
+                        let open = new_state()?;
+                        out.insert_base((start, None), open);
+                        out.tags.insert(open, Tag::Open(group));
+                        tasks.push(CompileTask::CloseGroup(group));
+                        tasks.push(CompileTask::Section(inner, open));
+                    }
+                    PatternSection::Char(c, _, _) => {
+                        let end = new_state()?;
+                        out.insert_base((start, Some(*c)), end);
+                        ends.push(end);
+                    }
+                    PatternSection::Any(_, _) => {
+                        let end = new_state()?;
+                        out.insert_wildcard(start, end);
+                        ends.push(end);
+                    }
+                    PatternSection::Literal(literal, _, _) => {
+                        let mut end = start;
+                        for c in literal.chars() {
+                            let next = new_state()?;
+                            out.insert_base((end, Some(c)), next);
+                            end = next;
+                        }
+                        ends.push(end);
+                    }
+                    // Single chars are plain moves, the ranges are read by one class move, so a
+                    // range costs the same whatever its size, eg: `[ -\u{10FFFF}]`.
+                    PatternSection::CharGroup(ranges, _, is_negated, _) => {
+                        let end = new_state()?;
+                        if *is_negated {
+                            let class = ClassRange::complement(ranges);
+                            out.insert_class(start, interner.intern(&class), end);
+                        } else {
+                            let (chars, ranges): (Vec<ClassRange>, Vec<ClassRange>) =
+                                ranges.iter().partition(|range| range.start == range.end);
+                            for range in chars {
+                                out.insert_base((start, Some(range.start)), end);
+                            }
+                            if !ranges.is_empty() {
+                                let class = ClassRange::normalize(ranges);
+                                out.insert_class(start, interner.intern(&class), end);
+                            }
+                        }
+                        ends.push(end);
+                    }
+                    PatternSection::Anchor(anchor, _, _) => {
+                        let end = new_state()?;
+                        out.insert_anchor(start, *anchor, end);
+                        ends.push(end);
+                    }
+                    PatternSection::Error(_, _) => ends.push(new_state()?),
+                },
+                CompileTask::And(list, i, mut end) => {
+                    if i > 0 {
+                        end = ends.pop().expect("Missing end state");
+                    }
 
-            if let Some(new_states) = self.base.get(&(state, Some('.'))) {
-                for new_state in new_states {
-                    out.push((*new_state, i + 1));
+                    if i == list.len() {
+                        ends.push(end);
+                    } else {
+                        tasks.push(CompileTask::And(list, i + 1, end));
+                        tasks.push(CompileTask::Section(&list[i], end));
+                    }
                 }
-            }
+                CompileTask::Or(list, i, start, mut branch_ends) => {
+                    if i > 0 {
+                        branch_ends.push(ends.pop().expect("Missing end state"));
+                    }
 
-            if let Some(submap) = self.negated.get(&state) {
-                for (not_chars, new_states) in submap {
-                    if !not_chars.contains(c) {
-                        for new_state in new_states {
-                            out.push((*new_state, i + 1));
+                    if i == list.len() {
+                        // Branch ends without moves or tags of their own become the end of the
+                        // alternation, the others move on to it, eg: the loop ending `a*|b`.
+                        let (joined, moving): (Vec<_>, Vec<_>) =
+                            branch_ends.into_iter().partition(|&end| {
+                                end != start
+                                    && !out.has_moves_from(end)
+                                    && !out.tags.contains_key(&end)
+                            });
+                        let end = match joined.first() {
+                            Some(end) => *end,
+                            None => new_state()?,
+                        };
+                        let renames = joined
+                            .iter()
+                            .skip(1)
+                            .map(|&joined| (joined, end))
+                            .collect::<HashMap<_, _>>();
+                        out.redirect(&renames);
+                        for prev_end in moving {
+                            out.insert_base((prev_end, None), end);
+                        }
+
+                        ends.push(end);
+                    } else {
+                        tasks.push(CompileTask::Or(list, i + 1, start, branch_ends));
+                        tasks.push(CompileTask::Section(&list[i], start));
+                    }
+                }
+                // `{2,4}` is 2 copies, then 2 copies that can be skipped. `{2,}` is 1 copy, then a
+                // copy looping back to its start.
+                CompileTask::Repeat(section, i, mut skip_list) => {
+                    let end = ends.pop().expect("Missing end state");
+                    let quantifier = section.get_mod();
+
+                    match quantifier.max {
+                        Some(max) if i < max => {
+                            if i >= quantifier.min {
+                                skip_list.push(end);
+                            }
+                            tasks.push(CompileTask::Repeat(section, i + 1, skip_list));
+                            tasks.push(CompileTask::WithoutMod(section, end));
                         }
+                        Some(_) => {
+                            // Skipping to a state with moves of its own would skip into the
+                            // section, eg: into the loop ending `(?:ab+)?`.
+                            let end = if !skip_list.is_empty() && out.has_moves_from(end) {
+                                let exit = new_state()?;
+                                out.insert_base((end, None), exit);
+                                exit
+                            } else {
+                                end
+                            };
+                            for skip_state in skip_list {
+                                out.insert_base((skip_state, None), end);
+                            }
+                            ends.push(end);
+                        }
+                        None if i + 1 < quantifier.min => {
+                            tasks.push(CompileTask::Repeat(section, i + 1, skip_list));
+                            tasks.push(CompileTask::WithoutMod(section, end));
+                        }
+                        // Loops get their own entry state, so looping back can't reach other
+                        // sections starting at the same state, eg: the other branch of `a|b+` or
+                        // the `a+` loop of `a+b+`.
+                        None => {
+                            let loop_start = new_state()?;
+                            out.insert_base((end, None), loop_start);
+                            tasks.push(CompileTask::Loop(section, loop_start));
+                            tasks.push(CompileTask::WithoutMod(section, loop_start));
+                        }
+                    }
+                }
+                CompileTask::Loop(section, loop_start) => {
+                    let end = ends.pop().expect("Missing end state");
+                    out.insert_base((end, None), loop_start);
+                    // Zero or more repetitions end in an exit of the loop start, as skipping to
+                    // the loop start itself would skip into the loop, eg: of `(?:ab*)?`.
+                    if section.get_mod().min == 0 {
+                        let exit = new_state()?;
+                        out.insert_base((loop_start, None), exit);
+                        ends.push(exit);
+                    } else {
+                        ends.push(end);
                     }
                 }
+                CompileTask::CloseGroup(group) => {
+                    let end = ends.pop().expect("Missing end state");
+                    let close = new_state()?;
+                    out.insert_base((end, None), close);
+                    out.tags.insert(close, Tag::Close(group));
+                    ends.push(close);
+                }
             }
         }
 
-        if let Some(new_states) = self.base.get(&(state, None)) {
-            for new_state in new_states {
-                out.push((*new_state, i));
-            }
+        Ok((out, ends.pop().expect("Missing end state")))
+    }
+
+    pub fn features(&self) -> PatternFeatures {
+        PatternFeatures {
+            // There is no syntax for backrefs or lookarounds.
+            has_captures: self.has_captures(),
+            has_backrefs: false,
+            has_lookaround: false,
+            is_literal: self.is_literal(),
+            is_anchored: self.is_anchored(),
+            max_len: self.max_len(),
         }
+    }
 
-        out
+    // Whether every match starts with `^`, eg: `^a|^b` but not `^?a`.
+    fn is_anchored(&self) -> bool {
+        if self.get_mod().min == 0 {
+            return false;
+        }
+
+        match self {
+            PatternSection::Anchor(anchor, _, _) => *anchor == Anchor::Start,
+            PatternSection::And(list, _, _) => {
+                list.first().is_some_and(|first| first.is_anchored())
+            }
+            PatternSection::Or(list, _, _) => {
+                !list.is_empty() && list.iter().all(|branch| branch.is_anchored())
+            }
+            PatternSection::Group(section, _, _, _) => section.is_anchored(),
+            _ => false,
+        }
     }
-}
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Op {
-    And,
-    Or,
-    Paren,
-}
+    fn is_literal(&self) -> bool {
+        match self {
+            PatternSection::And(list, Quantifier::ONE, _) => {
+                list.iter().all(|section| section.is_literal())
+            }
+            PatternSection::Char(_, Quantifier::ONE, _) => true,
+            PatternSection::Literal(_, Quantifier::ONE, _) => true,
+            PatternSection::Group(section, Quantifier::ONE, _, _) => section.is_literal(),
+            _ => false,
+        }
+    }
 
-#[derive(Debug, PartialEq)]
-pub enum Mod {
-    One,
-    ZeroOrOne,
-    OneOrMore,
-    Any,
-    Range(usize, usize),
-}
+    // Longest literal every match contains, eg: "bc" for `a?bcd+e`. Inputs without it can't match.
+    pub fn required_literal(&self) -> Option<String> {
+        if self.get_mod().min == 0 {
+            return None;
+        }
 
-impl Mod {
-    pub fn from(c: &char) -> Option<Mod> {
-        match c {
-            '?' => Some(Mod::ZeroOrOne),
-            '+' => Some(Mod::OneOrMore),
-            '*' => Some(Mod::Any),
+        let literal = match self {
+            PatternSection::Char(c, _, _) => Some(c.to_string()),
+            PatternSection::Literal(s, _, _) => Some(s.clone()),
+            PatternSection::And(list, _, _) => {
+                let mut candidates = vec![];
+                // Adjacent plain chars and literals make a longer literal together.
+                let mut run = String::new();
+                for section in list {
+                    match section {
+                        PatternSection::Char(c, Quantifier::ONE, _) => run.push(*c),
+                        PatternSection::Literal(s, Quantifier::ONE, _) => run.push_str(s),
+                        _ => {
+                            candidates.push(std::mem::take(&mut run));
+                            candidates.extend(section.required_literal());
+                        }
+                    }
+                }
+                candidates.push(run);
+                candidates.into_iter().max_by_key(|s| s.len())
+            }
+            PatternSection::Group(section, _, _, _) => section.required_literal(),
             _ => None,
+        };
+
+        literal.filter(|s| !s.is_empty())
+    }
+
+    fn max_len(&self) -> Option<usize> {
+        let len = match self {
+            PatternSection::And(list, _, _) => list
+                .iter()
+                .map(|section| section.max_len())
+                .sum::<Option<usize>>()?,
+            PatternSection::Or(list, _, _) => list
+                .iter()
+                .map(|section| section.max_len())
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .max()
+                .unwrap_or(0),
+            PatternSection::Group(section, _, _, _) => section.max_len()?,
+            PatternSection::Char(_, _, _) | PatternSection::Any(_, _) => 1,
+            PatternSection::Literal(s, _, _) => s.chars().count(),
+            PatternSection::CharGroup(_, _, _, _) => 1,
+            PatternSection::Anchor(_, _, _) | PatternSection::Error(_, _) => 0,
+        };
+
+        match self.get_mod().max {
+            Some(max) => len.checked_mul(max),
+            None if len == 0 => Some(0),
+            None => None,
         }
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub enum PatternSection {
-    And(Vec<PatternSection>, Mod),
-    Or(Vec<PatternSection>, Mod),
-    Char(char, Mod),
-    CharGroup(Vec<char>, Mod, bool), // chars + mod + is-negated
-}
+    // English description of the section, eg: `ab+` is "'a', then one or more of 'b'".
+    pub fn explain(&self) -> String {
+        let without_mod = match self {
+            PatternSection::And(list, _, _) if list.is_empty() => "nothing".into(),
+            PatternSection::And(list, _, _) => list
+                .iter()
+                .map(|section| section.explain_nested())
+                .collect::<Vec<_>>()
+                .join(", then "),
+            PatternSection::Or(list, _, _) => format!(
+                "either {}",
+                list.iter()
+                    .map(|section| section.explain_nested())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            PatternSection::Group(section, _, _, _) => section.explain_nested(),
+            PatternSection::Char(c, _, _) => format!("'{}'", c),
+            PatternSection::Any(_, _) => "any character".into(),
+            PatternSection::Literal(s, _, _) => format!("'{}'", s),
+            PatternSection::CharGroup(ranges, _, is_negated, _) => format!(
+                "{} {}",
+                if *is_negated {
+                    "any character except"
+                } else {
+                    "one of"
+                },
+                ranges
+                    .iter()
+                    .map(|range| {
+                        if range.start == range.end {
+                            format!("'{}'", range.start)
+                        } else {
+                            format!("'{}' to '{}'", range.start, range.end)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            PatternSection::Anchor(Anchor::Start, _, _) => "the start of the input".into(),
+            PatternSection::Anchor(Anchor::End, _, _) => "the end of the input".into(),
+            PatternSection::Error(_, _) => "an invalid part".into(),
+        };
 
-impl PatternSection {
-    pub fn to_transition(&self, start: State, next: State) -> TransitionAndEndState {
-        let mut out = Transition::new();
+        let without_mod = match (self, *self.get_mod()) {
+            (_, Quantifier::ONE) => return without_mod,
+            (PatternSection::And(list, _, _), _) if list.len() > 1 => format!("({})", without_mod),
+            (PatternSection::Or(_, _, _), _) => format!("({})", without_mod),
+            _ => without_mod,
+        };
 
-        let (states, new_end) = self.to_transition_without_mod(start, next);
+        let quantified = match (self.get_mod().min, self.get_mod().max) {
+            (0, Some(1)) => format!("optionally {}", without_mod),
+            (1, None) => format!("one or more of {}", without_mod),
+            (0, None) => format!("zero or more of {}", without_mod),
+            (min, None) => format!("at least {} of {}", min, without_mod),
+            (min, Some(max)) if min == max => format!("exactly {} of {}", min, without_mod),
+            (min, Some(max)) => format!("between {} and {} of {}", min, max, without_mod),
+        };
 
-        out.merge(states);
-        let mut end = new_end;
+        if self.get_mod().greedy {
+            quantified
+        } else {
+            format!("{} (as few as possible)", quantified)
+        }
+    }
 
-        match self.get_mod() {
-            Mod::One => {}
-            Mod::ZeroOrOne => {
-                out.insert_base((start, None), end);
+    // Explanation wrapped in parens when it has multiple parts, so it reads unambiguously inside
+    // a sequence or an alternation.
+    fn explain_nested(&self) -> String {
+        match self {
+            PatternSection::And(list, Quantifier::ONE, _) if list.len() > 1 => {
+                format!("({})", self.explain())
             }
-            Mod::OneOrMore => {
-                out.insert_base((end, None), start);
+            PatternSection::Or(_, Quantifier::ONE, _) => format!("({})", self.explain()),
+            _ => self.explain(),
+        }
+    }
+
+    pub fn with_mod(self, m: Quantifier) -> PatternSection {
+        match self {
+            PatternSection::And(v, _, span) => PatternSection::And(v, m, span),
+            PatternSection::Or(v, _, span) => PatternSection::Or(v, m, span),
+            PatternSection::Group(v, _, kind, span) => PatternSection::Group(v, m, kind, span),
+            PatternSection::Char(v, _, span) => PatternSection::Char(v, m, span),
+            PatternSection::Any(_, span) => PatternSection::Any(m, span),
+            PatternSection::Literal(v, _, span) => PatternSection::Literal(v, m, span),
+            PatternSection::CharGroup(v, _, is_negated, span) => {
+                PatternSection::CharGroup(v, m, is_negated, span)
             }
-            Mod::Any => {
-                out.insert_base((end, None), start);
-                out.insert_base((start, None), end + 1);
-                end += 1;
+            PatternSection::Anchor(v, _, span) => PatternSection::Anchor(v, m, span),
+            PatternSection::Error(_, span) => PatternSection::Error(m, span),
+        }
+    }
+
+    // Number of sections, counted without recursion like `to_transition`.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut todo = vec![self];
+        while let Some(section) = todo.pop() {
+            count += 1;
+            match section {
+                PatternSection::And(list, _, _) | PatternSection::Or(list, _, _) => {
+                    todo.extend(list)
+                }
+                PatternSection::Group(section, _, _, _) => todo.push(section),
+                _ => {}
             }
-            Mod::Range(min, max) => {
-                let mut skip_list = vec![];
+        }
+        count
+    }
 
-                assert!(*max >= 1);
+    // Number of capturing groups, named ones included.
+    pub fn capture_count(&self) -> usize {
+        self.capture_groups().len()
+    }
 
-                if *min == 0 {
-                    skip_list.push(start);
-                }
+    pub fn has_captures(&self) -> bool {
+        self.capture_count() > 0
+    }
 
-                for i in 1..*max {
-                    if i >= *min {
-                        skip_list.push(end);
-                    }
-                    let (states, new_end) = self.to_transition_without_mod(end, end + 1);
-                    out.merge(states);
-                    end = new_end;
-                }
+    // Name of each capturing group by index, None for the whole match at 0 and unnamed groups.
+    pub fn capture_names(&self) -> Vec<Option<String>> {
+        let names = self.capture_groups().into_iter().map(|group| match group {
+            PatternSection::Group(_, _, GroupKind::Named(name), _) => Some(name.clone()),
+            _ => None,
+        });
+        std::iter::once(None).chain(names).collect()
+    }
+
+    // 1-based index of each capturing group. Keyed by address, as repeated groups are compiled
+    // more than once but capture to the same index.
+    pub(crate) fn capture_indices(&self) -> HashMap<*const PatternSection, usize> {
+        self.capture_groups()
+            .into_iter()
+            .enumerate()
+            .map(|(i, group)| (std::ptr::from_ref(group), i + 1))
+            .collect()
+    }
 
-                for skip_state in skip_list {
-                    out.insert_base((skip_state, None), end);
+    // Capturing groups in the order of their opening parens.
+    pub(crate) fn capture_groups(&self) -> Vec<&PatternSection> {
+        let mut groups = vec![];
+        let mut todo = vec![self];
+        while let Some(section) = todo.pop() {
+            match section {
+                PatternSection::And(list, _, _) | PatternSection::Or(list, _, _) => {
+                    todo.extend(list.iter().rev())
                 }
+                PatternSection::Group(inner, _, kind, _) => {
+                    if *kind != GroupKind::NonCapturing {
+                        groups.push(section);
+                    }
+                    todo.push(inner);
+                }
+                _ => {}
             }
         }
+        groups
+    }
+
+    // Spans of the `.*` (or equivalents, eg: `(?:.)*`) the pattern starts or ends with, also after
+    // `^` or before `$`. A search finds matches anywhere already, so they only make the matches
+    // longer and hide the literal parts from the prefilters.
+    pub fn redundant_wildcards(&self) -> Vec<Span> {
+        let list = match self {
+            PatternSection::And(list, Quantifier::ONE, _) => list.as_slice(),
+            section => std::slice::from_ref(section),
+        };
 
-        (out, end)
+        let (leading, trailing) = PatternSection::redundant_wildcard_counts(list);
+        let mut spans = vec![];
+        if leading > 0 {
+            spans.push((list[0].span().0, list[leading - 1].span().1));
+        }
+        if trailing > 0 {
+            spans.push((
+                list[list.len() - trailing].span().0,
+                list[list.len() - 1].span().1,
+            ));
+        }
+        spans
     }
 
-    fn to_transition_without_mod(&self, start: State, next: State) -> TransitionAndEndState {
-        match self {
-            PatternSection::And(list, _) => self.to_transition_and(list, start, next),
-            PatternSection::Or(list, _) => self.to_transition_or(list, start, next),
-            PatternSection::Char(c, _) => self.to_transition_char(*c, start, next),
-            PatternSection::CharGroup(cs, _, is_negated) => {
-                self.to_transition_char_group(cs, *is_negated, start, next)
+    // The pattern without the sections of `redundant_wildcards`. Searches match the same strings,
+    // but the matches leave out what the wildcards read, eg: `.*ab` finds "ab" in "xab", and full
+    // matches have to match without them.
+    pub fn strip_redundant_wildcards(self) -> PatternSection {
+        let (mut list, span) = match self {
+            PatternSection::And(list, Quantifier::ONE, span) => (list, span),
+            section => {
+                let span = SourceSpan(section.span());
+                (vec![section], span)
             }
+        };
+
+        let (leading, trailing) = PatternSection::redundant_wildcard_counts(&list);
+        list.truncate(list.len() - trailing);
+        list.drain(..leading);
+        match list.len() {
+            1 => list.pop().unwrap(),
+            _ => PatternSection::And(list, Quantifier::ONE, span),
         }
     }
 
-    fn to_transition_char_group(
-        &self,
-        chars: &Vec<char>,
-        is_negated: bool,
-        start: State,
-        next: State,
-    ) -> TransitionAndEndState {
-        let mut out = Transition::new();
+    // Number of sections of the redundant wildcards at the start and at the end of the sequence.
+    fn redundant_wildcard_counts(list: &[PatternSection]) -> (usize, usize) {
+        let is_anchor = |section: Option<&PatternSection>, anchor| match section {
+            Some(PatternSection::Anchor(a, Quantifier::ONE, _)) => *a == anchor,
+            _ => false,
+        };
+        let is_any_run = |section: Option<&PatternSection>| section.is_some_and(|s| s.is_any_run());
 
-        if is_negated {
-            out.insert_negated(start, chars.clone(), next);
+        let leading = if is_any_run(list.first()) {
+            1
+        } else if is_anchor(list.first(), Anchor::Start) && is_any_run(list.get(1)) {
+            2
         } else {
-            for c in chars {
-                out.insert_base((start, Some(*c)), next);
-            }
-        }
+            0
+        };
 
-        (out, next)
-    }
+        let rest = &list[leading..];
+        let trailing = if is_any_run(rest.last()) {
+            1
+        } else if is_anchor(rest.last(), Anchor::End) && is_any_run(rest.iter().nth_back(1)) {
+            2
+        } else {
+            0
+        };
 
-    fn to_transition_char(&self, c: char, start: State, next: State) -> TransitionAndEndState {
-        let mut out = Transition::new();
-        out.insert_base((start, Some(c)), next);
-        (out, next)
+        (leading, trailing)
     }
 
-    fn to_transition_and(
-        &self,
-        list: &Vec<PatternSection>,
-        start: State,
-        next: State,
-    ) -> TransitionAndEndState {
-        let mut out = Transition::new();
-        let mut end = start;
-        let mut new_next = next;
-
-        for section in list {
-            let (states, new_end) = section.to_transition(end, new_next);
-            out.merge(states);
-            end = new_end;
-            new_next = end + 1;
+    // Whether the section matches any run of chars, eg: `.*`, `.*?` or `(?:.)*`.
+    fn is_any_run(&self) -> bool {
+        let m = self.get_mod();
+        let repeats = m.min == 0 && m.max.is_none();
+        match self {
+            PatternSection::Any(_, _) => repeats,
+            PatternSection::Group(inner, _, GroupKind::NonCapturing, _) => match **inner {
+                PatternSection::Any(Quantifier::ONE, _) => repeats,
+                _ => (repeats || *m == Quantifier::ONE) && inner.is_any_run(),
+            },
+            _ => false,
         }
-
-        (out, end)
     }
 
-    fn to_transition_or(
-        &self,
-        list: &Vec<PatternSection>,
-        start: State,
-        next: State,
-    ) -> TransitionAndEndState {
-        let mut out = Transition::new();
-        let mut latest_end = start;
-        let mut new_next = next;
-        let mut ends = vec![];
+    // Turns every capturing group into a non-capturing one, eg: to match with the smaller
+    // automaton when the groups are only used for precedence.
+    pub fn strip_captures(&mut self) {
+        let mut todo = vec![self];
+        while let Some(section) = todo.pop() {
+            match section {
+                PatternSection::And(list, _, _) | PatternSection::Or(list, _, _) => {
+                    todo.extend(list.iter_mut())
+                }
+                PatternSection::Group(inner, _, kind, _) => {
+                    *kind = GroupKind::NonCapturing;
+                    todo.push(inner);
+                }
+                _ => {}
+            }
+        }
+    }
 
-        for section in list {
-            let (states, new_end) = section.to_transition(start, new_next);
-            ends.push(new_end);
-            latest_end = new_end;
-            new_next = latest_end + 1;
-            out.merge(states);
+    // Drops the section without recursion, the default drop of a deeply nested one would overflow
+    // the stack.
+    pub fn drop_flat(self) {
+        let mut todo = vec![self];
+        while let Some(section) = todo.pop() {
+            match section {
+                PatternSection::And(list, _, _) | PatternSection::Or(list, _, _) => {
+                    todo.extend(list)
+                }
+                PatternSection::Group(section, _, _, _) => todo.push(*section),
+                _ => {}
+            }
         }
+    }
 
-        // Todo: figure out how to skip the +1 last transition.
-        for prev_end in ends {
-            out.insert_base((prev_end, None), latest_end + 1);
+    // Where the section is in the pattern, including its quantifier.
+    pub fn span(&self) -> Span {
+        match self {
+            PatternSection::And(_, _, span)
+            | PatternSection::Or(_, _, span)
+            | PatternSection::Group(_, _, _, span)
+            | PatternSection::Char(_, _, span)
+            | PatternSection::Any(_, span)
+            | PatternSection::Literal(_, _, span)
+            | PatternSection::CharGroup(_, _, _, span)
+            | PatternSection::Anchor(_, _, span)
+            | PatternSection::Error(_, span) => span.0,
         }
+    }
 
-        (out, latest_end + 1)
+    pub fn span_mut(&mut self) -> &mut SourceSpan {
+        match self {
+            PatternSection::And(_, _, span)
+            | PatternSection::Or(_, _, span)
+            | PatternSection::Group(_, _, _, span)
+            | PatternSection::Char(_, _, span)
+            | PatternSection::Any(_, span)
+            | PatternSection::Literal(_, _, span)
+            | PatternSection::CharGroup(_, _, _, span)
+            | PatternSection::Anchor(_, _, span)
+            | PatternSection::Error(_, span) => span,
+        }
     }
 
-    fn get_mod(&self) -> &Mod {
+    pub fn get_mod(&self) -> &Quantifier {
         match self {
-            PatternSection::And(_, m) => m,
-            PatternSection::Or(_, m) => m,
-            PatternSection::Char(_, m) => m,
-            PatternSection::CharGroup(_, m, _) => m,
+            PatternSection::And(_, m, _) => m,
+            PatternSection::Or(_, m, _) => m,
+            PatternSection::Group(_, m, _, _) => m,
+            PatternSection::Char(_, m, _) => m,
+            PatternSection::Any(m, _) => m,
+            PatternSection::Literal(_, m, _) => m,
+            PatternSection::CharGroup(_, m, _, _) => m,
+            PatternSection::Anchor(_, m, _) => m,
+            PatternSection::Error(m, _) => m,
         }
     }
 }
@@ -272,7 +2368,7 @@ mod test {
             transition_this("abc"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
+                    .with_base(HashMap::from_iter([
                         ((0, Some('a')), vec![1]),
                         ((1, Some('b')), vec![2]),
                         ((2, Some('c')), vec![3]),
@@ -289,17 +2385,52 @@ mod test {
             transition_this("a|b.|3"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
+                    .with_base(HashMap::from_iter([
                         ((0, Some('a')), vec![1]),
                         ((0, Some('b')), vec![2]),
-                        ((2, Some('.')), vec![3]),
-                        ((0, Some('3')), vec![4]),
-                        ((1, None), vec![5]),
-                        ((3, None), vec![5]),
-                        ((4, None), vec![5]),
+                        ((0, Some('3')), vec![1]),
                     ]))
+                    .with_wildcard(HashMap::from_iter([(2, vec![1])]))
+                    .build(),
+                1
+            )
+        );
+
+        // The branches end in the same state, without a join state and epsilon moves.
+        let (transitions, end) = transition_this("a|b");
+        assert_eq!(1, end);
+        assert_eq!(
+            AutomatonSize {
+                states: 2,
+                edges: 2
+            },
+            transitions.size()
+        );
+        // Ends with moves of their own move on to it.
+        assert_eq!(
+            vec![
+                (0, "eps".to_string(), 2),
+                (0, "a".to_string(), 1),
+                (2, "eps".to_string(), 1),
+                (2, "b".to_string(), 3),
+                (3, "c".to_string(), 4),
+                (4, "eps".to_string(), 2),
+            ],
+            transition_this("a|(?:bc)*").0.edges()
+        );
+    }
+
+    #[test]
+    fn test_wildcard() {
+        // The escaped dot is a char move, the wildcard has moves of its own.
+        assert_eq!(
+            transition_this("\\.."),
+            (
+                TransitionBuilder::new()
+                    .with_base(HashMap::from_iter([((0, Some('.')), vec![1])]))
+                    .with_wildcard(HashMap::from_iter([(1, vec![2])]))
                     .build(),
-                5
+                2
             )
         );
     }
@@ -310,19 +2441,20 @@ mod test {
             transition_this("a+"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
-                        ((0, Some('a')), vec![1]),
-                        ((1, None), vec![0])
+                    .with_base(HashMap::from_iter([
+                        ((0, None), vec![1]),
+                        ((1, Some('a')), vec![2]),
+                        ((2, None), vec![1])
                     ]))
                     .build(),
-                1,
+                2,
             ),
         );
         assert_eq!(
             transition_this("a?"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
+                    .with_base(HashMap::from_iter([
                         ((0, Some('a')), vec![1]),
                         ((0, None), vec![1])
                     ]))
@@ -334,13 +2466,14 @@ mod test {
             transition_this("a*"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
-                        ((0, Some('a')), vec![1]),
-                        ((1, None), vec![0]),
-                        ((0, None), vec![2])
+                    .with_base(HashMap::from_iter([
+                        ((0, None), vec![1]),
+                        ((1, Some('a')), vec![2]),
+                        ((2, None), vec![1]),
+                        ((1, None), vec![3])
                     ]))
                     .build(),
-                2
+                3
             ),
         );
     }
@@ -351,7 +2484,7 @@ mod test {
             transition_this("[ab]"),
             (
                 TransitionBuilder::new()
-                    .with_base(HashMap::from([
+                    .with_base(HashMap::from_iter([
                         ((0, Some('b')), vec![1]),
                         ((0, Some('a')), vec![1]),
                     ]))
@@ -364,9 +2497,15 @@ mod test {
             transition_this("[^ab]"),
             (
                 TransitionBuilder::new()
-                    .with_negated(HashMap::from([(
+                    .with_classes(HashMap::from_iter([(
                         0,
-                        HashMap::from([(vec!['a', 'b'], vec![1])])
+                        HashMap::from_iter([(
+                            CharClass::from([
+                                ClassRange::new('\0', '`'),
+                                ClassRange::new('c', char::MAX)
+                            ]),
+                            vec![1]
+                        )])
                     ),]))
                     .build(),
                 1,
@@ -374,8 +2513,192 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_state_overflow() {
+        let pattern = Parser::parse(&"(ab|c)*d{2}".repeat(100)).unwrap();
+        let err = pattern
+            .to_transition_with_budget(
+                State::MAX - 100,
+                State::MAX - 99,
+                &CompileBudget::default(),
+                &mut CharClassInterner::default(),
+            )
+            .unwrap_err();
+        assert_eq!(ErrorCode::PatternTooLarge, err.code());
+
+        assert!(pattern
+            .to_transition_with_budget(
+                0,
+                1,
+                &CompileBudget::default(),
+                &mut CharClassInterner::default(),
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_class_range() {
+        assert_eq!(
+            vec![
+                ClassRange::new('\0', '\u{D7FF}'),
+                ClassRange::new('\u{E001}', char::MAX)
+            ],
+            ClassRange::complement(&[ClassRange::new('\u{E000}', '\u{E000}')])
+        );
+        assert_eq!(
+            vec![
+                ClassRange::new('\u{1}', '`'),
+                ClassRange::new('b', char::MAX)
+            ],
+            ClassRange::complement(&[
+                ClassRange::new('a', 'a'),
+                ClassRange::new('\0', '\0'),
+                ClassRange::new('a', 'a'),
+            ])
+        );
+        assert_eq!(
+            vec![ClassRange::new('a', 'e'), ClassRange::new('x', 'x')],
+            ClassRange::normalize(vec![
+                ClassRange::new('x', 'x'),
+                ClassRange::new('d', 'e'),
+                ClassRange::new('a', 'c'),
+                ClassRange::new('b', 'b'),
+            ])
+        );
+        assert_eq!(
+            vec![ClassRange::ALL],
+            ClassRange::normalize(vec![
+                ClassRange::new('\u{E000}', char::MAX),
+                ClassRange::new('\0', '\u{D7FF}'),
+            ])
+        );
+
+        let class = [ClassRange::new('0', '9'), ClassRange::new('a', 'f')];
+        assert!(ClassRange::any_contains(&class, '5'));
+        assert!(ClassRange::any_contains(&class, 'f'));
+        assert!(!ClassRange::any_contains(&class, 'g'));
+        assert!(!ClassRange::any_contains(&class, '\0'));
+
+        let not_b_to_z = ClassRange::complement(&[ClassRange::new('b', 'z')]);
+        assert!(ClassRange::overlap([&class[..], &not_b_to_z]));
+        assert!(!ClassRange::overlap([
+            &class[1..],
+            &[ClassRange::new('x', 'z')]
+        ]));
+    }
+
+    #[test]
+    fn test_class_range_pattern() {
+        let class = vec![ClassRange::new(' ', '-'), ClassRange::new('a', 'z')];
+        assert_eq!("[\\u{20}-\\-a-z]", ClassRange::to_pattern(&class));
+        assert_eq!(Some(class), ClassRange::from_pattern("[\\u{20}-\\-a-z]"));
+
+        let not_ab = ClassRange::complement(&[ClassRange::new('a', 'b')]);
+        assert_eq!("[^ab]", ClassRange::to_pattern(&not_ab));
+        assert_eq!(Some(not_ab), ClassRange::from_pattern("[^ab]"));
+
+        assert_eq!(None, ClassRange::from_pattern("[z-a]"));
+        assert_eq!(None, ClassRange::from_pattern("ab"));
+    }
+
+    #[test]
+    fn test_redundant_wildcards() {
+        let spans = |raw| Parser::parse(raw).unwrap().redundant_wildcards();
+        assert_eq!(vec![(0, 2), (4, 7)], spans(".*ab.*?"));
+        assert_eq!(vec![(0, 3), (5, 8)], spans("^.*ab.*$"));
+        assert_eq!(vec![(0, 6)], spans("(?:.)*a"));
+        assert_eq!(vec![(0, 2)], spans(".*"));
+        assert_eq!(Vec::<Span>::new(), spans(".+a(.*)"));
+        assert_eq!(Vec::<Span>::new(), spans("a.*b|c"));
+
+        let strip = |raw| Parser::parse(raw).unwrap().strip_redundant_wildcards();
+        assert_eq!(Parser::parse("a.*b").unwrap(), strip(".*a.*b.*"));
+        assert_eq!(Parser::parse("ab").unwrap(), strip("^.*ab.*$"));
+        assert_eq!(Parser::parse("").unwrap(), strip(".*"));
+        assert_eq!(Parser::parse("^ab").unwrap(), strip("^ab"));
+    }
+
+    #[test]
+    fn test_features() {
+        let features = Parser::parse("abc").unwrap().features();
+        assert!(features.is_literal);
+        assert_eq!(Some(3), features.max_len);
+        assert!(!features.has_captures);
+        assert!(Parser::parse("a(b)").unwrap().features().has_captures);
+        assert!(!Parser::parse("a(?:b)").unwrap().features().has_captures);
+        assert!(!features.is_anchored);
+        assert!(Parser::parse("^a|(^b)+").unwrap().features().is_anchored);
+        assert!(!Parser::parse("^a|b").unwrap().features().is_anchored);
+        assert!(!Parser::parse("^?a").unwrap().features().is_anchored);
+
+        assert!(Parser::parse("").unwrap().features().is_literal);
+        assert!(!Parser::parse("a.c").unwrap().features().is_literal);
+        assert!(!Parser::parse("ab?").unwrap().features().is_literal);
+        assert!(!Parser::parse("a|b").unwrap().features().is_literal);
+
+        assert_eq!(Some(0), Parser::parse("").unwrap().features().max_len);
+        assert_eq!(
+            Some(3),
+            Parser::parse("a|b[cd]e").unwrap().features().max_len
+        );
+        assert_eq!(
+            Some(4),
+            Parser::parse("ab?c{1,2}").unwrap().features().max_len
+        );
+        assert_eq!(
+            Some(6),
+            Parser::parse("(ab|c){3}").unwrap().features().max_len
+        );
+        assert_eq!(None, Parser::parse("ab+").unwrap().features().max_len);
+        assert_eq!(None, Parser::parse("a(b|c*)").unwrap().features().max_len);
+    }
+
+    #[test]
+    fn test_required_literal() {
+        assert_eq!(
+            Some("abc".into()),
+            Parser::parse("abc").unwrap().required_literal()
+        );
+        assert_eq!(
+            Some("bc".into()),
+            Parser::parse("a?bcd+e").unwrap().required_literal()
+        );
+        assert_eq!(
+            Some("cd".into()),
+            Parser::parse("a.b(cd){2}").unwrap().required_literal()
+        );
+        assert_eq!(None, Parser::parse("(cd)?").unwrap().required_literal());
+        assert_eq!(None, Parser::parse("ab|ac").unwrap().required_literal());
+        assert_eq!(None, Parser::parse("").unwrap().required_literal());
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!("nothing", Parser::parse("").unwrap().explain());
+        assert_eq!(
+            "'a', then one or more of one of 'b', 'c', then optionally any character",
+            Parser::parse("a[bc]+.?").unwrap().explain()
+        );
+        assert_eq!(
+            "either 'ab' or (any character except 'c', then 'd')",
+            Parser::parse("ab|[^c]d").unwrap().explain()
+        );
+        assert_eq!(
+            "exactly 2 of ('x', then zero or more of (either 'y' or 'z'))",
+            Parser::parse("(x(y|z)*){2}").unwrap().explain()
+        );
+        assert_eq!(
+            "between 1 and 3 of 'ab'",
+            Parser::parse("(ab){1,3}").unwrap().explain()
+        );
+        assert_eq!(
+            "the start of the input, then 'a', then the end of the input",
+            Parser::parse("^a$").unwrap().explain()
+        );
+    }
+
     fn transition_this(raw_pattern: &str) -> TransitionAndEndState {
-        let p = Parser::parse(raw_pattern);
+        let p = Parser::parse(raw_pattern).unwrap();
         p.to_transition(0, 1)
     }
 
@@ -398,11 +2721,16 @@ mod test {
             self
         }
 
-        fn with_negated(
+        fn with_classes(
             mut self,
-            negated: HashMap<State, HashMap<Vec<char>, Vec<State>>>,
+            classes: HashMap<State, HashMap<CharClass, Vec<State>>>,
         ) -> TransitionBuilder {
-            self.t.negated = negated;
+            self.t.classes = classes;
+            self
+        }
+
+        fn with_wildcard(mut self, wildcard: HashMap<State, Vec<State>>) -> TransitionBuilder {
+            self.t.wildcard = wildcard;
             self
         }
 
@@ -0,0 +1,4 @@
+// Stable API shared by the other modules, eg: the errors and the inputs to match.
+
+pub use crate::input::{CharIter, Input};
+pub use crate::types::{ErrorCode, RegexError, Span};
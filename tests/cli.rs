@@ -0,0 +1,338 @@
+// Runs the binary end to end, like the scripts using it do.
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    run_command(Command::new(env!("CARGO_BIN_EXE_regexp")).args(args), stdin)
+}
+
+// Runs with the config file at `config` instead of the one in the home dir.
+fn run_with_config(config: &str, args: &[&str], stdin: &str) -> Output {
+    run_command(
+        Command::new(env!("CARGO_BIN_EXE_regexp"))
+            .env("ITARATO_REGEX_CONFIG", config)
+            .args(args),
+        stdin,
+    )
+}
+
+fn run_command(command: &mut Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+// A file in the temp dir, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, content: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("regexp-cli-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        TempFile(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_help() {
+    let output = run(&["--help"], "");
+    assert_eq!(Some(0), output.status.code());
+    assert!(stdout(&output).starts_with("Usage:\n"));
+
+    let output = run(&["--man"], "");
+    assert!(stdout(&output).starts_with(".TH REGEXP 1\n"));
+}
+
+#[test]
+fn test_match() {
+    let output = run(&["a+b", "xaab"], "");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("is_match: true\nis_full_match: false\n", stdout(&output));
+
+    let output = run(&["a+b", "aab"], "");
+    assert_eq!("is_match: true\nis_full_match: true\n", stdout(&output));
+}
+
+#[test]
+fn test_invalid_pattern() {
+    let output = run(&["a("], "");
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "error[E0007]: Missing end of group at (1, 2)\nhelp: add ')' to close the '(' opened at offset 1\n",
+        stderr(&output)
+    );
+
+    assert_eq!(
+        Some(2),
+        run(&["grep", "--no-config", "a("], "").status.code()
+    );
+}
+
+#[test]
+fn test_dot() {
+    let output = run(&["ab"], "");
+    assert_eq!(Some(0), output.status.code());
+    let dot = stdout(&output);
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.contains("[label=\"a\",color=\"black\"]"));
+    assert!(dot.trim_end().ends_with('}'));
+}
+
+#[test]
+fn test_table_and_explain() {
+    let output = run(&["table", "ab"], "");
+    assert_eq!("start 0\naccept 2\n0 a 1\n1 b 2\n", stdout(&output));
+
+    let output = run(&["explain", "a|b"], "");
+    assert_eq!("match either 'a' or 'b'\n", stdout(&output));
+}
+
+#[test]
+fn test_lint() {
+    let output = run(&["lint", ".*ab"], "");
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(
+        "warning[E0018]: Redundant wildcard in a search at (0, 2)\nhelp: remove '.*', searches find matches anywhere already\n",
+        stdout(&output)
+    );
+
+    let output = run(&["lint", "ab"], "");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("", stdout(&output));
+}
+
+#[test]
+fn test_grep() {
+    let file = TempFile::new("grep.txt", "foo\nbar\nfoobar\n");
+
+    let output = run(&["grep", "--no-config", "fo+", file.path()], "");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("foo\nfoobar\n", stdout(&output));
+
+    let output = run(&["grep", "--no-config", "-o", "o+", file.path()], "");
+    assert_eq!("oo\noo\n", stdout(&output));
+
+    let output = run(
+        &["grep", "--no-config", "--with-source", "bar", file.path()],
+        "",
+    );
+    let expected = format!("{0}:2:4:bar\n{0}:3:11:bar\n", file.path());
+    assert_eq!(expected, stdout(&output));
+
+    let output = run(
+        &[
+            "grep",
+            "--no-config",
+            "--format",
+            "$2\\t$1",
+            "(f)(o+)",
+            file.path(),
+        ],
+        "",
+    );
+    assert_eq!("oo\tf\noo\tf\n", stdout(&output));
+
+    let output = run(
+        &[
+            "grep",
+            "--no-config",
+            "--group",
+            "2",
+            "(f)(o+)",
+            file.path(),
+        ],
+        "",
+    );
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("oo\noo\n", stdout(&output));
+
+    // Nothing matched.
+    let output = run(&["grep", "--no-config", "baz", file.path()], "");
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("", stdout(&output));
+}
+
+#[test]
+fn test_grep_usage_errors() {
+    for args in [
+        &["grep", "--no-config", "--group"][..],
+        &["grep", "--no-config", "--group", "x", "a"],
+        &["grep", "--no-config", "--group", "2", "(a)"],
+        &["grep", "--no-config", "--format"],
+        &["grep", "--no-config", "--record-separator"],
+        &["grep", "--no-config", "--multiline", "-o", "a"],
+        &[
+            "grep",
+            "--no-config",
+            "--group",
+            "1",
+            "--format",
+            "$1",
+            "(a)",
+        ],
+        &["grep", "--no-config", "--with-source", "-o", "a"],
+        &[
+            "grep",
+            "--no-config",
+            "--with-source",
+            "--group",
+            "1",
+            "(a)",
+        ],
+        &[
+            "grep",
+            "--no-config",
+            "--paragraph",
+            "--record-separator",
+            "x",
+            "a",
+        ],
+        &["grep", "--no-config", "--follow", "--with-source", "a", "f"],
+    ] {
+        // No input, the binary exits before reading it.
+        let output = run(args, "");
+        assert_eq!(Some(2), output.status.code(), "{:?}", args);
+        assert!(stderr(&output).starts_with("error: "), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_grep_config() {
+    let file = TempFile::new("config-grep.txt", "foo\nbar\n");
+    let config = TempFile::new("config.toml", "only_matching = true\n");
+    let output = run_with_config(config.path(), &["grep", "o+", file.path()], "");
+    assert_eq!("oo\n", stdout(&output));
+
+    // The flags of the command line override the file.
+    let config = TempFile::new("config-multiline.toml", "multiline = true\n");
+    let output = run_with_config(config.path(), &["grep", "-o", "o+", file.path()], "");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("oo\n", stdout(&output));
+
+    // `--follow` runs until it's killed instead of exiting with a usage error.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_regexp"))
+        .env("ITARATO_REGEX_CONFIG", config.path())
+        .args(["grep", "--follow", "fo+", file.path()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    assert!(child.try_wait().unwrap().is_none());
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!("foo\n", stdout(&output));
+    assert_eq!("", stderr(&output));
+
+    let config = TempFile::new(
+        "config-conflict.toml",
+        "multiline = true\nparagraph = true\n",
+    );
+    let output = run_with_config(config.path(), &["grep", "o+", file.path()], "");
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        format!(
+            "{}: multiline and paragraph can't both be set\n",
+            config.path()
+        ),
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_grep_stdin() {
+    let output = run(&["grep", "--no-config", "b"], "abc\nxyz\n");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("abc\n", stdout(&output));
+}
+
+#[test]
+fn test_grep_missing_file() {
+    let path = std::env::temp_dir().join(format!("regexp-cli-{}-missing", std::process::id()));
+    let output = run(&["grep", "--no-config", "a", path.to_str().unwrap()], "");
+    assert_eq!(Some(2), output.status.code());
+    assert!(!stderr(&output).is_empty());
+}
+
+#[test]
+fn test_replace() {
+    let output = run(&["replace", "(\\d+)", "<$1>"], "a1b22\n");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("a<1>b<22>\n", stdout(&output));
+
+    let file = TempFile::new("replace.txt", "ab\ncd\n");
+    let output = run(
+        &["replace", "(?<first>\\w)(\\w)", "$2${first}", file.path()],
+        "",
+    );
+    assert_eq!("ba\ndc\n", stdout(&output));
+}
+
+#[test]
+fn test_extract() {
+    let output = run(
+        &["extract", "(?P<user>\\w+)\\:(?P<code>\\d+)"],
+        "ann:200\nnone\nbob:404\n",
+    );
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("user,code\nann,200\nbob,404\n", stdout(&output));
+
+    let output = run(&["extract", "x(\\d)"], "abc\n");
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!("1\n", stdout(&output));
+
+    let file = TempFile::new("extract.txt", "ann:200 bob:404\nnone\n");
+    let output = run(
+        &[
+            "extract",
+            "--format",
+            "${code}\\t$1",
+            "(?P<user>\\w+)\\:(?P<code>\\d+)",
+            file.path(),
+        ],
+        "",
+    );
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("200\tann\n", stdout(&output));
+
+    let output = run(&["extract", "--format"], "");
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr(&output).starts_with("error: "));
+}
+
+#[test]
+fn test_classify() {
+    let rules = TempFile::new("rules.txt", "\\d+ => number\n[a-z]+ => word\n");
+    let output = run(&["classify", rules.path()], "42\nabc\n!\n");
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("number\t42\nword\tabc\n-\t!\n", stdout(&output));
+}
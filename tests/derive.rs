@@ -0,0 +1,53 @@
+// Fills structs from the named groups, like the users of the `derive` feature do.
+use regexp::meta::{CaptureParseError, Engine, FromCaptures};
+
+#[derive(Debug, PartialEq, regexp_derive::FromCaptures)]
+struct LogLine {
+    ip: String,
+    status: u16,
+    agent: Option<String>,
+    r#type: char,
+}
+
+fn parse(line: &str) -> Result<LogLine, CaptureParseError<String>> {
+    let engine = Engine::new(
+        r"(?P<ip>\d+\.\d+\.\d+\.\d+)-(?P<type>[A-Z])-(?P<status>\w+)(?:-(?P<agent>\w+))?",
+    )
+    .unwrap();
+    LogLine::from_captures(&engine.captures(line).unwrap())
+}
+
+#[test]
+fn test_derive() {
+    assert_eq!(
+        Ok(LogLine {
+            ip: "10.0.0.1".to_string(),
+            status: 404,
+            agent: Some("curl".to_string()),
+            r#type: 'G',
+        }),
+        parse("10.0.0.1-G-404-curl")
+    );
+    assert_eq!(None, parse("10.0.0.1-P-200").unwrap().agent);
+    assert_eq!(
+        Err(CaptureParseError::Invalid(
+            "status: invalid digit found in string".to_string()
+        )),
+        parse("10.0.0.1-G-OK")
+    );
+}
+
+#[test]
+fn test_derive_missing_group() {
+    #[derive(Debug, regexp_derive::FromCaptures)]
+    struct Pair {
+        #[allow(dead_code)]
+        key: String,
+    }
+
+    let caps = Engine::new("(?P<name>a+)").unwrap().captures("aa").unwrap();
+    assert_eq!(
+        CaptureParseError::Missing("key".to_string()),
+        Pair::from_captures(&caps).unwrap_err()
+    );
+}